@@ -1,49 +1,41 @@
-// This example records audio for 5 seconds and writes to a raw PCM file.
+// This example records audio for 10 seconds and writes to a WAV file.
 
 // Setup async main
 include!(concat!(env!("OUT_DIR"), "/main.rs"));
 
-use fon::{mono::Mono32, Audio, Frame};
+use std::{io::Result, time::Duration};
+
 use pasts::{prelude::*, Join};
-use wavy::{Microphone, MicrophoneStream};
+use wavy::{Microphone, RecordFormat, Recorder, WavFormat};
 
 /// Shared state between tasks on the thread.
 struct App {
-    /// Handle to the mono microphone
-    microphone: Microphone<1>,
-    /// Temporary buffer for holding real-time audio samples.
-    buffer: Audio<Mono32>,
+    /// Records the mono microphone to disk.
+    recorder: Recorder<1>,
 }
 
 impl App {
     /// Event loop.  Return false to stop program.
-    fn record(&mut self, stream: MicrophoneStream<Mono32>) -> Poll<()> {
-        self.buffer.extend(stream);
-        if self.buffer.len() >= 48_000 * 10 {
+    fn record(&mut self, written: Result<()>) -> Poll<()> {
+        written.expect("Failed to write file");
+        if self.recorder.duration() >= Duration::from_secs(10) {
             return Ready(());
         }
         Pending
     }
 
     async fn main(_executor: Executor) {
-        let buffer = Audio::with_silence(48_000, 0);
-        let microphone = Microphone::default();
-        let mut app = App { buffer, microphone };
+        let recorder = Recorder::new(
+            Microphone::default(),
+            "recording.wav",
+            RecordFormat::Wav(WavFormat::Pcm16),
+        );
+        let mut app = App { recorder };
 
         Join::new(&mut app)
-            .on(|s| &mut s.microphone, App::record)
+            .on(|s| &mut s.recorder, App::record)
             .await;
 
-        write_pcm(&app.buffer);
-    }
-}
-
-/// Save a Raw PCM File from an audio buffer.
-fn write_pcm(buffer: &Audio<Mono32>) {
-    let mut pcm: Vec<u8> = Vec::new();
-    for frame in buffer.iter() {
-        let sample: f32 = frame.channels()[0].into();
-        pcm.extend(sample.to_le_bytes().iter());
+        app.recorder.stop().expect("Failed to finish file");
     }
-    std::fs::write("pcm.raw", pcm.as_slice()).expect("Failed to write file");
 }
@@ -0,0 +1,140 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    fmt::{Debug, Formatter, Result},
+    marker::PhantomData,
+    sync::{Arc, Mutex, Weak},
+};
+
+use fon::{chan::Ch32, Frame};
+
+/// Buffers of blocks that have been dropped, ready to be reused.
+#[derive(Default)]
+pub(crate) struct BlockPool(Arc<Mutex<Vec<Vec<Ch32>>>>);
+
+impl BlockPool {
+    /// Take an empty buffer, recycled if there is one.
+    pub(crate) fn buffer(&self) -> Vec<Ch32> {
+        self.0.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    /// Create a block from `samples`, in a recycled buffer if there is one.
+    pub(crate) fn block<F: Frame<Chan = Ch32>>(
+        &self,
+        samples: &[Ch32],
+        sample_rate: f64,
+        position: u64,
+    ) -> AudioBlock<F> {
+        let mut buffer = self.buffer();
+        buffer.extend_from_slice(samples);
+        self.lend(buffer, sample_rate, position)
+    }
+
+    /// Create a block that owns `buffer`, returning it to the pool when
+    /// dropped.
+    pub(crate) fn lend<F: Frame<Chan = Ch32>>(
+        &self,
+        buffer: Vec<Ch32>,
+        sample_rate: f64,
+        position: u64,
+    ) -> AudioBlock<F> {
+        AudioBlock {
+            samples: buffer,
+            sample_rate,
+            position,
+            pool: Arc::downgrade(&self.0),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// An owned block of audio recorded by a
+/// [`Microphone`](crate::Microphone).
+///
+/// Blocks can be sent to other threads or tasks, and return their buffer to
+/// the microphone when dropped, so recording doesn't allocate once enough
+/// blocks are in flight.
+pub struct AudioBlock<F: Frame<Chan = Ch32>> {
+    /// Interleaved channels.
+    samples: Vec<Ch32>,
+    /// Sample rate of the audio.
+    sample_rate: f64,
+    /// Number of frames recorded before this block.
+    position: u64,
+    /// Where the buffer is returned when dropped.
+    pool: Weak<Mutex<Vec<Vec<Ch32>>>>,
+    /// Frame type of the block.
+    _phantom: PhantomData<F>,
+}
+
+impl<F: Frame<Chan = Ch32>> Debug for AudioBlock<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "AudioBlock(len: {}, rate: {}, position: {})",
+            self.len(),
+            self.sample_rate,
+            self.position,
+        )
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> AudioBlock<F> {
+    /// Get the interleaved samples of the block.
+    pub fn samples(&self) -> &[Ch32] {
+        &self.samples
+    }
+
+    /// Get the interleaved samples of the block, to modify them in place.
+    pub fn samples_mut(&mut self) -> &mut [Ch32] {
+        &mut self.samples
+    }
+
+    /// Iterate over the frames of the block.
+    pub fn frames(&self) -> impl Iterator<Item = F> + '_ {
+        self.samples.chunks(F::CHAN_COUNT).map(F::from_channels)
+    }
+
+    /// Get the number of frames in the block.
+    pub fn len(&self) -> usize {
+        self.samples.len() / F::CHAN_COUNT
+    }
+
+    /// Check if the block has no frames.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Get the number of channels in each frame.
+    pub fn channels(&self) -> usize {
+        F::CHAN_COUNT
+    }
+
+    /// Get the sample rate of the block.
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Get the capture position of the block: the number of frames the
+    /// microphone recorded before the first frame of this block.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Drop for AudioBlock<F> {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.upgrade() {
+            let mut buffer = std::mem::take(&mut self.samples);
+            buffer.clear();
+            pool.lock().unwrap().push(buffer);
+        }
+    }
+}
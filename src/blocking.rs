@@ -0,0 +1,68 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Polling devices without an async executor.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+/// Wakes the thread blocked in [`wait()`].
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Waker that does nothing, for polling once.
+struct Noop;
+
+impl Wake for Noop {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Poll until ready, parking the thread until woken in between.
+pub(crate) fn wait<T>(poll: impl FnMut(&mut Context<'_>) -> Poll<T>) -> T {
+    wait_unless(&AtomicBool::new(false), poll).unwrap()
+}
+
+/// Poll until ready, parking the thread until woken in between, or return
+/// `None` once `quit` is set (the thread must be unparked after setting it).
+pub(crate) fn wait_unless<T>(
+    quit: &AtomicBool,
+    mut poll: impl FnMut(&mut Context<'_>) -> Poll<T>,
+) -> Option<T> {
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if quit.load(Ordering::Acquire) {
+            return None;
+        }
+        if let Poll::Ready(output) = poll(&mut cx) {
+            return Some(output);
+        }
+        thread::park();
+    }
+}
+
+/// Poll once, without waiting to be woken.
+pub(crate) fn poll_now<T>(
+    poll: impl FnOnce(&mut Context<'_>) -> Poll<T>,
+) -> Poll<T> {
+    static NOOP: OnceLock<Waker> = OnceLock::new();
+    let waker = NOOP.get_or_init(|| Waker::from(Arc::new(Noop)));
+    poll(&mut Context::from_waker(waker))
+}
@@ -0,0 +1,198 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use fon::{
+    chan::{Ch32, Channel},
+    Frame,
+};
+
+/// Most channels of a frame.
+const CHANNELS: usize = 6;
+
+/// Position of a speaker (or microphone capsule) a device channel is for, as
+/// reported by the device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelPosition {
+    /// Position isn't known.
+    Unknown,
+    /// Channel isn't connected.
+    NotAvailable,
+    /// Mono (the only channel).
+    Mono,
+    /// Front left.
+    FrontLeft,
+    /// Front right.
+    FrontRight,
+    /// Rear left.
+    RearLeft,
+    /// Rear right.
+    RearRight,
+    /// Front center.
+    FrontCenter,
+    /// Low frequency effects (subwoofer).
+    Lfe,
+    /// Side left.
+    SideLeft,
+    /// Side right.
+    SideRight,
+    /// Rear center.
+    RearCenter,
+    /// Another position, by the platform's number for it.
+    Other(u32),
+}
+
+/// Matrix mixing the channels of frames into (possibly fewer or more)
+/// channels, each output channel being a weighted sum of the input channels.
+///
+/// Channels are numbered in their order in frames.  For 5.1 frames, fon
+/// orders them rear left, front left, center, front right, rear right and
+/// LFE; sound cards may use another order (see
+/// [`Speakers::channel_positions()`](crate::Speakers::channel_positions)),
+/// which a map can reorder to.
+///
+/// ```
+/// use fon::{stereo::Stereo32, surround::Surround32};
+/// use wavy::ChannelMap;
+///
+/// // Upmix stereo to 5.1, with both channels in the center and the rear
+/// // speakers quieter.
+/// let upmix = ChannelMap::new(2, 6)
+///     .route(0, 1)
+///     .route(1, 3)
+///     .gain(0, 2, 0.5)
+///     .gain(1, 2, 0.5)
+///     .gain(0, 0, 0.5)
+///     .gain(1, 4, 0.5);
+/// let frame: Surround32 = upmix.mix(Stereo32::new(0.5, -0.5));
+/// assert_eq!(frame, Surround32::new(0.25, 0.5, 0.0, -0.5, -0.25, 0.0));
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ChannelMap {
+    /// Number of input channels.
+    inputs: usize,
+    /// Number of output channels.
+    outputs: usize,
+    /// Gain of each input (columns) in each output (rows).
+    gains: [[f32; CHANNELS]; CHANNELS],
+}
+
+impl ChannelMap {
+    /// Create a silent map from `inputs` to `outputs` channels (each from 1
+    /// to 6).
+    ///
+    /// # Panics
+    /// If either number of channels is out of range.
+    pub fn new(inputs: usize, outputs: usize) -> Self {
+        assert!(
+            (1..=CHANNELS).contains(&inputs)
+                && (1..=CHANNELS).contains(&outputs),
+            "Channel maps are from 1 to 6 channels"
+        );
+        Self {
+            inputs,
+            outputs,
+            gains: [[0.0; CHANNELS]; CHANNELS],
+        }
+    }
+
+    /// Create a map passing each of `channels` channels through unchanged.
+    pub fn identity(channels: usize) -> Self {
+        (0..channels).fold(Self::new(channels, channels), |map, channel| {
+            map.route(channel, channel)
+        })
+    }
+
+    /// Create an ITU-R BS.775 downmix of 5.1 frames to stereo: the center
+    /// and rear channels are added to the front channels at -3 dB, and the
+    /// LFE channel is left out (add it with [`ChannelMap::gain()`] if the
+    /// speakers can reproduce it).
+    ///
+    /// The sum isn't normalized, so loud surround audio may clip.
+    ///
+    /// ```
+    /// use fon::{stereo::Stereo32, surround::Surround32};
+    /// use wavy::ChannelMap;
+    ///
+    /// let downmix = ChannelMap::surround_to_stereo()
+    ///     .gain(5, 0, 0.5)
+    ///     .gain(5, 1, 0.5);
+    /// let frame = Surround32::new(0.0, 0.25, 0.0, 0.0, 0.0, 0.5);
+    /// assert_eq!(downmix.mix::<_, Stereo32>(frame), Stereo32::new(0.5, 0.25));
+    /// ```
+    pub fn surround_to_stereo() -> Self {
+        Self::new(6, 2)
+            .route(1, 0)
+            .route(3, 1)
+            .gain(2, 0, FRAC_1_SQRT_2)
+            .gain(2, 1, FRAC_1_SQRT_2)
+            .gain(0, 0, FRAC_1_SQRT_2)
+            .gain(4, 1, FRAC_1_SQRT_2)
+    }
+
+    /// Send input channel `input` to output channel `output` at full level.
+    pub fn route(self, input: usize, output: usize) -> Self {
+        self.gain(input, output, 1.0)
+    }
+
+    /// Set the gain of input channel `input` in output channel `output`.
+    ///
+    /// # Panics
+    /// If either channel is out of range.
+    pub fn gain(mut self, input: usize, output: usize, gain: f32) -> Self {
+        assert!(input < self.inputs, "No input channel {}", input);
+        assert!(output < self.outputs, "No output channel {}", output);
+        self.gains[output][input] = gain;
+        self
+    }
+
+    /// Get the number of input channels.
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    /// Get the number of output channels.
+    pub fn outputs(&self) -> usize {
+        self.outputs
+    }
+
+    /// Get the gain of input channel `input` in output channel `output`.
+    pub fn get(&self, input: usize, output: usize) -> f32 {
+        if input < self.inputs && output < self.outputs {
+            self.gains[output][input]
+        } else {
+            0.0
+        }
+    }
+
+    /// Mix a frame.
+    ///
+    /// # Panics
+    /// If the frames' numbers of channels don't match the map's.
+    pub fn mix<S, F>(&self, frame: S) -> F
+    where
+        S: Frame,
+        F: Frame<Chan = Ch32>,
+    {
+        assert_eq!(S::CHAN_COUNT, self.inputs, "Wrong number of inputs");
+        assert_eq!(F::CHAN_COUNT, self.outputs, "Wrong number of outputs");
+        let frame = frame.channels();
+        let mut out = F::default();
+        for (dst, gains) in out.channels_mut().iter_mut().zip(&self.gains) {
+            let sum: f32 = frame
+                .iter()
+                .zip(gains)
+                .map(|(src, gain)| src.to_f64() as f32 * gain)
+                .sum();
+            *dst = Ch32::new(sum);
+        }
+        out
+    }
+}
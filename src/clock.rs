@@ -0,0 +1,21 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+/// How periods are paced on devices that aren't backed by a sound card.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Clock {
+    /// Periods become ready in real time, as they would on hardware (not on
+    /// WebAssembly, which has no threads to wake devices from).
+    #[cfg(not(target_arch = "wasm32"))]
+    RealTime,
+    /// Periods become ready as soon as the device is polled, so audio is
+    /// processed as fast as possible in a deterministic order (useful for
+    /// tests).
+    Virtual,
+}
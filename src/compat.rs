@@ -0,0 +1,119 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Implementations of the `futures` traits, for use with other executors.
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{
+    microphone::MicrophoneProperties, speakers::SpeakersProperties, AudioBlock,
+    Microphone, Speakers,
+};
+
+/// Microphones are a stream of pooled [`AudioBlock`]s, each containing the
+/// frames recorded since the previous block.
+///
+/// ```
+/// use std::{future::poll_fn, pin::Pin};
+///
+/// use fon::mono::Mono32;
+/// use futures_core::Stream;
+/// use wavy::{Clock, Microphone};
+///
+/// async fn run() {
+///     let signal = std::iter::repeat(Mono32::new(0.25));
+///     let mut microphone =
+///         Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+///     let block = poll_fn(|cx| Pin::new(&mut microphone).poll_next(cx))
+///         .await
+///         .unwrap();
+///     assert_eq!(block.len(), 64);
+///     assert!(block.frames().all(|f| f == Mono32::new(0.25)));
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+impl<const N: usize> futures_core::Stream for Microphone<N>
+where
+    Microphone<N>: MicrophoneProperties,
+{
+    type Item = AudioBlock<<Self as MicrophoneProperties>::Sample>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_block(cx).map(Some)
+    }
+}
+
+/// Speakers are a sink of slices of frames, played at
+/// [`Speakers::sample_rate()`] without resampling.  Flushing pads the last
+/// period with silence.
+///
+/// ```
+/// use std::{future::poll_fn, pin::Pin};
+///
+/// use fon::mono::Mono32;
+/// use futures_sink::Sink;
+/// use wavy::{Clock, Speakers};
+///
+/// async fn run() {
+///     let mut speakers = Speakers::<1>::null(Clock::Virtual, 48_000);
+///     let frames = [Mono32::new(0.5); 100];
+///     poll_fn(|cx| Pin::new(&mut speakers).poll_ready(cx)).await.unwrap();
+///     Pin::new(&mut speakers).start_send(&frames).unwrap();
+///     poll_fn(|cx| Pin::new(&mut speakers).poll_flush(cx)).await.unwrap();
+///     let output = speakers.take_output().unwrap();
+///     assert_eq!(output.len(), 128);
+///     assert!(output.iter().take(100).all(|f| *f == Mono32::new(0.5)));
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+impl<const N: usize>
+    futures_sink::Sink<&[<Speakers<N> as SpeakersProperties>::Sample]>
+    for Speakers<N>
+where
+    Speakers<N>: SpeakersProperties,
+{
+    type Error = std::convert::Infallible;
+
+    fn poll_ready(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Speakers::poll_ready(self.get_mut(), cx).map(Ok)
+    }
+
+    fn start_send(
+        self: Pin<&mut Self>,
+        frames: &[<Speakers<N> as SpeakersProperties>::Sample],
+    ) -> Result<(), Self::Error> {
+        self.get_mut().push(frames);
+        Ok(())
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Speakers::poll_flush(self.get_mut(), cx).map(Ok)
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Speakers::poll_flush(self.get_mut(), cx).map(Ok)
+    }
+}
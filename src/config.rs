@@ -0,0 +1,70 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+/// Format of the samples exchanged with a device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// 32-bit float.
+    F32,
+    /// Signed 32-bit integer, converted from and to float by wavy (exact in
+    /// the 24 most significant bits).
+    I32,
+    /// Signed 16-bit integer, converted from and to float by wavy (exact).
+    I16,
+}
+
+/// Configuration negotiated with a device when it's configured by its first
+/// period, and what the platform guarantees about it.
+///
+/// This describes the device's stream, before any conversion by wavy (such as
+/// [`Microphone::set_sample_rate()`](crate::Microphone::set_sample_rate)).
+///
+/// ```
+/// use pasts::prelude::*;
+/// use wavy::{Clock, SampleFormat, Speakers};
+///
+/// async fn run() {
+///     let mut speakers = Speakers::<2>::null(Clock::Virtual, 48_000);
+///     assert_eq!(speakers.stream_config(), None);
+///     drop(speakers.next().await);
+///
+///     let config = speakers.stream_config().unwrap();
+///     assert_eq!(config.sample_rate, 48_000.0);
+///     assert_eq!(config.channels, 2);
+///     assert_eq!(config.format, SampleFormat::F32);
+///     // Nothing converts between null speakers and the memory they play to.
+///     assert!(config.native_rate && config.native_channels);
+///     assert!(config.native_format);
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StreamConfig {
+    /// Sample rate, in hertz.
+    pub sample_rate: f64,
+    /// Number of interleaved channels.
+    pub channels: usize,
+    /// Number of frames in a period.
+    pub period: usize,
+    /// Estimated latency, in frames: from handing a period to speakers until
+    /// it's heard, or from recording a period until a microphone reads it.
+    pub latency: usize,
+    /// Format of the samples.
+    pub format: SampleFormat,
+    /// The sample rate is guaranteed to be the device's own, without
+    /// resampling by the platform (`SND_PCM_NO_AUTO_RESAMPLE` on Linux).
+    pub native_rate: bool,
+    /// The channels are guaranteed to be the device's own, without
+    /// conversion by the platform (`SND_PCM_NO_AUTO_CHANNELS` on Linux).
+    pub native_channels: bool,
+    /// The sample format is guaranteed to be the device's own, without
+    /// conversion by the platform (`SND_PCM_NO_AUTO_FORMAT` on Linux).
+    pub native_format: bool,
+}
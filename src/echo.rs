@@ -0,0 +1,438 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Formatter, Result},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use fon::{chan::Ch32, Frame};
+
+/// Most audio a reference keeps before a microphone reads it, in seconds.
+const MAX_REFERENCE: f64 = 1.0;
+
+/// Step size of the adaptive filters (from 0 to 2: larger adapts faster, but
+/// leaves more residual echo).
+const STEP: f32 = 0.5;
+
+/// Added to the energy of the reference, so the step stays bounded during
+/// silence.
+const EPSILON: f32 = 1.0e-6;
+
+/// Audio played, waiting to be read by an echo canceller.
+#[derive(Default)]
+struct Played {
+    /// Mono mix of the audio, oldest first.
+    audio: VecDeque<f32>,
+    /// Sample rate of the audio.
+    sample_rate: f64,
+    /// Latency of the speakers, in frames.
+    latency: usize,
+    /// Whether any audio was played yet.
+    started: bool,
+    /// Frames taken as silence because the speakers fell behind, skipped
+    /// when they're played so the audio stays aligned with what's recorded.
+    owed: usize,
+}
+
+/// Audio played through a [`Speakers`](crate::Speakers) (mixed to mono,
+/// after the software volume), from
+/// [`Speakers::echo_reference()`](crate::Speakers::echo_reference).
+///
+/// References can be cloned and sent to other tasks or threads.
+///
+/// A microphone may start recording before the speakers start playing (the
+/// reference is silent until they do), and is aligned with them once they
+/// have.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use fon::{mono::Mono32, Audio, Frame, Sink};
+/// use pasts::prelude::*;
+/// use wavy::{Clock, EchoCanceller, Loopback};
+///
+/// async fn run() {
+///     let mut impulse_response = vec![0.0; 32];
+///     impulse_response[0] = 0.6;
+///     impulse_response[31] = -0.3;
+///     let (mut speakers, mut microphone) = Loopback {
+///         clock: Clock::Virtual,
+///         latency: 192,
+///         impulse_response,
+///         ..Loopback::default()
+///     }
+///     .open::<1>();
+///     let reference = speakers.echo_reference();
+///     let canceller = EchoCanceller::new(reference, Duration::from_millis(1));
+///     microphone.set_echo_canceller(Some(canceller));
+///
+///     // Record 10 periods before the speakers start.
+///     for _ in 0..10 {
+///         microphone.next().await;
+///     }
+///
+///     let mut random = 1u32;
+///     let mut residual = 0.0;
+///     for period in 0..200 {
+///         let noise: Vec<Mono32> = (0..64)
+///             .map(|_| {
+///                 random =
+///                     random.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+///                 Mono32::new((random >> 16) as f32 / 32_768.0 - 1.0)
+///             })
+///             .collect();
+///         let mut noise = Audio::with_frames(48_000, noise);
+///         speakers.next().await.stream(noise.drain());
+///         for frame in microphone.next().await {
+///             if period >= 150 {
+///                 residual += f32::from(frame.channels()[0]).powi(2);
+///             }
+///         }
+///     }
+///     // The echo's energy would be about 0.15 per frame; it's now 40 dB
+///     // lower.
+///     assert!(residual / (50.0 * 64.0) < 0.15e-4);
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+#[derive(Clone, Default)]
+pub struct EchoReference(Arc<Mutex<Played>>);
+
+impl Debug for EchoReference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let played = self.0.lock().unwrap();
+        write!(f, "EchoReference(buffered: {})", played.audio.len())
+    }
+}
+
+impl EchoReference {
+    /// Add a period played at `sample_rate`, through speakers with `latency`
+    /// frames of latency.
+    pub(crate) fn push<F>(&self, frames: &[F], sample_rate: f64, latency: usize)
+    where
+        F: Frame<Chan = Ch32>,
+    {
+        let mut played = self.0.lock().unwrap();
+        played.sample_rate = sample_rate;
+        played.latency = latency;
+        played.started = true;
+        let skip = played.owed.min(frames.len());
+        played.owed -= skip;
+        let scale = 1.0 / F::CHAN_COUNT as f32;
+        played.audio.extend(frames[skip..].iter().map(|frame| {
+            let sum: f32 = frame.channels().iter().map(|&c| f32::from(c)).sum();
+            sum * scale
+        }));
+        // Keep the latest audio if no microphone is reading it.
+        let max = (MAX_REFERENCE * sample_rate) as usize;
+        let excess = played.audio.len().saturating_sub(max);
+        played.audio.drain(..excess);
+    }
+
+    /// Take `frames` frames (silence once there are none left) into `output`,
+    /// returning their sample rate and the latency of the speakers.
+    fn take(&self, frames: usize, output: &mut Vec<f32>) -> (f64, usize) {
+        let mut played = self.0.lock().unwrap();
+        let available = frames.min(played.audio.len());
+        output.extend(played.audio.drain(..available));
+        output.resize(output.len() + frames - available, 0.0);
+        // Silence before the speakers start is what's recorded; once they
+        // have, it's audio they're late with (up to a period, so a microphone
+        // running ahead can't starve the reference for good).
+        if played.started {
+            played.owed = (played.owed + frames - available).min(frames);
+        }
+        (played.sample_rate, played.latency)
+    }
+}
+
+/// Acoustic echo canceller, which removes the audio played through a
+/// [`Speakers`](crate::Speakers) from what a microphone records (set with
+/// [`Microphone::set_echo_canceller()`]).
+///
+/// An adaptive (NLMS) filter per recorded channel models the path from the
+/// speakers to the microphone, from the [`EchoReference`] delayed by the
+/// latency between playing and recording (the speakers' and microphone's
+/// [`StreamConfig::latency`](crate::StreamConfig::latency), unless set), and
+/// its estimate of the echo is subtracted.  Each period recorded is paired
+/// with as many frames of the reference, so the speakers and microphone must
+/// run at the same sample rate (periods recorded at another rate are passed
+/// through unchanged, and the reference is discarded).  Echo cancellation
+/// happens before the microphone converts to a [requested sample
+/// rate](crate::Microphone::set_sample_rate).
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use fon::{mono::Mono32, Audio, Frame, Sink};
+/// use pasts::prelude::*;
+/// use wavy::{Clock, EchoCanceller, Loopback};
+///
+/// async fn run() {
+///     // A room that echoes what's played 4 ms later, with a reflection.
+///     let mut impulse_response = vec![0.0; 32];
+///     impulse_response[0] = 0.6;
+///     impulse_response[31] = -0.3;
+///     let (mut speakers, mut microphone) = Loopback {
+///         clock: Clock::Virtual,
+///         latency: 192,
+///         impulse_response,
+///         ..Loopback::default()
+///     }
+///     .open::<1>();
+///     let reference = speakers.echo_reference();
+///     let canceller = EchoCanceller::new(reference, Duration::from_millis(1));
+///     microphone.set_echo_canceller(Some(canceller));
+///
+///     // Play noise, and measure the energy left in the last periods.
+///     let mut random = 1u32;
+///     let mut residual = 0.0;
+///     for period in 0..200 {
+///         let noise: Vec<Mono32> = (0..64)
+///             .map(|_| {
+///                 random =
+///                     random.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+///                 Mono32::new((random >> 16) as f32 / 32_768.0 - 1.0)
+///             })
+///             .collect();
+///         let mut noise = Audio::with_frames(48_000, noise);
+///         // Once, record two periods before playing the first of them (as
+///         // if the speakers' task was late), and catch up.
+///         let mut recorded = Vec::new();
+///         if period == 100 {
+///             recorded.extend(microphone.next().await);
+///             recorded.extend(microphone.next().await);
+///         }
+///         if period == 101 {
+///             let mut late = Audio::<Mono32>::with_silence(48_000, 64);
+///             speakers.next().await.stream(late.drain());
+///         }
+///         speakers.next().await.stream(noise.drain());
+///         if period != 100 {
+///             recorded.extend(microphone.next().await);
+///         }
+///         for frame in recorded {
+///             if period >= 150 {
+///                 residual += f32::from(frame.channels()[0]).powi(2);
+///             }
+///         }
+///     }
+///     // The echo's energy would be about 0.15 per frame; it's now 40 dB
+///     // lower.
+///     assert!(residual / (50.0 * 64.0) < 0.15e-4);
+///     // The delay is the loopback's latency.
+///     let canceller = microphone.echo_canceller().unwrap();
+///     assert_eq!(canceller.delay(), Duration::from_millis(4));
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+///
+/// [`Microphone::set_echo_canceller()`]: crate::Microphone::set_echo_canceller
+pub struct EchoCanceller {
+    /// Audio played.
+    reference: EchoReference,
+    /// Length of the echo modeled by the filters.
+    tail: Duration,
+    /// Latency from playing audio to recording it, if set.
+    delay: Option<Duration>,
+    /// Latency of the speakers and microphone in the last period, in frames.
+    latency: usize,
+    /// Sample rate the filters are for (0.0 until the first period).
+    sample_rate: f64,
+    /// Last reference samples, oldest first, enough for the delay and tail.
+    history: Vec<f32>,
+    /// Adaptive filter of each channel, oldest tap first.
+    filters: Vec<Vec<f32>>,
+}
+
+impl Debug for EchoCanceller {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "EchoCanceller(tail: {:?}, delay: {:?})",
+            self.tail, self.delay
+        )
+    }
+}
+
+impl EchoCanceller {
+    /// Create an echo canceller removing echoes of `reference` lasting up to
+    /// `tail` (longer tails cancel more of a room's reverberation, but take
+    /// longer to adapt and more processing).
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use fon::{mono::Mono32, Audio, Frame, Sink};
+    /// use pasts::prelude::*;
+    /// use wavy::{Clock, EchoCanceller, Loopback, Microphone};
+    ///
+    /// async fn run() {
+    ///     // Impulse response of a small room (the direct path, early
+    ///     // reflections and a decaying reverberation), 512 frames at 48 kHz.
+    ///     let path = concat!(
+    ///         env!("CARGO_MANIFEST_DIR"),
+    ///         "/tests/fixtures/room-ir.wav"
+    ///     );
+    ///     let mut file = Microphone::<1>::wav(Clock::Virtual, path).unwrap();
+    ///     let mut response = [Mono32::default(); 512];
+    ///     file.read_blocking(&mut response);
+    ///     let impulse_response: Vec<f32> =
+    ///         response.iter().map(|f| f.channels()[0].into()).collect();
+    ///     // Energy per frame of the echo of uniform noise.
+    ///     let echo: f32 =
+    ///         impulse_response.iter().map(|x| x * x).sum::<f32>() / 3.0;
+    ///
+    ///     let (mut speakers, mut microphone) = Loopback {
+    ///         clock: Clock::Virtual,
+    ///         latency: 96,
+    ///         impulse_response,
+    ///         ..Loopback::default()
+    ///     }
+    ///     .open::<1>();
+    ///     // The tail covers the 10.7 ms response.
+    ///     let reference = speakers.echo_reference();
+    ///     let tail = Duration::from_millis(12);
+    ///     let canceller = EchoCanceller::new(reference, tail);
+    ///     microphone.set_echo_canceller(Some(canceller));
+    ///
+    ///     let mut random = 1u32;
+    ///     let mut residual = 0.0;
+    ///     for period in 0..200 {
+    ///         let noise: Vec<Mono32> = (0..64)
+    ///             .map(|_| {
+    ///                 random = random
+    ///                     .wrapping_mul(1_103_515_245)
+    ///                     .wrapping_add(12_345);
+    ///                 Mono32::new((random >> 16) as f32 / 32_768.0 - 1.0)
+    ///             })
+    ///             .collect();
+    ///         let mut noise = Audio::with_frames(48_000, noise);
+    ///         speakers.next().await.stream(noise.drain());
+    ///         for frame in microphone.next().await {
+    ///             if period >= 150 {
+    ///                 residual += f32::from(frame.channels()[0]).powi(2);
+    ///             }
+    ///         }
+    ///     }
+    ///     // The echo is at least 40 dB lower.
+    ///     assert!(residual / (50.0 * 64.0) < echo * 1.0e-4);
+    /// }
+    ///
+    /// pasts::Executor::default().spawn(run());
+    /// ```
+    pub fn new(reference: EchoReference, tail: Duration) -> Self {
+        Self {
+            reference,
+            tail,
+            delay: None,
+            latency: 0,
+            sample_rate: 0.0,
+            history: Vec::new(),
+            filters: Vec::new(),
+        }
+    }
+
+    /// Get the length of the echoes cancelled.
+    pub fn tail(&self) -> Duration {
+        self.tail
+    }
+
+    /// Get the latency the reference is delayed by (as of the last period,
+    /// unless set).
+    pub fn delay(&self) -> Duration {
+        self.delay.unwrap_or_else(|| {
+            if self.sample_rate == 0.0 {
+                return Duration::ZERO;
+            }
+            Duration::from_secs_f64(self.latency as f64 / self.sample_rate)
+        })
+    }
+
+    /// Set the latency between playing audio and recording it (the output
+    /// and input latency of the sound card, minus the time from handing a
+    /// period to the speakers to recording the period it's paired with), which
+    /// the reference is delayed by, or with `None` use the latency the
+    /// speakers and microphone report (the default).  Echoes arriving up to
+    /// the [tail](EchoCanceller::tail) after it are cancelled.
+    pub fn set_delay(&mut self, delay: Option<Duration>) {
+        self.delay = delay;
+    }
+
+    /// Forget the echo path adapted to (such as after moving the
+    /// microphone).
+    pub fn reset(&mut self) {
+        self.filters.clear();
+    }
+
+    /// Remove the echo from a period of interleaved audio with `channels`
+    /// channels, recorded at `sample_rate` by a microphone with `latency`
+    /// frames of latency.
+    pub(crate) fn process(
+        &mut self,
+        audio: &mut [Ch32],
+        channels: usize,
+        sample_rate: f64,
+        latency: usize,
+    ) {
+        let start = self.history.len();
+        let frames = audio.len() / channels;
+        let (reference_rate, output_latency) =
+            self.reference.take(frames, &mut self.history);
+        if reference_rate != sample_rate {
+            self.history.truncate(start);
+            return;
+        }
+        self.latency = output_latency + latency;
+        let frames_of = |duration: Duration| {
+            (duration.as_secs_f64() * sample_rate).round() as usize
+        };
+        let taps = frames_of(self.tail).max(1);
+        let delay = self.delay.map_or(self.latency, frames_of);
+        if sample_rate != self.sample_rate || self.filters.len() != channels {
+            self.sample_rate = sample_rate;
+            self.filters = vec![vec![0.0; taps]; channels];
+        }
+
+        // Keep the samples before the period the delay and tail reach back to.
+        let window = delay + taps;
+        let keep = window - 1;
+        if start < keep {
+            let pad = keep - start;
+            self.history.splice(..0, std::iter::repeat(0.0).take(pad));
+        } else {
+            self.history.drain(..start - keep);
+        }
+
+        for (i, frame) in audio.chunks_mut(channels).enumerate() {
+            let reference = &self.history[i..][..taps];
+            let energy: f32 =
+                reference.iter().map(|x| x * x).sum::<f32>() + EPSILON;
+            for (chan, filter) in frame.iter_mut().zip(&mut self.filters) {
+                let echo: f32 =
+                    filter.iter().zip(reference).map(|(w, x)| w * x).sum();
+                let error = f32::from(*chan) - echo;
+                let step = STEP * error / energy;
+                for (w, x) in filter.iter_mut().zip(reference) {
+                    *w += step * x;
+                }
+                *chan = Ch32::new(error);
+            }
+        }
+
+        // Drop the samples no longer reached.
+        let len = self.history.len();
+        self.history.drain(..len - keep);
+    }
+}
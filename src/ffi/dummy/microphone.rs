@@ -47,6 +47,34 @@ impl Microphone {
     pub(crate) fn channels(&self) -> u8 {
         1
     }
+
+    pub(crate) fn channel_positions(&self) -> Vec<crate::ChannelPosition> {
+        Vec::new()
+    }
+
+    pub(crate) fn stream_config(&self) -> Option<crate::StreamConfig> {
+        None
+    }
+
+    pub(crate) fn volume_controls(&self) -> Vec<super::VolumeControl> {
+        Vec::new()
+    }
+
+    pub(crate) fn sample_rate(&self) -> Option<f64> {
+        Some(crate::consts::SAMPLE_RATE.into())
+    }
+
+    pub(crate) fn set_sample_rate(&mut self, _sample_rate: u32) {
+        // The sample rate can't be chosen, so audio is resampled instead.
+    }
+
+    pub(crate) fn kind(&self) -> crate::SourceKind {
+        crate::SourceKind::Input
+    }
+
+    pub(crate) fn take_error(&mut self) -> Option<std::io::Error> {
+        None
+    }
 }
 
 impl Future for Microphone {
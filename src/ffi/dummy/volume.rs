@@ -0,0 +1,55 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    io::Result,
+    task::{Context, Poll},
+};
+
+/// Hardware volume controls aren't supported on this platform, so there are
+/// never any.
+pub(crate) enum VolumeControl {}
+
+impl VolumeControl {
+    pub(crate) fn name(&self) -> &str {
+        match *self {}
+    }
+
+    pub(crate) fn db_range(&self) -> Option<(f32, f32)> {
+        match *self {}
+    }
+
+    pub(crate) fn volume_db(&self) -> Option<f32> {
+        match *self {}
+    }
+
+    pub(crate) fn set_volume_db(&mut self, _db: f32) -> Result<()> {
+        match *self {}
+    }
+
+    pub(crate) fn volume_percent(&self) -> Option<f32> {
+        match *self {}
+    }
+
+    pub(crate) fn set_volume_percent(&mut self, _percent: f32) -> Result<()> {
+        match *self {}
+    }
+
+    pub(crate) fn is_muted(&self) -> Option<bool> {
+        match *self {}
+    }
+
+    pub(crate) fn set_muted(&mut self, _muted: bool) -> Result<()> {
+        match *self {}
+    }
+
+    pub(crate) fn poll_change(&mut self, _cx: &mut Context<'_>) -> Poll<()> {
+        match *self {}
+    }
+}
@@ -9,7 +9,10 @@
 
 #![allow(unsafe_code)]
 
-use std::os::raw::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
+use std::{
+    os::raw::{c_char, c_int, c_long, c_uint, c_ulong, c_void},
+    sync::OnceLock,
+};
 
 /// Stream Mode
 #[allow(unused)]
@@ -25,6 +28,13 @@ pub(crate) enum SndPcmMode {
     Async = 2,
 }
 
+/// Don't let plugins resample (for `snd_pcm_open()`).
+pub(crate) const SND_PCM_NO_AUTO_RESAMPLE: c_int = 0x0001_0000;
+/// Don't let plugins convert channels (for `snd_pcm_open()`).
+pub(crate) const SND_PCM_NO_AUTO_CHANNELS: c_int = 0x0002_0000;
+/// Don't let plugins convert the sample format (for `snd_pcm_open()`).
+pub(crate) const SND_PCM_NO_AUTO_FORMAT: c_int = 0x0004_0000;
+
 /// PCM stream (direction)
 #[allow(unused)]
 #[repr(C)]
@@ -230,6 +240,8 @@ dl_api::linker!(extern "C" Alsa "libasound.so.2" {
     fn snd_pcm_prepare(pcm: *mut c_void) -> c_int;
     fn snd_pcm_resume(pcm: *mut c_void) -> c_int;
     fn snd_pcm_state(pcm: *mut c_void) -> SndPcmState;
+    fn snd_pcm_delay(pcm: *mut c_void, delayp: *mut c_long) -> c_int;
+    fn snd_pcm_get_chmap(pcm: *mut c_void) -> *mut c_uint;
     fn snd_pcm_readi(
         pcm: *mut c_void,
         buffer: *mut c_void,
@@ -249,6 +261,8 @@ dl_api::linker!(extern "C" Alsa "libasound.so.2" {
     fn snd_pcm_hw_params(pcm: *mut c_void, params: *mut c_void) -> c_int;
     fn snd_pcm_hw_params_free(params: *mut c_void) -> ();
     fn snd_pcm_hw_params_set_rate_near(pcm: *mut c_void, params: *mut c_void, val: *mut c_uint, dir: *mut c_int) -> c_int;
+    fn snd_pcm_hw_params_set_rate(pcm: *mut c_void, params: *mut c_void, val: c_uint, dir: c_int) -> c_int;
+    fn snd_pcm_hw_params_test_rate(pcm: *mut c_void, params: *mut c_void, val: c_uint, dir: c_int) -> c_int;
     fn snd_pcm_hw_params_get_rate_numden(params: *mut c_void, rate_num: *mut c_uint, rate_den: *mut c_uint) -> c_int;
     fn snd_pcm_hw_params_any(pcm: *mut c_void, params: *mut c_void) -> c_int;
     fn snd_pcm_hw_params_test_channels(pcm: *mut c_void, params: *mut c_void, val: c_uint) -> c_int;
@@ -275,6 +289,46 @@ dl_api::linker!(extern "C" Alsa "libasound.so.2" {
         val: *mut c_uint,
         dir: *mut c_int,
     ) -> c_int;
+
+    // Mixer
+    fn snd_mixer_open(mixer: *mut *mut c_void, mode: c_int) -> c_int;
+    fn snd_mixer_close(mixer: *mut c_void) -> c_int;
+    fn snd_mixer_attach(mixer: *mut c_void, name: *const c_char) -> c_int;
+    fn snd_mixer_selem_register(
+        mixer: *mut c_void,
+        options: *mut c_void,
+        classp: *mut *mut c_void,
+    ) -> c_int;
+    fn snd_mixer_load(mixer: *mut c_void) -> c_int;
+    fn snd_mixer_handle_events(mixer: *mut c_void) -> c_int;
+    fn snd_mixer_poll_descriptors(mixer: *mut c_void, pfds: *mut PollFd, space: c_uint) -> c_int;
+    fn snd_mixer_poll_descriptors_count(mixer: *mut c_void) -> c_int;
+    fn snd_mixer_first_elem(mixer: *mut c_void) -> *mut c_void;
+    fn snd_mixer_elem_next(elem: *mut c_void) -> *mut c_void;
+
+    // Simple Mixer Elements
+    fn snd_mixer_selem_get_name(elem: *mut c_void) -> *const c_char;
+    fn snd_mixer_selem_get_index(elem: *mut c_void) -> c_uint;
+    fn snd_mixer_selem_has_playback_volume(elem: *mut c_void) -> c_int;
+    fn snd_mixer_selem_has_capture_volume(elem: *mut c_void) -> c_int;
+    fn snd_mixer_selem_has_playback_switch(elem: *mut c_void) -> c_int;
+    fn snd_mixer_selem_has_capture_switch(elem: *mut c_void) -> c_int;
+    fn snd_mixer_selem_get_playback_volume_range(elem: *mut c_void, min: *mut c_long, max: *mut c_long) -> c_int;
+    fn snd_mixer_selem_get_capture_volume_range(elem: *mut c_void, min: *mut c_long, max: *mut c_long) -> c_int;
+    fn snd_mixer_selem_get_playback_dB_range(elem: *mut c_void, min: *mut c_long, max: *mut c_long) -> c_int;
+    fn snd_mixer_selem_get_capture_dB_range(elem: *mut c_void, min: *mut c_long, max: *mut c_long) -> c_int;
+    fn snd_mixer_selem_get_playback_volume(elem: *mut c_void, channel: c_int, value: *mut c_long) -> c_int;
+    fn snd_mixer_selem_get_capture_volume(elem: *mut c_void, channel: c_int, value: *mut c_long) -> c_int;
+    fn snd_mixer_selem_get_playback_dB(elem: *mut c_void, channel: c_int, value: *mut c_long) -> c_int;
+    fn snd_mixer_selem_get_capture_dB(elem: *mut c_void, channel: c_int, value: *mut c_long) -> c_int;
+    fn snd_mixer_selem_set_playback_volume_all(elem: *mut c_void, value: c_long) -> c_int;
+    fn snd_mixer_selem_set_capture_volume_all(elem: *mut c_void, value: c_long) -> c_int;
+    fn snd_mixer_selem_set_playback_dB_all(elem: *mut c_void, value: c_long, dir: c_int) -> c_int;
+    fn snd_mixer_selem_set_capture_dB_all(elem: *mut c_void, value: c_long, dir: c_int) -> c_int;
+    fn snd_mixer_selem_get_playback_switch(elem: *mut c_void, channel: c_int, value: *mut c_int) -> c_int;
+    fn snd_mixer_selem_get_capture_switch(elem: *mut c_void, channel: c_int, value: *mut c_int) -> c_int;
+    fn snd_mixer_selem_set_playback_switch_all(elem: *mut c_void, value: c_int) -> c_int;
+    fn snd_mixer_selem_set_capture_switch_all(elem: *mut c_void, value: c_int) -> c_int;
 });
 
 //
@@ -282,11 +336,21 @@ extern "C" {
     pub(super) fn free(ptr: *mut c_void);
 }
 
-thread_local! {
-    static ALSA: Option<Alsa> = Alsa::new().ok();
+/// The ALSA library, loaded on first use and shared by all threads.
+struct Library(OnceLock<Option<Alsa>>);
+
+impl Library {
+    /// Call `f` with the library, or `None` if it isn't installed.
+    fn with<T>(&self, f: impl FnOnce(&Option<Alsa>) -> T) -> T {
+        f(self.0.get_or_init(|| Alsa::new().ok()))
+    }
 }
 
+static ALSA: Library = Library(OnceLock::new());
+
 #[path = "device_list.rs"]
 pub(super) mod device_list;
+#[path = "mixer.rs"]
+pub(super) mod mixer;
 #[path = "pcm.rs"]
 pub(super) mod pcm;
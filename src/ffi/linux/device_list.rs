@@ -11,63 +11,167 @@
 
 use std::{
     convert::TryInto,
-    ffi::CStr,
+    ffi::{CStr, CString},
     mem::MaybeUninit,
-    os::raw::{c_char, c_void},
+    os::raw::{c_char, c_int, c_void},
 };
 
 use fon::chan::{Ch32, Channel};
 
 use super::{
     free, pcm, Alsa, SndPcmAccess, SndPcmFormat, SndPcmMode, SndPcmStream,
+    SND_PCM_NO_AUTO_CHANNELS, SND_PCM_NO_AUTO_FORMAT, SND_PCM_NO_AUTO_RESAMPLE,
 };
+use crate::{ChannelPosition, SampleFormat, SourceKind, StreamConfig};
 
 pub(crate) const DEFAULT: &[u8] = b"default\0";
 
+/// PulseAudio (or PipeWire) monitor of the default output, through the ALSA
+/// pulse plugin.
+const MONITOR: &[u8] = b"pulse:DEVICE=@DEFAULT_MONITOR@\0";
+
+/// Capture end of the first `snd-aloop` device, which records what's played
+/// into `hw:Loopback,0`.
+const ALOOP: &[u8] = b"plughw:CARD=Loopback,DEV=1\0";
+
+/// Native endian 32-bit float samples.
+pub(crate) const FLOAT: SndPcmFormat = if cfg!(target_endian = "little") {
+    SndPcmFormat::FloatLe
+} else {
+    SndPcmFormat::FloatBe
+};
+
+/// Native endian signed 32-bit samples.
+const S32: SndPcmFormat = if cfg!(target_endian = "little") {
+    SndPcmFormat::S32Le
+} else {
+    SndPcmFormat::S32Be
+};
+
+/// Native endian signed 16-bit samples.
+const S16: SndPcmFormat = if cfg!(target_endian = "little") {
+    SndPcmFormat::S16Le
+} else {
+    SndPcmFormat::S16Be
+};
+
 /// Reset hardware parameters.
 pub(crate) unsafe fn reset_hwp(
     pcm: *mut c_void,
     hwp: *mut c_void,
+    format: SndPcmFormat,
 ) -> Option<()> {
-    let format = if cfg!(target_endian = "little") {
-        SndPcmFormat::FloatLe
-    } else if cfg!(target_endian = "big") {
-        SndPcmFormat::FloatBe
-    } else {
-        unreachable!()
-    };
     pcm::hw_params_any(pcm, hwp).ok()?;
     pcm::hw_params_set_access(pcm, hwp, SndPcmAccess::RwInterleaved).ok()?;
     pcm::hw_params_set_format(pcm, hwp, format).ok()?;
     Some(())
 }
 
-/// Open a PCM Device.
-pub(crate) fn open(
+/// Open a PCM device with the first of `formats` it supports, and get the
+/// PCM, its hardware parameters, supported channels and format.
+fn open_with(
     name: *const c_char,
     stream: SndPcmStream,
-) -> Option<(*mut c_void, *mut c_void, u8)> {
+    flags: c_int,
+    formats: &[SndPcmFormat],
+) -> Option<(*mut c_void, *mut c_void, u8, SndPcmFormat)> {
     unsafe {
-        let pcm = pcm::open(name, stream, SndPcmMode::Nonblock).ok()?;
-        let hwp = pcm::hw_params_malloc().ok()?;
+        let pcm = pcm::open(name, stream, SndPcmMode::Nonblock, flags).ok()?;
+        let hwp = match pcm::hw_params_malloc() {
+            Ok(hwp) => hwp,
+            Err(_) => {
+                let _ = pcm::close(pcm);
+                return None;
+            }
+        };
+        let format = formats
+            .iter()
+            .copied()
+            .find(|&format| reset_hwp(pcm, hwp, format).is_some());
+        let format = match format {
+            Some(format) => format,
+            None => {
+                // Close right away, so the device isn't kept busy.
+                pcm::hw_params_free(hwp);
+                let _ = pcm::close(pcm);
+                return None;
+            }
+        };
         let mut channels = 0;
-        reset_hwp(pcm, hwp)?;
         for i in 1..=8 {
             if pcm::hw_test_channels(pcm, hwp, i).is_ok() {
                 channels |= 1 << (i - 1);
             }
         }
-        Some((pcm, hwp, channels))
+        Some((pcm, hwp, channels, format))
+    }
+}
+
+/// Open a PCM Device.
+pub(crate) fn open(
+    name: *const c_char,
+    stream: SndPcmStream,
+) -> Option<(*mut c_void, *mut c_void, u8)> {
+    let (pcm, hwp, channels, _) = open_with(name, stream, 0, &[FLOAT])?;
+    Some((pcm, hwp, channels))
+}
+
+/// Close a PCM device opened with [`open()`].
+fn close(pcm: *mut c_void, hwp: *mut c_void) {
+    unsafe {
+        pcm::hw_params_free(hwp);
+        let _ = pcm::close(pcm);
+    }
+}
+
+/// Open the `plughw` PCM `pcm_name` through `dmix` or `dsnoop` so other
+/// streams can use the sound card at the same time, unless sharing supports
+/// fewer channels than the card (they default to stereo) or isn't available.
+fn open_shared(
+    pcm_name: *const c_char,
+    id: &str,
+    stream: SndPcmStream,
+) -> Option<(*mut c_void, *mut c_void, u8)> {
+    let shared = match shared_name(id, stream == SndPcmStream::Capture) {
+        Some(shared) => shared,
+        None => return open(pcm_name, stream),
+    };
+    // Check the card's channels first, as it's busy while shared.
+    let hardware = open(pcm_name, stream).map(|(pcm, hwp, supported)| {
+        close(pcm, hwp);
+        supported
+    });
+    let most = |supported: u8| 8 - supported.leading_zeros();
+    match open(shared.as_ptr(), stream) {
+        Some((pcm, hwp, supported))
+            if hardware.map_or(true, |hw| most(supported) >= most(hw)) =>
+        {
+            Some((pcm, hwp, supported))
+        }
+        Some((pcm, hwp, _)) => {
+            close(pcm, hwp);
+            open(pcm_name, stream)
+        }
+        None => open(pcm_name, stream),
     }
 }
 
+/// Open a PCM device without any automatic conversion, in the first sample
+/// format it supports natively.
+fn open_exclusive(
+    name: *const c_char,
+    stream: SndPcmStream,
+) -> Option<(*mut c_void, *mut c_void, u8, SndPcmFormat)> {
+    let flags = SND_PCM_NO_AUTO_RESAMPLE
+        | SND_PCM_NO_AUTO_CHANNELS
+        | SND_PCM_NO_AUTO_FORMAT;
+    open_with(name, stream, flags, &[FLOAT, S32, S16])
+}
+
 pub(crate) trait SoundDevice:
     std::fmt::Display + From<AudioDevice>
 {
     const INPUT: bool;
-
-    fn pcm(&self) -> *mut c_void;
-    fn hwp(&self) -> *mut c_void;
 }
 
 /// An Audio Device (input or output).
@@ -81,10 +185,25 @@ pub(crate) struct AudioDevice {
     pub(crate) hwp: *mut c_void,
     /// Bitflags for numbers of channels (which of 1-8 are supported)
     pub(crate) supported: u8,
+    /// What the device records (always `Input` for outputs).
+    pub(crate) kind: SourceKind,
+    /// Name of the ALSA control device for its mixer.
+    pub(crate) ctl: String,
     /// File descriptors associated with this device.
     pub(crate) fds: Vec<smelling_salts::Device>,
+    /// Sample format of the PCM.
+    pub(crate) format: SndPcmFormat,
+    /// Whether the PCM is opened on the hardware without conversions.
+    pub(crate) exclusive: bool,
+    /// Samples converted to or from the format, if it isn't float.
+    pub(crate) scratch: Vec<u8>,
 }
 
+// The PCM handles are owned exclusively by the device, and ALSA allows a handle
+// to be used from any thread as long as it isn't used from two at once.
+#[allow(unsafe_code)]
+unsafe impl Send for AudioDevice {}
+
 impl AudioDevice {
     /// Generate file descriptors.
     pub(crate) fn start(&mut self) -> Option<()> {
@@ -99,6 +218,112 @@ impl AudioDevice {
         }
         Some(())
     }
+
+    /// Get the sample format of the PCM.
+    pub(crate) fn sample_format(&self) -> SampleFormat {
+        if self.format == S32 {
+            SampleFormat::I32
+        } else if self.format == S16 {
+            SampleFormat::I16
+        } else {
+            SampleFormat::F32
+        }
+    }
+
+    /// Check if the PCM can run at exactly `sample_rate`.
+    pub(crate) fn supports_rate(&self, sample_rate: u32) -> bool {
+        unsafe {
+            reset_hwp(self.pcm, self.hwp, self.format).is_some()
+                && pcm::hw_test_rate(self.pcm, self.hwp, sample_rate).is_ok()
+        }
+    }
+
+    /// Get the configuration of the PCM, if configured.
+    pub(crate) fn stream_config(
+        &self,
+        sample_rate: Option<f64>,
+        channels: u8,
+        period: u16,
+    ) -> Option<StreamConfig> {
+        if channels == 0 {
+            return None;
+        }
+        Some(StreamConfig {
+            sample_rate: sample_rate?,
+            channels: channels.into(),
+            period: period.into(),
+            // Estimated from the buffer (sized to hold a period) if ALSA
+            // can't tell, such as before the PCM starts.
+            latency: unsafe { pcm::delay(self.pcm) }
+                .unwrap_or_else(|| period.into()),
+            format: self.sample_format(),
+            native_rate: self.exclusive,
+            native_channels: self.exclusive,
+            native_format: self.exclusive,
+        })
+    }
+
+    /// Write `frames` frames of interleaved audio, converted to the PCM's
+    /// sample format (scaled by the same factor as when reading, so integer
+    /// samples go through unchanged).
+    pub(crate) unsafe fn writei(
+        &mut self,
+        buffer: &[Ch32],
+        frames: usize,
+    ) -> Result<usize, isize> {
+        self.scratch.clear();
+        match self.sample_format() {
+            SampleFormat::F32 => {
+                return pcm::writei(self.pcm, buffer.as_ptr(), frames)
+            }
+            SampleFormat::I32 => {
+                for sample in buffer {
+                    let sample = sample.to_f64() * -f64::from(i32::MIN);
+                    let bytes = (sample.round() as i32).to_ne_bytes();
+                    self.scratch.extend_from_slice(&bytes);
+                }
+            }
+            SampleFormat::I16 => {
+                for sample in buffer {
+                    let sample = sample.to_f64() * -f64::from(i16::MIN);
+                    let bytes = (sample.round() as i16).to_ne_bytes();
+                    self.scratch.extend_from_slice(&bytes);
+                }
+            }
+        }
+        pcm::writei(self.pcm, self.scratch.as_ptr(), frames)
+    }
+
+    /// Read up to `frames` frames of interleaved audio, converted from the
+    /// PCM's sample format.
+    pub(crate) unsafe fn readi(
+        &mut self,
+        buffer: &mut [Ch32],
+        frames: u16,
+    ) -> Result<usize, isize> {
+        let size = match self.sample_format() {
+            SampleFormat::F32 => {
+                return pcm::readi(self.pcm, buffer.as_mut_ptr(), frames)
+            }
+            SampleFormat::I32 => 4,
+            SampleFormat::I16 => 2,
+        };
+        self.scratch.resize(buffer.len() * size, 0);
+        let len = pcm::readi(self.pcm, self.scratch.as_mut_ptr(), frames)?;
+        let samples = len * buffer.len() / usize::from(frames).max(1);
+        let scratch = self.scratch.chunks_exact(size);
+        for (dst, src) in buffer[..samples].iter_mut().zip(scratch) {
+            let sample = if size == 4 {
+                let sample = i32::from_ne_bytes(src.try_into().unwrap());
+                f64::from(sample) / -f64::from(i32::MIN)
+            } else {
+                let sample = i16::from_ne_bytes(src.try_into().unwrap());
+                f64::from(sample) / -f64::from(i16::MIN)
+            };
+            *dst = Ch32::new(sample as f32);
+        }
+        Ok(len)
+    }
 }
 
 impl Drop for AudioDevice {
@@ -115,21 +340,86 @@ impl Drop for AudioDevice {
     }
 }
 
-/// Return a list of available audio devices.
+/// Return a list of available audio devices, or if `exclusive` of the sound
+/// cards' hardware devices opened without conversions.
 pub(crate) fn device_list<D: SoundDevice, F: Fn(D) -> T, T>(
+    exclusive: bool,
     abstrakt: F,
 ) -> Vec<T> {
     super::ALSA.with(|alsa| {
         if let Some(alsa) = alsa {
-            device_list_internal(&alsa, abstrakt)
+            device_list_internal(alsa, exclusive, abstrakt)
         } else {
             Vec::new()
         }
     })
 }
 
+/// Open the microphone that records what's played through the system's
+/// default output, if there is one.
+pub(crate) fn system_output<D: SoundDevice>() -> Option<D> {
+    debug_assert!(D::INPUT);
+    let sources = [
+        (
+            MONITOR,
+            "Monitor of Default",
+            SourceKind::Monitor,
+            "default",
+        ),
+        (ALOOP, "Loopback", SourceKind::Loopback, "hw:CARD=Loopback"),
+    ];
+    sources.iter().find_map(|&(pcm_name, name, kind, ctl)| {
+        let (pcm, hwp, supported) =
+            open(pcm_name.as_ptr().cast(), SndPcmStream::Capture)?;
+        Some(D::from(AudioDevice {
+            name: name.to_string(),
+            pcm,
+            hwp,
+            supported,
+            kind,
+            ctl: ctl.to_string(),
+            fds: Vec::new(),
+            format: FLOAT,
+            exclusive: false,
+            scratch: Vec::new(),
+        }))
+    })
+}
+
+/// Get the name of the control device of the card a PCM is on, falling back
+/// to the default control device.
+fn control_name(pcm_name: &str) -> String {
+    match pcm_name.split_once("CARD=") {
+        Some((_, card)) => {
+            format!("hw:CARD={}", card.split(',').next().unwrap_or(card))
+        }
+        None => "default".to_string(),
+    }
+}
+
+/// Get the name of the PCM sharing the sound card a `plughw` PCM opens
+/// exclusively: `dmix` for playback or `dsnoop` for capture, behind `plug` to
+/// convert to and from float samples.
+fn shared_name(pcm_name: &str, input: bool) -> Option<CString> {
+    let card = pcm_name.strip_prefix("plughw:")?;
+    let plugin = if input { "dsnoop" } else { "dmix" };
+    CString::new(format!("plug:\"{}:{}\"", plugin, card)).ok()
+}
+
+/// Guess what a capture device records from its ALSA PCM name.
+fn source_kind(pcm_name: &str) -> SourceKind {
+    if pcm_name.contains("CARD=Loopback") {
+        SourceKind::Loopback
+    } else if pcm_name.to_ascii_lowercase().contains("monitor") {
+        SourceKind::Monitor
+    } else {
+        SourceKind::Input
+    }
+}
+
 fn device_list_internal<D: SoundDevice, F: Fn(D) -> T, T>(
     alsa: &Alsa,
+    exclusive: bool,
     abstrakt: F,
 ) -> Vec<T> {
     let tpcm = CStr::from_bytes_with_nul(b"pcm\0").unwrap();
@@ -152,6 +442,13 @@ fn device_list_internal<D: SoundDevice, F: Fn(D) -> T, T>(
             let pcm_name = (alsa.snd_device_name_get_hint)(*n, tname.as_ptr());
             let io = (alsa.snd_device_name_get_hint)(*n, tioid.as_ptr());
             debug_assert_ne!(pcm_name, std::ptr::null_mut());
+            let id = CStr::from_ptr(pcm_name).to_string_lossy();
+            let kind = if D::INPUT {
+                source_kind(&id)
+            } else {
+                SourceKind::Input
+            };
+            let ctl = control_name(&id);
 
             // Convert description to Rust String
             let name = match CStr::from_ptr(pcm_name).to_str() {
@@ -164,6 +461,11 @@ fn device_list_internal<D: SoundDevice, F: Fn(D) -> T, T>(
                     n = n.offset(1);
                     continue;
                 }
+                Ok(x) if exclusive && !x.starts_with("hw:") => {
+                    // Only hardware devices don't go through plugins.
+                    n = n.offset(1);
+                    continue;
+                }
                 Ok("default") => "Default".to_string(),
                 _a => {
                     let name =
@@ -185,55 +487,122 @@ fn device_list_internal<D: SoundDevice, F: Fn(D) -> T, T>(
 
             // Right input type?
             if (D::INPUT && is_input) || (!D::INPUT && is_output) {
-                // Try to connect to PCM.
-                let dev = open(
-                    pcm_name,
-                    if D::INPUT {
-                        SndPcmStream::Capture
-                    } else {
-                        SndPcmStream::Playback
-                    },
-                );
-
-                if let Some((pcm, hwp, supported)) = dev {
+                let stream = if D::INPUT {
+                    SndPcmStream::Capture
+                } else {
+                    SndPcmStream::Playback
+                };
+                // Try to connect to PCM, shared if the sound card supports it
+                // so other streams can use the card at the same time.
+                let dev = if exclusive {
+                    open_exclusive(pcm_name, stream)
+                } else {
+                    open_shared(pcm_name, &id, stream).map(
+                        |(pcm, hwp, supported)| (pcm, hwp, supported, FLOAT),
+                    )
+                };
+
+                if let Some((pcm, hwp, supported, format)) = dev {
                     // Add device to list of devices.
                     devices.push(abstrakt(D::from(AudioDevice {
                         name,
                         pcm,
                         hwp,
                         supported,
+                        kind,
+                        ctl,
                         fds: Vec::new(),
+                        format,
+                        exclusive,
+                        scratch: Vec::new(),
                     })));
                 }
             }
+            drop(id);
             free(pcm_name.cast());
             n = n.offset(1);
         }
         (alsa.snd_device_name_free_hint)(hints);
     }
+    // Monitor sources aren't listed in the hints.
+    if D::INPUT && !exclusive {
+        if let Some((pcm, hwp, supported)) =
+            open(MONITOR.as_ptr().cast(), SndPcmStream::Capture)
+        {
+            devices.push(abstrakt(D::from(AudioDevice {
+                name: "Monitor of Default".to_string(),
+                pcm,
+                hwp,
+                supported,
+                kind: SourceKind::Monitor,
+                ctl: "default".to_string(),
+                fds: Vec::new(),
+                format: FLOAT,
+                exclusive: false,
+                scratch: Vec::new(),
+            })));
+        }
+    }
     devices
 }
 
+/// Get the position of each channel the device is configured with (empty if
+/// it isn't configured, or they aren't known).
 #[allow(unsafe_code)]
+pub(crate) fn channel_positions(device: &AudioDevice) -> Vec<ChannelPosition> {
+    let positions = unsafe { pcm::get_chmap(device.pcm) }.unwrap_or_default();
+    positions
+        .into_iter()
+        .map(|position| match position {
+            0 => ChannelPosition::Unknown,
+            1 => ChannelPosition::NotAvailable,
+            2 => ChannelPosition::Mono,
+            3 => ChannelPosition::FrontLeft,
+            4 => ChannelPosition::FrontRight,
+            5 => ChannelPosition::RearLeft,
+            6 => ChannelPosition::RearRight,
+            7 => ChannelPosition::FrontCenter,
+            8 => ChannelPosition::Lfe,
+            9 => ChannelPosition::SideLeft,
+            10 => ChannelPosition::SideRight,
+            11 => ChannelPosition::RearCenter,
+            other => ChannelPosition::Other(other),
+        })
+        .collect()
+}
+
+#[allow(unsafe_code)]
+/// Configure the PCM for `channels` channels at `target_rate` (or the
+/// nearest rate it supports, unless `exact`).
 pub(crate) fn pcm_hw_params(
     device: &AudioDevice,
     channels: u8,
+    mut target_rate: u32,
+    exact: bool,
     buffer: &mut Vec<Ch32>,
     sample_rate: &mut Option<f64>,
     period: &mut u16,
 ) -> Option<()> {
     unsafe {
+        // Stop the device if it's running, so it can be reconfigured.
+        let _ = pcm::drop(device.pcm);
+
         // Reset hardware parameters to any interleaved native endian float32
-        reset_hwp(device.pcm, device.hwp)?;
+        reset_hwp(device.pcm, device.hwp, device.format)?;
 
-        // Set Hz near library target Hz.
-        pcm::hw_params_set_rate_near(
-            device.pcm,
-            device.hwp,
-            &mut crate::consts::SAMPLE_RATE.into(),
-            &mut 0,
-        )
-        .ok()?;
+        // Set Hz to (or near) target Hz.
+        if exact {
+            pcm::hw_params_set_rate(device.pcm, device.hwp, target_rate)
+                .ok()?;
+        } else {
+            pcm::hw_params_set_rate_near(
+                device.pcm,
+                device.hwp,
+                &mut target_rate,
+                &mut 0,
+            )
+            .ok()?;
+        }
         // Set the number of channels.
         pcm::hw_set_channels(device.pcm, device.hwp, channels).ok()?;
         // Set period near library target period.
@@ -10,12 +10,19 @@
 mod asound;
 mod microphone;
 mod speakers;
+mod thread;
+mod volume;
 
 // Implementation Expectations:
-pub(crate) use asound::device_list::device_list;
+pub(crate) use asound::device_list::{device_list, system_output};
 use asound::{
-    device_list::{open, pcm_hw_params, AudioDevice, SoundDevice, DEFAULT},
+    device_list::{
+        channel_positions, open, pcm_hw_params, AudioDevice, SoundDevice,
+        DEFAULT, FLOAT,
+    },
     PollFd, SndPcmAccess, SndPcmFormat, SndPcmMode, SndPcmState, SndPcmStream,
 };
 pub(crate) use microphone::{Microphone, MicrophoneStream};
 pub(crate) use speakers::{Speakers, SpeakersSink};
+pub(crate) use thread::set_realtime_priority;
+pub(crate) use volume::VolumeControl;
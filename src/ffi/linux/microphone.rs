@@ -12,75 +12,57 @@
 use std::{
     fmt::{Display, Error, Formatter},
     future::Future,
+    io,
     marker::PhantomData,
-    os::raw::c_void,
     pin::Pin,
-    sync::atomic::{AtomicBool, Ordering::SeqCst},
+    sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
-use fon::{chan::Ch32, Frame, Stream};
+use fon::{
+    chan::{Ch32, Channel},
+    Frame, Stream,
+};
 
 use super::{
-    asound, pcm_hw_params, AudioDevice, SndPcmState, SndPcmStream, SoundDevice,
-    DEFAULT,
+    asound, channel_positions, pcm_hw_params, AudioDevice, SndPcmState,
+    SndPcmStream, SoundDevice, VolumeControl, DEFAULT, FLOAT,
 };
+use crate::{ChannelPosition, SourceKind, StreamConfig};
 
-struct MicrophoneInner {
+pub(crate) struct Microphone {
+    // Number of channels on the Microphone.
+    pub(crate) channels: u8,
+    // Sample Rate of The Microphone (src)
+    pub(crate) sample_rate: Option<f64>,
+    // Sample rate to ask the hardware for, if requested.
+    target_rate: Option<u32>,
+    // What the microphone records.
+    kind: SourceKind,
     // PCM I/O Handle
     device: AudioDevice,
-    // Interleaved Audio Buffer.
+    // Interleaved Audio Buffer (empty while lent to a stream).
     buffer: Vec<Ch32>,
+    // Where a stream returns the buffer when dropped.
+    returned: Arc<Mutex<Vec<Ch32>>>,
     // The period of the microphone.
     period: u16,
     // Index to stop reading.
     endi: usize,
-    /// Microphone are locked
-    locked: AtomicBool,
-}
-
-pub(crate) struct Microphone {
-    // Number of channels on the Microphone.
-    pub(crate) channels: u8,
-    // Sample Rate of The Microphone (src)
-    pub(crate) sample_rate: Option<f64>,
-    /// Leaked shared box
-    inner: *mut MicrophoneInner,
-}
-
-impl Drop for Microphone {
-    fn drop(&mut self) {
-        // Safety
-        if unsafe { (*self.inner).locked.load(SeqCst) } {
-            eprintln!("Microphone dropped before dropping stream");
-            std::process::exit(1);
-        }
-
-        unsafe { drop(Box::from_raw(self.inner)) };
-    }
+    // Why the sound card couldn't be configured, if it couldn't (stopping
+    // the microphone until the sample rate is changed).
+    error: Option<io::Error>,
+    // Whether configuring the sound card failed.
+    failed: bool,
 }
 
 impl SoundDevice for Microphone {
     const INPUT: bool = true;
-
-    fn pcm(&self) -> *mut c_void {
-        unsafe { (*self.inner).device.pcm }
-    }
-
-    fn hwp(&self) -> *mut c_void {
-        unsafe { (*self.inner).device.pcm }
-    }
 }
 
 impl Display for Microphone {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        // Safety
-        if unsafe { (*self.inner).locked.load(SeqCst) } {
-            eprintln!("Tried to display speakers before dropping sink");
-            std::process::exit(1);
-        }
-
-        unsafe { f.write_str((*self.inner).device.name.as_str()) }
+        f.write_str(self.device.name.as_str())
     }
 }
 
@@ -89,13 +71,15 @@ impl From<AudioDevice> for Microphone {
         Self {
             channels: 0,
             sample_rate: None,
-            inner: Box::leak(Box::new(MicrophoneInner {
-                device,
-                buffer: Vec::new(),
-                period: 0,
-                endi: 0,
-                locked: AtomicBool::new(false),
-            })),
+            target_rate: None,
+            kind: device.kind,
+            device,
+            buffer: Vec::new(),
+            returned: Arc::new(Mutex::new(Vec::new())),
+            period: 0,
+            endi: 0,
+            error: None,
+            failed: false,
         }
     }
 }
@@ -110,14 +94,19 @@ impl Default for Microphone {
             pcm,
             hwp,
             supported,
+            kind: SourceKind::Input,
+            ctl: "default".to_string(),
             fds: Vec::new(),
+            format: FLOAT,
+            exclusive: false,
+            scratch: Vec::new(),
         })
     }
 }
 
 impl Microphone {
     /// Attempt to configure the microphone for a specific number of channels.
-    fn set_channels<F>(&mut self, inner: &mut MicrophoneInner) -> Option<bool>
+    fn set_channels<F>(&mut self) -> Option<bool>
     where
         F: Frame<Chan = Ch32>,
     {
@@ -125,15 +114,35 @@ impl Microphone {
             if !matches!(F::CHAN_COUNT, 1 | 2 | 6) {
                 panic!("Unknown speaker configuration")
             }
-            self.channels = F::CHAN_COUNT as u8;
-            // Configure Hardware Parameters
-            pcm_hw_params(
-                &inner.device,
-                self.channels,
-                &mut inner.buffer,
+            // Configure Hardware Parameters (at exactly the requested rate,
+            // if exclusive).
+            let rate = self
+                .target_rate
+                .unwrap_or_else(|| crate::consts::SAMPLE_RATE.into());
+            let exact = self.device.exclusive && self.target_rate.is_some();
+            if pcm_hw_params(
+                &self.device,
+                F::CHAN_COUNT as u8,
+                rate,
+                exact,
+                &mut self.buffer,
                 &mut self.sample_rate,
-                &mut inner.period,
-            )?;
+                &mut self.period,
+            )
+            .is_none()
+            {
+                self.error = Some(io::Error::other(format!(
+                    "{} can't record {} channels at {} {} Hz",
+                    self.device.name,
+                    F::CHAN_COUNT,
+                    if exact { "exactly" } else { "about" },
+                    rate,
+                )));
+                self.channels = 0;
+                self.failed = true;
+                return None;
+            }
+            self.channels = F::CHAN_COUNT as u8;
             Some(true)
         } else {
             Some(false)
@@ -143,25 +152,77 @@ impl Microphone {
     pub(crate) fn record<F: Frame<Chan = Ch32>>(
         &mut self,
     ) -> MicrophoneStream<F> {
-        // Always called after ready, so should be safe
-        let inner = unsafe { self.inner.as_mut().unwrap() };
-
-        // Change number of channels, if different than last call.
-        self.set_channels::<F>(inner)
-            .expect("Microphone::record() called with invalid configuration");
+        // Change number of channels, if different than last call (recording
+        // nothing if the sound card can't be configured).
+        let len = match self.set_channels::<F>() {
+            Some(_) => self.endi * self.channels as usize,
+            None => 0,
+        };
 
-        // Stream from microphone's buffer.
-        MicrophoneStream(inner, 0, PhantomData, self.sample_rate, self.channels)
+        // Lend the microphone's buffer to the stream.
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.truncate(len);
+        self.endi = 0;
+        MicrophoneStream {
+            buffer,
+            returned: self.returned.clone(),
+            index: 0,
+            sample_rate: self.sample_rate,
+            _phantom: PhantomData,
+        }
     }
 
+    /// Get the supported numbers of channels (none, if exclusive and the
+    /// requested sample rate isn't supported).
     pub(crate) fn channels(&self) -> u8 {
-        // Safety
-        if unsafe { (*self.inner).locked.load(SeqCst) } {
-            eprintln!("Tried to poll speakers before dropping sink");
-            std::process::exit(1);
+        match self.target_rate {
+            Some(rate)
+                if self.device.exclusive
+                    && !self.device.supports_rate(rate) =>
+            {
+                0
+            }
+            _ => self.device.supported,
         }
+    }
 
-        unsafe { (*self.inner).device.supported }
+    pub(crate) fn channel_positions(&self) -> Vec<ChannelPosition> {
+        channel_positions(&self.device)
+    }
+
+    pub(crate) fn volume_controls(&self) -> Vec<VolumeControl> {
+        VolumeControl::list(&self.device.ctl, true)
+    }
+
+    pub(crate) fn sample_rate(&self) -> Option<f64> {
+        self.sample_rate
+    }
+
+    /// Ask for `sample_rate` when next configured, reconfiguring on the next
+    /// period if already configured at another rate.
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.target_rate = Some(sample_rate);
+        self.failed = false;
+        if self.sample_rate.is_some()
+            && self.sample_rate != Some(sample_rate.into())
+        {
+            self.channels = 0;
+            self.endi = 0;
+        }
+    }
+
+    pub(crate) fn kind(&self) -> SourceKind {
+        self.kind
+    }
+
+    /// Take the error that stopped the microphone, if any.
+    pub(crate) fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub(crate) fn stream_config(&self) -> Option<StreamConfig> {
+        self.device
+            .stream_config(self.sample_rate, self.channels, self.period)
     }
 }
 
@@ -173,24 +234,21 @@ impl Future for Microphone {
         // Get mutable reference to microphone.
         let this = self.get_mut();
 
-        // Safety
-        if unsafe { (*this.inner).locked.load(SeqCst) } {
-            eprintln!("Tried to poll microphone before dropping stream");
-            std::process::exit(1);
+        // Wait for another sample rate if the sound card couldn't be
+        // configured.
+        if this.failed {
+            return Poll::Pending;
         }
-        //
-        let inner = unsafe { this.inner.as_mut().unwrap() };
 
         // If microphone is unconfigured, return Ready to configure and play.
         if this.channels == 0 {
-            let _ = inner.device.start();
-            inner.locked.store(true, SeqCst);
+            let _ = this.device.start();
             return Poll::Ready(());
         }
 
         // Check if not woken, then yield.
         let mut pending = true;
-        for fd in &inner.device.fds {
+        for fd in &this.device.fds {
             if !fd.should_yield() {
                 pending = false;
                 break;
@@ -200,14 +258,19 @@ impl Future for Microphone {
             return Poll::Pending;
         }
 
+        // Take back the buffer lent to the last stream (only allocating if
+        // that stream is still alive).
+        if this.buffer.is_empty() {
+            this.buffer = std::mem::take(&mut *this.returned.lock().unwrap());
+            this.buffer.resize(
+                usize::from(this.period) * usize::from(this.channels),
+                Ch32::MID,
+            );
+        }
+
         // Attempt to overwrite the internal microphone buffer.
-        let result = unsafe {
-            asound::pcm::readi(
-                inner.device.pcm,
-                inner.buffer.as_mut_slice().as_mut_ptr(),
-                inner.period,
-            )
-        };
+        let result =
+            unsafe { this.device.readi(&mut this.buffer, this.period) };
 
         // Check if it succeeds, then return Ready.
         match result {
@@ -225,11 +288,11 @@ impl Future for Microphone {
                         unreachable!()
                     }
                     -32 => {
-                        match unsafe { asound::pcm::state(inner.device.pcm) } {
+                        match unsafe { asound::pcm::state(this.device.pcm) } {
                             SndPcmState::Xrun => {
                                 eprintln!("Microphone XRUN: Latency cause?");
                                 unsafe {
-                                    asound::pcm::prepare(inner.device.pcm)
+                                    asound::pcm::prepare(this.device.pcm)
                                         .unwrap();
                                 }
                             }
@@ -248,15 +311,15 @@ impl Future for Microphone {
                         "Stream got suspended, trying to recover… (-ESTRPIPE)"
                     );
                         unsafe {
-                            if asound::pcm::resume(inner.device.pcm).is_ok() {
+                            if asound::pcm::resume(this.device.pcm).is_ok() {
                                 // Prepare, so we keep getting samples.
-                                asound::pcm::prepare(inner.device.pcm).unwrap();
+                                asound::pcm::prepare(this.device.pcm).unwrap();
                             }
                         }
                     }
                     _ => unreachable!(),
                 }
-                for fd in &inner.device.fds {
+                for fd in &this.device.fds {
                     // Register waker
                     fd.register_waker(cx.waker());
                 }
@@ -264,52 +327,52 @@ impl Future for Microphone {
                 Poll::Pending
             }
             Ok(len) => {
-                inner.endi = len;
+                this.endi = len;
                 // Ready, audio buffer has been filled!
-                inner.locked.store(true, SeqCst);
                 Poll::Ready(())
             }
         }
     }
 }
 
-pub(crate) struct MicrophoneStream<F: Frame<Chan = Ch32>>(
-    *mut MicrophoneInner,
-    usize,
-    PhantomData<F>,
-    Option<f64>,
-    u8,
-);
+pub(crate) struct MicrophoneStream<F: Frame<Chan = Ch32>> {
+    // Interleaved audio recorded during the last period.
+    buffer: Vec<Ch32>,
+    // Where the buffer is returned to the microphone.
+    returned: Arc<Mutex<Vec<Ch32>>>,
+    // Index of the next frame.
+    index: usize,
+    // Sample rate of the microphone.
+    sample_rate: Option<f64>,
+    // Frame type of the stream.
+    _phantom: PhantomData<F>,
+}
 
 impl<F: Frame<Chan = Ch32>> Iterator for MicrophoneStream<F> {
     type Item = F;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mic = unsafe { self.0.as_mut().unwrap() };
-        if self.1 >= mic.endi {
+        let start = self.index * F::CHAN_COUNT;
+        if start >= self.buffer.len() {
             return None;
         }
-        let frame = F::from_channels(&mic.buffer[self.1 * self.4 as usize..]);
-        self.1 += 1;
-        Some(frame)
+        self.index += 1;
+        Some(F::from_channels(&self.buffer[start..]))
     }
 }
 
 impl<F: Frame<Chan = Ch32>> Stream<F> for MicrophoneStream<F> {
     fn sample_rate(&self) -> Option<f64> {
-        self.3
+        self.sample_rate
     }
 
     fn len(&self) -> Option<usize> {
-        let mic = unsafe { self.0.as_mut().unwrap() };
-        Some(mic.endi)
+        Some(self.buffer.len() / F::CHAN_COUNT)
     }
 }
 
 impl<F: Frame<Chan = Ch32>> Drop for MicrophoneStream<F> {
     fn drop(&mut self) {
-        let mic = unsafe { self.0.as_mut().unwrap() };
-        // Unlock
-        mic.locked.store(false, SeqCst);
+        *self.returned.lock().unwrap() = std::mem::take(&mut self.buffer);
     }
 }
@@ -0,0 +1,281 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+#![allow(unsafe_code)]
+
+use std::{
+    convert::TryInto,
+    ffi::CStr,
+    mem::MaybeUninit,
+    os::raw::{c_char, c_int, c_long, c_void},
+    ptr,
+};
+
+use super::{super::PollFd, Alsa, ALSA};
+
+/// Channel read from elements (`SND_MIXER_SCHN_FRONT_LEFT`, also mono).
+const CHANNEL: c_int = 0;
+
+/// Call `f` with the library, failing if it isn't installed.
+fn with_alsa<T>(f: impl FnOnce(&Alsa) -> Result<T, i64>) -> Result<T, i64> {
+    ALSA.with(|alsa| {
+        if let Some(alsa) = alsa {
+            f(alsa)
+        } else {
+            Err(0)
+        }
+    })
+}
+
+/// Check the return value of an ALSA function.
+fn check(ret: c_int) -> Result<c_int, i64> {
+    if ret < 0 {
+        Err(ret.into())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Open a mixer for the control device `name`, with simple elements loaded.
+pub(crate) unsafe fn open(name: *const c_char) -> Result<*mut c_void, i64> {
+    with_alsa(|alsa| {
+        let mut mixer = MaybeUninit::uninit();
+        check((alsa.snd_mixer_open)(mixer.as_mut_ptr(), 0))?;
+        let mixer = mixer.assume_init();
+        let loaded = check((alsa.snd_mixer_attach)(mixer, name))
+            .and_then(|_| {
+                check((alsa.snd_mixer_selem_register)(
+                    mixer,
+                    ptr::null_mut(),
+                    ptr::null_mut(),
+                ))
+            })
+            .and_then(|_| check((alsa.snd_mixer_load)(mixer)));
+        if let Err(error) = loaded {
+            (alsa.snd_mixer_close)(mixer);
+            return Err(error);
+        }
+        Ok(mixer)
+    })
+}
+
+pub(crate) unsafe fn close(mixer: *mut c_void) -> Result<(), i64> {
+    with_alsa(|alsa| check((alsa.snd_mixer_close)(mixer)).map(|_| ()))
+}
+
+/// Process pending events, so elements have up-to-date values.
+pub(crate) unsafe fn handle_events(mixer: *mut c_void) -> Result<(), i64> {
+    with_alsa(|alsa| check((alsa.snd_mixer_handle_events)(mixer)).map(|_| ()))
+}
+
+pub(crate) unsafe fn poll_descriptors(
+    mixer: *mut c_void,
+) -> Result<Vec<PollFd>, i64> {
+    with_alsa(|alsa| {
+        let size: usize =
+            check((alsa.snd_mixer_poll_descriptors_count)(mixer))?
+                .try_into()
+                .unwrap();
+        let mut poll = Vec::with_capacity(size);
+        let ret = (alsa.snd_mixer_poll_descriptors)(
+            mixer,
+            poll.as_mut_ptr(),
+            size.try_into().unwrap(),
+        );
+        check(ret)?;
+        poll.set_len(size);
+        Ok(poll)
+    })
+}
+
+/// Get the simple elements of a mixer.
+pub(crate) unsafe fn elements(mixer: *mut c_void) -> Vec<*mut c_void> {
+    with_alsa(|alsa| {
+        let mut elements = Vec::new();
+        let mut elem = (alsa.snd_mixer_first_elem)(mixer);
+        while !elem.is_null() {
+            elements.push(elem);
+            elem = (alsa.snd_mixer_elem_next)(elem);
+        }
+        Ok(elements)
+    })
+    .unwrap_or_default()
+}
+
+/// Get the name and index of an element.
+pub(crate) unsafe fn name(elem: *mut c_void) -> Result<(String, u32), i64> {
+    with_alsa(|alsa| {
+        let name = (alsa.snd_mixer_selem_get_name)(elem);
+        let name = CStr::from_ptr(name).to_string_lossy().to_string();
+        Ok((name, (alsa.snd_mixer_selem_get_index)(elem)))
+    })
+}
+
+/// Check if an element has a volume and a mute switch, for capture or
+/// playback.
+pub(crate) unsafe fn has_volume_switch(
+    elem: *mut c_void,
+    capture: bool,
+) -> (bool, bool) {
+    with_alsa(|alsa| {
+        Ok(if capture {
+            (
+                (alsa.snd_mixer_selem_has_capture_volume)(elem) != 0,
+                (alsa.snd_mixer_selem_has_capture_switch)(elem) != 0,
+            )
+        } else {
+            (
+                (alsa.snd_mixer_selem_has_playback_volume)(elem) != 0,
+                (alsa.snd_mixer_selem_has_playback_switch)(elem) != 0,
+            )
+        })
+    })
+    .unwrap_or((false, false))
+}
+
+/// Get the range of raw volume values.
+pub(crate) unsafe fn volume_range(
+    elem: *mut c_void,
+    capture: bool,
+) -> Result<(c_long, c_long), i64> {
+    with_alsa(|alsa| {
+        let (mut min, mut max) = (0, 0);
+        check(if capture {
+            (alsa.snd_mixer_selem_get_capture_volume_range)(
+                elem, &mut min, &mut max,
+            )
+        } else {
+            (alsa.snd_mixer_selem_get_playback_volume_range)(
+                elem, &mut min, &mut max,
+            )
+        })?;
+        Ok((min, max))
+    })
+}
+
+/// Get the range of volume in hundredths of a decibel.
+pub(crate) unsafe fn db_range(
+    elem: *mut c_void,
+    capture: bool,
+) -> Result<(c_long, c_long), i64> {
+    with_alsa(|alsa| {
+        let (mut min, mut max) = (0, 0);
+        check(if capture {
+            (alsa.snd_mixer_selem_get_capture_dB_range)(
+                elem, &mut min, &mut max,
+            )
+        } else {
+            (alsa.snd_mixer_selem_get_playback_dB_range)(
+                elem, &mut min, &mut max,
+            )
+        })?;
+        Ok((min, max))
+    })
+}
+
+/// Get the raw volume.
+pub(crate) unsafe fn volume(
+    elem: *mut c_void,
+    capture: bool,
+) -> Result<c_long, i64> {
+    with_alsa(|alsa| {
+        let mut value = 0;
+        check(if capture {
+            (alsa.snd_mixer_selem_get_capture_volume)(elem, CHANNEL, &mut value)
+        } else {
+            (alsa.snd_mixer_selem_get_playback_volume)(
+                elem, CHANNEL, &mut value,
+            )
+        })?;
+        Ok(value)
+    })
+}
+
+/// Get the volume in hundredths of a decibel.
+pub(crate) unsafe fn db(
+    elem: *mut c_void,
+    capture: bool,
+) -> Result<c_long, i64> {
+    with_alsa(|alsa| {
+        let mut value = 0;
+        check(if capture {
+            (alsa.snd_mixer_selem_get_capture_dB)(elem, CHANNEL, &mut value)
+        } else {
+            (alsa.snd_mixer_selem_get_playback_dB)(elem, CHANNEL, &mut value)
+        })?;
+        Ok(value)
+    })
+}
+
+/// Set the raw volume of all channels.
+pub(crate) unsafe fn set_volume(
+    elem: *mut c_void,
+    capture: bool,
+    value: c_long,
+) -> Result<(), i64> {
+    with_alsa(|alsa| {
+        check(if capture {
+            (alsa.snd_mixer_selem_set_capture_volume_all)(elem, value)
+        } else {
+            (alsa.snd_mixer_selem_set_playback_volume_all)(elem, value)
+        })
+        .map(|_| ())
+    })
+}
+
+/// Set the volume of all channels in hundredths of a decibel, rounding to the
+/// nearest step.
+pub(crate) unsafe fn set_db(
+    elem: *mut c_void,
+    capture: bool,
+    value: c_long,
+) -> Result<(), i64> {
+    with_alsa(|alsa| {
+        check(if capture {
+            (alsa.snd_mixer_selem_set_capture_dB_all)(elem, value, 0)
+        } else {
+            (alsa.snd_mixer_selem_set_playback_dB_all)(elem, value, 0)
+        })
+        .map(|_| ())
+    })
+}
+
+/// Get the switch (on is unmuted).
+pub(crate) unsafe fn switch(
+    elem: *mut c_void,
+    capture: bool,
+) -> Result<bool, i64> {
+    with_alsa(|alsa| {
+        let mut value = 0;
+        check(if capture {
+            (alsa.snd_mixer_selem_get_capture_switch)(elem, CHANNEL, &mut value)
+        } else {
+            (alsa.snd_mixer_selem_get_playback_switch)(
+                elem, CHANNEL, &mut value,
+            )
+        })?;
+        Ok(value != 0)
+    })
+}
+
+/// Set the switch of all channels (on is unmuted).
+pub(crate) unsafe fn set_switch(
+    elem: *mut c_void,
+    capture: bool,
+    on: bool,
+) -> Result<(), i64> {
+    with_alsa(|alsa| {
+        check(if capture {
+            (alsa.snd_mixer_selem_set_capture_switch_all)(elem, on.into())
+        } else {
+            (alsa.snd_mixer_selem_set_playback_switch_all)(elem, on.into())
+        })
+        .map(|_| ())
+    })
+}
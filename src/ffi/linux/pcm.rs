@@ -12,7 +12,7 @@
 use std::{
     convert::TryInto,
     mem::MaybeUninit,
-    os::raw::{c_char, c_int, c_uint, c_void},
+    os::raw::{c_char, c_int, c_long, c_uint, c_void},
 };
 
 use super::{
@@ -113,6 +113,42 @@ pub(crate) unsafe fn hw_params_set_rate_near(
     })
 }
 
+/// Set the exact sample rate.
+pub(crate) unsafe fn hw_params_set_rate(
+    pcm: *mut c_void,
+    params: *mut c_void,
+    val: c_uint,
+) -> Result<(), i64> {
+    ALSA.with(|alsa| {
+        let alsa = if let Some(alsa) = alsa {
+            alsa
+        } else {
+            return Err(0);
+        };
+        let ret = (alsa.snd_pcm_hw_params_set_rate)(pcm, params, val, 0);
+        let _: u64 = ret.try_into().map_err(|_| ret)?;
+        Ok(())
+    })
+}
+
+/// Test if an exact sample rate is supported.
+pub(crate) unsafe fn hw_test_rate(
+    pcm: *mut c_void,
+    params: *mut c_void,
+    val: c_uint,
+) -> Result<(), i64> {
+    ALSA.with(|alsa| {
+        let alsa = if let Some(alsa) = alsa {
+            alsa
+        } else {
+            return Err(0);
+        };
+        let ret = (alsa.snd_pcm_hw_params_test_rate)(pcm, params, val, 0);
+        let _: u64 = ret.try_into().map_err(|_| ret)?;
+        Ok(())
+    })
+}
+
 pub(crate) unsafe fn hw_params_free(params: *mut c_void) {
     ALSA.with(|alsa| {
         let alsa = if let Some(alsa) = alsa {
@@ -267,6 +303,7 @@ pub(crate) unsafe fn open(
     name: *const c_char,
     stream: SndPcmStream,
     mode: SndPcmMode,
+    flags: c_int,
 ) -> Result<*mut c_void, i64> {
     ALSA.with(|alsa| {
         let alsa = if let Some(alsa) = alsa {
@@ -275,8 +312,8 @@ pub(crate) unsafe fn open(
             return Err(0);
         };
         let mut pcm = MaybeUninit::uninit();
-        let ret =
-            (alsa.snd_pcm_open)(pcm.as_mut_ptr(), name, stream, mode as c_int);
+        let mode = mode as c_int | flags;
+        let ret = (alsa.snd_pcm_open)(pcm.as_mut_ptr(), name, stream, mode);
         let _: u64 = ret.try_into().map_err(|_| ret)?;
         let pcm = pcm.assume_init();
         Ok(pcm)
@@ -342,6 +379,36 @@ pub(crate) unsafe fn state(pcm: *mut c_void) -> SndPcmState {
     })
 }
 
+/// Get the delay in frames: from writing a frame until it's heard, or from
+/// recording a frame until it's read.
+pub(crate) unsafe fn delay(pcm: *mut c_void) -> Option<usize> {
+    ALSA.with(|alsa| {
+        let alsa = alsa.as_ref()?;
+        let mut delay: c_long = 0;
+        if (alsa.snd_pcm_delay)(pcm, &mut delay) < 0 {
+            return None;
+        }
+        delay.try_into().ok()
+    })
+}
+
+/// Get the position of each channel of the current configuration, as ALSA
+/// `SND_CHMAP_*` numbers, or `None` if the driver doesn't know them.
+pub(crate) unsafe fn get_chmap(pcm: *mut c_void) -> Option<Vec<u32>> {
+    ALSA.with(|alsa| {
+        let alsa = alsa.as_ref()?;
+        // A `snd_pcm_chmap_t`: the number of channels, then their positions.
+        let chmap = (alsa.snd_pcm_get_chmap)(pcm);
+        if chmap.is_null() {
+            return None;
+        }
+        let channels = *chmap as usize;
+        let positions = (0..channels).map(|i| *chmap.add(1 + i)).collect();
+        super::free(chmap.cast());
+        Some(positions)
+    })
+}
+
 /// Read microphone input into an audio frame buffer.
 ///
 /// Marked unsafe because pcm must be configured to handle interleaved frames
@@ -358,7 +425,7 @@ pub(crate) unsafe fn readi<T>(
             return Ok(0);
         };
         let ret = (alsa.snd_pcm_readi)(pcm, buffer.cast(), length.into());
-        Ok(ret.try_into().map_err(|_| -> isize { ret as isize })?)
+        ret.try_into().map_err(|_| -> isize { ret as isize })
     })
 }
 
@@ -378,6 +445,6 @@ pub(crate) unsafe fn writei<T>(
             return Ok(0);
         };
         let ret = (alsa.snd_pcm_writei)(pcm, buffer.cast(), length as _);
-        Ok(ret.try_into().map_err(|_| -> isize { ret as isize })?)
+        ret.try_into().map_err(|_| -> isize { ret as isize })
     })
 }
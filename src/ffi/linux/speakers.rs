@@ -12,10 +12,8 @@
 use std::{
     fmt::{Display, Error, Formatter},
     future::Future,
-    marker::PhantomData,
-    os::raw::c_void,
     pin::Pin,
-    sync::atomic::{AtomicBool, Ordering::SeqCst},
+    sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
@@ -26,23 +24,18 @@ use fon::{
 };
 
 use super::{
-    asound, pcm_hw_params, AudioDevice, SndPcmState, SndPcmStream, SoundDevice,
-    DEFAULT,
+    asound, channel_positions, pcm_hw_params, AudioDevice, SndPcmState,
+    SndPcmStream, SoundDevice, VolumeControl, DEFAULT, FLOAT,
 };
+use crate::{frames::frames_mut, ChannelPosition, StreamConfig};
 
-struct SpeakersInner {
-    /// ALSA PCM type for both speakers and microphones.
-    device: AudioDevice,
-    /// Index into audio frames to start writing.
-    starti: usize,
-    /// Raw buffer of audio yet to be played.
+/// The period most recently filled by a sink, waiting to be played.
+struct Period {
+    /// Interleaved audio for the period (empty once copied to the speakers,
+    /// and lent to the sink while it's filled).
     buffer: Vec<Ch32>,
     /// Resampler context for speakers sink.
     resampler: ([Ch32; 6], f64),
-    /// The number of frames in the buffer.
-    period: u16,
-    /// Speakers are locked
-    locked: AtomicBool,
 }
 
 /// ALSA Speakers connection.
@@ -51,43 +44,25 @@ pub(crate) struct Speakers {
     pub(crate) channels: u8,
     /// The sample rate of the speakers.
     pub(crate) sample_rate: Option<f64>,
-    /// Leaked shared box
-    inner: *mut SpeakersInner,
-}
-
-impl Drop for Speakers {
-    fn drop(&mut self) {
-        // Safety
-        if unsafe { (*self.inner).locked.load(SeqCst) } {
-            eprintln!("Speakers dropped before dropping sink");
-            std::process::exit(1);
-        }
-
-        unsafe { drop(Box::from_raw(self.inner)) };
-    }
+    /// ALSA PCM type for both speakers and microphones.
+    device: AudioDevice,
+    /// Index into audio frames to start writing.
+    starti: usize,
+    /// Raw buffer of audio yet to be played.
+    buffer: Vec<Ch32>,
+    /// The number of frames in the buffer.
+    period: u16,
+    /// Period shared with the sink.
+    next: Arc<Mutex<Period>>,
 }
 
 impl SoundDevice for Speakers {
     const INPUT: bool = false;
-
-    fn pcm(&self) -> *mut c_void {
-        unsafe { (*self.inner).device.pcm }
-    }
-
-    fn hwp(&self) -> *mut c_void {
-        unsafe { (*self.inner).device.pcm }
-    }
 }
 
 impl Display for Speakers {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-        // Safety
-        if unsafe { (*self.inner).locked.load(SeqCst) } {
-            eprintln!("Tried to display speakers before dropping sink");
-            std::process::exit(1);
-        }
-
-        unsafe { f.write_str((*self.inner).device.name.as_str()) }
+        f.write_str(self.device.name.as_str())
     }
 }
 
@@ -96,13 +71,13 @@ impl From<AudioDevice> for Speakers {
         Self {
             sample_rate: None,
             channels: 0,
-            inner: Box::leak(Box::new(SpeakersInner {
-                device,
-                starti: 0,
+            device,
+            starti: 0,
+            buffer: Vec::new(),
+            period: 0,
+            next: Arc::new(Mutex::new(Period {
                 buffer: Vec::new(),
                 resampler: ([Ch32::MID; 6], 0.0),
-                period: 0,
-                locked: AtomicBool::new(false),
             })),
         }
     }
@@ -118,29 +93,30 @@ impl Default for Speakers {
             pcm,
             hwp,
             supported,
+            kind: crate::SourceKind::Input,
+            ctl: "default".to_string(),
             fds: Vec::new(),
+            format: FLOAT,
+            exclusive: false,
+            scratch: Vec::new(),
         })
     }
 }
 
 impl Speakers {
     /// Attempt to configure the speaker for a specific number of channels.
-    fn set_channels<F>(&mut self, inner: &mut SpeakersInner) -> Option<bool>
-    where
-        F: Frame<Chan = Ch32>,
-    {
-        if F::CHAN_COUNT != self.channels.into() {
-            if !matches!(F::CHAN_COUNT, 1 | 2 | 6) {
-                panic!("Unknown speaker configuration")
-            }
-            self.channels = F::CHAN_COUNT as u8;
+    fn set_channels(&mut self, channels: u8) -> Option<bool> {
+        if channels != self.channels {
+            self.channels = channels;
             // Configure Hardware Parameters
             pcm_hw_params(
-                &inner.device,
+                &self.device,
                 self.channels,
-                &mut inner.buffer,
+                crate::consts::SAMPLE_RATE.into(),
+                false,
+                &mut self.buffer,
                 &mut self.sample_rate,
-                &mut inner.period,
+                &mut self.period,
             )?;
             Some(true)
         } else {
@@ -153,28 +129,70 @@ impl Speakers {
     where
         F: Frame<Chan = Ch32>,
     {
-        // Always called after ready, so should be safe
-        let inner = unsafe { self.inner.as_mut().unwrap() };
         // Change number of channels, if different than last call.
-        self.set_channels::<F>(inner)
+        if !matches!(F::CHAN_COUNT, 1 | 2 | 6) {
+            panic!("Unknown speaker configuration")
+        }
+        self.set_channels(F::CHAN_COUNT as u8)
             .expect("Speaker::play() called with invalid configuration");
         // Convert the resampler to the target speaker configuration.
+        let mut next = self.next.lock().unwrap();
         let resampler = Resampler::<F>::new(
-            Surround32::from_channels(&inner.resampler.0[..]).convert(),
-            inner.resampler.1,
+            Surround32::from_channels(&next.resampler.0[..]).convert(),
+            next.resampler.1,
+        );
+        // Lend the period buffer to the sink, so it isn't reallocated, sized
+        // for the part of the period that isn't yet filled.
+        let mut buffer = std::mem::take(&mut next.buffer);
+        buffer.clear();
+        buffer.resize(
+            (usize::from(self.period) - self.starti) * F::CHAN_COUNT,
+            Ch32::MID,
         );
-        // Create a sink that borrows this speaker's buffer mutably.
-        SpeakersSink(inner, resampler, PhantomData, self.sample_rate.unwrap())
+        drop(next);
+        SpeakersSink {
+            next: self.next.clone(),
+            buffer,
+            resampler,
+            sample_rate: self.sample_rate.unwrap(),
+        }
+    }
+
+    /// Configure for `channels` interleaved channels (any number the device
+    /// supports), and get the number of frames to write for the period.
+    pub(crate) fn play_raw(&mut self, channels: u8) -> usize {
+        self.set_channels(channels)
+            .expect("Speaker::play_raw() called with invalid configuration");
+        self.period as usize - self.starti
+    }
+
+    /// Queue interleaved audio for the period, after
+    /// [`Speakers::play_raw()`].
+    pub(crate) fn write_raw(&mut self, audio: &[Ch32]) {
+        let mut next = self.next.lock().unwrap();
+        next.buffer.clear();
+        next.buffer.extend_from_slice(audio);
     }
 
     pub(crate) fn channels(&self) -> u8 {
-        // Safety
-        if unsafe { (*self.inner).locked.load(SeqCst) } {
-            eprintln!("Tried to poll speakers before dropping sink");
-            std::process::exit(1);
-        }
+        self.device.supported
+    }
 
-        unsafe { (*self.inner).device.supported }
+    pub(crate) fn channel_positions(&self) -> Vec<ChannelPosition> {
+        channel_positions(&self.device)
+    }
+
+    pub(crate) fn volume_controls(&self) -> Vec<VolumeControl> {
+        VolumeControl::list(&self.device.ctl, false)
+    }
+
+    pub(crate) fn sample_rate(&self) -> Option<f64> {
+        self.sample_rate
+    }
+
+    pub(crate) fn stream_config(&self) -> Option<StreamConfig> {
+        self.device
+            .stream_config(self.sample_rate, self.channels, self.period)
     }
 }
 
@@ -185,24 +203,24 @@ impl Future for Speakers {
         // Get mutable reference to speakers.
         let this = self.get_mut();
 
-        // Safety
-        if unsafe { (*this.inner).locked.load(SeqCst) } {
-            eprintln!("Tried to poll speakers before dropping sink");
-            std::process::exit(1);
-        }
-        //
-        let inner = unsafe { this.inner.as_mut().unwrap() };
-
         // If speaker is unconfigured, return Ready to configure and play.
         if this.channels == 0 {
-            let _ = inner.device.start();
-            inner.locked.store(true, SeqCst);
+            let _ = this.device.start();
             return Poll::Ready(());
         }
 
+        // Copy the period last filled by a sink into the buffer.
+        let mut next = this.next.lock().unwrap();
+        let start = this.starti * this.channels as usize;
+        for (dst, src) in this.buffer[start..].iter_mut().zip(&next.buffer) {
+            *dst = *src;
+        }
+        next.buffer.clear();
+        drop(next);
+
         // Check if not woken, then yield.
         let mut pending = true;
-        for fd in &inner.device.fds {
+        for fd in &this.device.fds {
             if !fd.should_yield() {
                 pending = false;
                 break;
@@ -214,13 +232,8 @@ impl Future for Speakers {
         }
 
         // Attempt to write remaining internal speaker buffer to the speakers.
-        let result = unsafe {
-            asound::pcm::writei(
-                inner.device.pcm,
-                inner.buffer.as_ptr(),
-                inner.period.into(),
-            )
-        };
+        let result =
+            unsafe { this.device.writei(&this.buffer, this.period.into()) };
 
         // Check if it succeeds, then return Ready.
         let len = match result {
@@ -232,25 +245,25 @@ impl Future for Speakers {
                     // page)
                     -11 => {
                         /* Pending */
-                        for fd in &inner.device.fds {
+                        for fd in &this.device.fds {
                             // Register waker, and then return not ready.
                             fd.register_waker(cx.waker());
                         }
                         return Poll::Pending;
                     }
                     -32 => {
-                        match unsafe { asound::pcm::state(inner.device.pcm) } {
+                        match unsafe { asound::pcm::state(this.device.pcm) } {
                             SndPcmState::Xrun => {
                                 // Player samples are not generated fast enough
                                 unsafe {
-                                    asound::pcm::prepare(inner.device.pcm)
+                                    asound::pcm::prepare(this.device.pcm)
                                         .unwrap();
-                                    asound::pcm::writei(
-                                        inner.device.pcm,
-                                        inner.buffer.as_ptr(),
-                                        inner.period.into(),
-                                    )
-                                    .unwrap()
+                                    this.device
+                                        .writei(
+                                            &this.buffer,
+                                            this.period.into(),
+                                        )
+                                        .unwrap()
                                 }
                             }
                             st => {
@@ -279,15 +292,12 @@ impl Future for Speakers {
                         // Prepare, so we keep getting samples.
                         unsafe {
                             // Whether this works or not, we want to prepare.
-                            let _ = asound::pcm::resume(inner.device.pcm);
+                            let _ = asound::pcm::resume(this.device.pcm);
                             // Prepare
-                            asound::pcm::prepare(inner.device.pcm).unwrap();
-                            asound::pcm::writei(
-                                inner.device.pcm,
-                                inner.buffer.as_ptr(),
-                                inner.period.into(),
-                            )
-                            .unwrap()
+                            asound::pcm::prepare(this.device.pcm).unwrap();
+                            this.device
+                                .writei(&this.buffer, this.period.into())
+                                .unwrap()
                         }
                     }
                     _ => unreachable!(),
@@ -296,60 +306,49 @@ impl Future for Speakers {
         };
 
         // Shift buffer.
-        inner.buffer.drain(..len * this.channels as usize);
-        inner.starti = inner.buffer.len() / this.channels as usize;
-        inner
-            .buffer
-            .resize(inner.period as usize * this.channels as usize, Ch32::MID);
+        this.buffer.drain(..len * this.channels as usize);
+        this.starti = this.buffer.len() / this.channels as usize;
+        this.buffer
+            .resize(this.period as usize * this.channels as usize, Ch32::MID);
         // Ready for more samples.
-        inner.locked.store(true, SeqCst);
         Poll::Ready(())
     }
 }
 
-pub(crate) struct SpeakersSink<F: Frame<Chan = Ch32>>(
-    *mut SpeakersInner,
-    Resampler<F>,
-    PhantomData<F>,
-    f64,
-);
+pub(crate) struct SpeakersSink<F: Frame<Chan = Ch32>> {
+    /// Where the period is sent once the sink is dropped.
+    next: Arc<Mutex<Period>>,
+    /// Interleaved audio for the period, lent by the speakers.
+    buffer: Vec<Ch32>,
+    /// Resampler context.
+    resampler: Resampler<F>,
+    /// Sample rate of the speakers.
+    sample_rate: f64,
+}
 
 impl<F: Frame<Chan = Ch32>> Sink<F> for SpeakersSink<F> {
     fn sample_rate(&self) -> f64 {
-        self.3
+        self.sample_rate
     }
 
     fn resampler(&mut self) -> &mut Resampler<F> {
-        &mut self.1
+        &mut self.resampler
     }
 
     fn buffer(&mut self) -> &mut [F] {
-        let speakers = unsafe { self.0.as_mut().unwrap() };
-        let data = speakers.buffer.as_mut_ptr().cast();
-        let count = speakers.period.into();
-        unsafe {
-            &mut std::slice::from_raw_parts_mut(data, count)[speakers.starti..]
-        }
+        frames_mut(&mut self.buffer)
     }
 }
 
 impl<F: Frame<Chan = Ch32>> Drop for SpeakersSink<F> {
     fn drop(&mut self) {
-        //
-        let speakers = unsafe { self.0.as_mut().unwrap() };
+        let mut next = self.next.lock().unwrap();
+        // Return the period buffer to the speakers.
+        next.buffer = std::mem::take(&mut self.buffer);
         // Store 5.1 surround sample to resampler.
-        let frame: Surround32 = self.1.frame().convert();
-        speakers.resampler.0 = [
-            frame.channels()[0],
-            frame.channels()[1],
-            frame.channels()[2],
-            frame.channels()[3],
-            frame.channels()[4],
-            frame.channels()[5],
-        ];
+        let frame: Surround32 = self.resampler.frame().convert();
+        next.resampler.0.copy_from_slice(frame.channels());
         // Store partial index from resampler.
-        speakers.resampler.1 = self.1.index() % 1.0;
-        // Unlock
-        speakers.locked.store(false, SeqCst);
+        next.resampler.1 = self.resampler.index() % 1.0;
     }
 }
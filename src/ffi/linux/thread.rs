@@ -0,0 +1,41 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+#![allow(unsafe_code)]
+
+use std::os::raw::{c_int, c_ulong};
+
+/// Real-time first-in first-out scheduling policy.
+const SCHED_FIFO: c_int = 1;
+
+/// Priority requested for audio threads (same as PulseAudio's default).
+const PRIORITY: c_int = 5;
+
+#[repr(C)]
+struct SchedParam {
+    sched_priority: c_int,
+}
+
+extern "C" {
+    fn pthread_self() -> c_ulong;
+    fn pthread_setschedparam(
+        thread: c_ulong,
+        policy: c_int,
+        param: *const SchedParam,
+    ) -> c_int;
+}
+
+/// Try to schedule the current thread with real-time priority, returning
+/// whether it worked (it needs `CAP_SYS_NICE` or an `RLIMIT_RTPRIO` limit).
+pub(crate) fn set_realtime_priority() -> bool {
+    let param = SchedParam {
+        sched_priority: PRIORITY,
+    };
+    unsafe { pthread_setschedparam(pthread_self(), SCHED_FIFO, &param) == 0 }
+}
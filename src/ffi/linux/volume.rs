@@ -0,0 +1,269 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+#![allow(unsafe_code)]
+
+use std::{
+    ffi::CString,
+    io::{Error, ErrorKind, Result},
+    os::raw::{c_long, c_void},
+    task::{Context, Poll},
+};
+
+use super::asound::mixer;
+
+/// Convert an ALSA error code to an I/O error.
+fn error(code: i64) -> Error {
+    match i32::try_from(-code) {
+        Ok(errno) if errno > 0 => Error::from_raw_os_error(errno),
+        _ => Error::other("ALSA is not available"),
+    }
+}
+
+/// Error for an element that doesn't have a volume or switch.
+fn unsupported() -> Error {
+    ErrorKind::Unsupported.into()
+}
+
+/// A simple mixer element of an ALSA control device.
+pub(crate) struct VolumeControl {
+    /// Name of the element (with its index, if not 0).
+    name: String,
+    /// Whether this controls capture rather than playback.
+    capture: bool,
+    /// Whether the element has a volume.
+    has_volume: bool,
+    /// Whether the element has a mute switch.
+    has_switch: bool,
+    /// Mixer handle, owned by this control.
+    mixer: *mut c_void,
+    /// The element, owned by the mixer handle.
+    elem: *mut c_void,
+    /// File descriptors of the mixer, to wait for changes.
+    fds: Vec<smelling_salts::Device>,
+    /// Raw volume and switch last produced by the notifier.
+    last: (Option<c_long>, Option<bool>),
+}
+
+// The mixer handle is owned exclusively by the control, and ALSA allows a
+// handle to be used from any thread as long as it isn't used from two at once.
+unsafe impl Send for VolumeControl {}
+
+impl std::fmt::Debug for VolumeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl Drop for VolumeControl {
+    fn drop(&mut self) {
+        // Unregister async file descriptors before closing the mixer.
+        for fd in &mut self.fds {
+            fd.old();
+        }
+        unsafe {
+            let _ = mixer::close(self.mixer);
+        }
+    }
+}
+
+impl VolumeControl {
+    /// Open the elements of the control device `ctl` that have a volume or
+    /// mute switch for capture or playback.
+    pub(crate) fn list(ctl: &str, capture: bool) -> Vec<Self> {
+        let ctl = match CString::new(ctl) {
+            Ok(ctl) => ctl,
+            Err(_) => return Vec::new(),
+        };
+        let names = unsafe {
+            let handle = match mixer::open(ctl.as_ptr()) {
+                Ok(handle) => handle,
+                Err(_) => return Vec::new(),
+            };
+            let names: Vec<_> = mixer::elements(handle)
+                .into_iter()
+                .filter(|&elem| {
+                    let (volume, switch) =
+                        mixer::has_volume_switch(elem, capture);
+                    volume || switch
+                })
+                .filter_map(|elem| mixer::name(elem).ok())
+                .collect();
+            let _ = mixer::close(handle);
+            names
+        };
+        // Each control gets its own handle, so it can wait for changes.
+        names
+            .into_iter()
+            .filter_map(|(name, index)| Self::open(&ctl, name, index, capture))
+            .collect()
+    }
+
+    /// Open element `name` with `index` on a new mixer handle.
+    fn open(
+        ctl: &CString,
+        name: String,
+        index: u32,
+        capture: bool,
+    ) -> Option<Self> {
+        unsafe {
+            let handle = mixer::open(ctl.as_ptr()).ok()?;
+            let elem = mixer::elements(handle).into_iter().find(|&elem| {
+                mixer::name(elem).ok().as_ref() == Some(&(name.clone(), index))
+            });
+            let elem = match elem {
+                Some(elem) => elem,
+                None => {
+                    let _ = mixer::close(handle);
+                    return None;
+                }
+            };
+            let (has_volume, has_switch) =
+                mixer::has_volume_switch(elem, capture);
+            let fds = mixer::poll_descriptors(handle)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|fd| {
+                    smelling_salts::Device::new(
+                        fd.fd,
+                        smelling_salts::Watcher::from_raw(fd.events as u32),
+                    )
+                })
+                .collect();
+            let mut control = Self {
+                name: if index == 0 {
+                    name
+                } else {
+                    format!("{} {}", name, index)
+                },
+                capture,
+                has_volume,
+                has_switch,
+                mixer: handle,
+                elem,
+                fds,
+                last: (None, None),
+            };
+            control.last = control.state();
+            Some(control)
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn db_range(&self) -> Option<(f32, f32)> {
+        if !self.has_volume {
+            return None;
+        }
+        let (min, max) =
+            unsafe { mixer::db_range(self.elem, self.capture).ok()? };
+        Some((min as f32 / 100.0, max as f32 / 100.0))
+    }
+
+    pub(crate) fn volume_db(&self) -> Option<f32> {
+        if !self.has_volume {
+            return None;
+        }
+        self.refresh();
+        let db = unsafe { mixer::db(self.elem, self.capture).ok()? };
+        Some(db as f32 / 100.0)
+    }
+
+    pub(crate) fn set_volume_db(&mut self, db: f32) -> Result<()> {
+        if !self.has_volume {
+            return Err(unsupported());
+        }
+        let value = (db * 100.0).round() as c_long;
+        unsafe { mixer::set_db(self.elem, self.capture, value) }.map_err(error)
+    }
+
+    pub(crate) fn volume_percent(&self) -> Option<f32> {
+        if !self.has_volume {
+            return None;
+        }
+        self.refresh();
+        unsafe {
+            let (min, max) =
+                mixer::volume_range(self.elem, self.capture).ok()?;
+            let value = mixer::volume(self.elem, self.capture).ok()?;
+            if max <= min {
+                return None;
+            }
+            Some((value - min) as f32 * 100.0 / (max - min) as f32)
+        }
+    }
+
+    pub(crate) fn set_volume_percent(&mut self, percent: f32) -> Result<()> {
+        if !self.has_volume {
+            return Err(unsupported());
+        }
+        unsafe {
+            let (min, max) =
+                mixer::volume_range(self.elem, self.capture).map_err(error)?;
+            let range = (max - min) as f32;
+            let percent = percent.clamp(0.0, 100.0);
+            let value = min + (percent * range / 100.0).round() as c_long;
+            mixer::set_volume(self.elem, self.capture, value).map_err(error)
+        }
+    }
+
+    pub(crate) fn is_muted(&self) -> Option<bool> {
+        if !self.has_switch {
+            return None;
+        }
+        self.refresh();
+        unsafe { mixer::switch(self.elem, self.capture).ok().map(|on| !on) }
+    }
+
+    pub(crate) fn set_muted(&mut self, muted: bool) -> Result<()> {
+        if !self.has_switch {
+            return Err(unsupported());
+        }
+        unsafe { mixer::set_switch(self.elem, self.capture, !muted) }
+            .map_err(error)
+    }
+
+    /// Wait for the volume or mute switch to be changed (by this or another
+    /// program).
+    pub(crate) fn poll_change(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        for register in [false, true] {
+            let state = self.state();
+            if state != self.last {
+                self.last = state;
+                return Poll::Ready(());
+            }
+            if !register {
+                for fd in &self.fds {
+                    fd.register_waker(cx.waker());
+                }
+            }
+        }
+        Poll::Pending
+    }
+
+    /// Get the raw volume and switch, after processing pending events.
+    fn state(&self) -> (Option<c_long>, Option<bool>) {
+        self.refresh();
+        unsafe {
+            (
+                mixer::volume(self.elem, self.capture).ok(),
+                mixer::switch(self.elem, self.capture).ok(),
+            )
+        }
+    }
+
+    /// Process pending events, so the element's values are up to date.
+    fn refresh(&self) {
+        unsafe {
+            let _ = mixer::handle_events(self.mixer);
+        }
+    }
+}
@@ -15,7 +15,13 @@ pub(crate) trait SoundDevice: Display {
 
 /// Return a list of available audio devices.
 pub(crate) fn device_list<D: SoundDevice, F: Fn(D) -> T, T>(
+    _exclusive: bool,
     _abstrakt: F,
 ) -> Vec<T> {
     vec![]
 }
+
+/// Return the microphone that records the system's output, if any.
+pub(crate) fn system_output<D: SoundDevice>() -> Option<D> {
+    None
+}
@@ -49,6 +49,29 @@ impl Speakers {
     pub(crate) fn channels(&self) -> u8 {
         1
     }
+
+    pub(crate) fn play_raw(&mut self, _channels: u8) -> usize {
+        // Routing channels isn't supported, so there's nothing to write.
+        0
+    }
+
+    pub(crate) fn write_raw(&mut self, _audio: &[Ch32]) {}
+
+    pub(crate) fn channel_positions(&self) -> Vec<crate::ChannelPosition> {
+        Vec::new()
+    }
+
+    pub(crate) fn stream_config(&self) -> Option<crate::StreamConfig> {
+        None
+    }
+
+    pub(crate) fn volume_controls(&self) -> Vec<super::VolumeControl> {
+        Vec::new()
+    }
+
+    pub(crate) fn sample_rate(&self) -> Option<f64> {
+        self.sample_rate
+    }
 }
 
 impl Future for Speakers {
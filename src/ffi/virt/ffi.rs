@@ -0,0 +1,44 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Virtual devices, available on every platform and selected at runtime.
+
+mod loopback;
+mod microphone;
+mod speakers;
+mod timer;
+
+use fon::{
+    chan::Ch32, mono::Mono32, stereo::Stereo32, surround::Surround32, Frame,
+};
+pub(crate) use loopback::Link;
+use loopback::LinkReader;
+pub(crate) use microphone::{Microphone, MicrophoneStream};
+pub(crate) use speakers::{Speakers, SpeakersSink};
+use timer::Timer;
+
+/// Read an interleaved frame of 1, 2 or 6 channels.
+pub(crate) fn read_frame(input: &[Ch32]) -> Surround32 {
+    match input.len() {
+        1 => Mono32::from_channels(input).convert(),
+        2 => Stereo32::from_channels(input).convert(),
+        6 => Surround32::from_channels(input),
+        _ => unreachable!(),
+    }
+}
+
+/// Write an interleaved frame of 1, 2 or 6 channels.
+pub(crate) fn write_frame(frame: Surround32, output: &mut [Ch32]) {
+    match output.len() {
+        1 => output.copy_from_slice(frame.convert::<Mono32>().channels()),
+        2 => output.copy_from_slice(frame.convert::<Stereo32>().channels()),
+        6 => output.copy_from_slice(frame.channels()),
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+use fon::{chan::Ch32, surround::Surround32, Frame};
+
+use crate::Loopback;
+
+/// Loopback links that are still in use, so they can be queried.
+static LINKS: Mutex<Vec<Weak<Link>>> = Mutex::new(Vec::new());
+
+/// Seed for noise and dropout injection, fixed so runs are reproducible.
+const SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+/// Most periods kept beyond the latency, if the microphone doesn't keep up
+/// (or isn't recording), before the oldest audio is dropped.
+const MAX_PERIODS: usize = 4;
+
+/// Audio played into a link.
+struct Played {
+    /// Audio played, and not yet recorded.
+    fifo: VecDeque<Surround32>,
+    /// Echoes of audio played so far, still to be added to the frames played
+    /// next.
+    tail: VecDeque<[f32; 6]>,
+    /// Whether the speakers started (until they do, the latency hasn't, so
+    /// the microphone records silence without using it up).
+    started: bool,
+}
+
+/// Connection between loopback speakers and microphones.
+pub(crate) struct Link {
+    /// Configuration of the pair.
+    pub(crate) config: Loopback,
+    /// Audio played, and not yet recorded.
+    played: Mutex<Played>,
+    /// Whether a microphone records the link (there's only one, so they
+    /// don't split the audio between them).
+    reading: AtomicBool,
+}
+
+impl Link {
+    /// Create and register a new link.
+    pub(crate) fn new(config: Loopback) -> Arc<Self> {
+        let fifo = vec![Surround32::default(); config.latency as usize];
+        let tail = vec![[0.0; 6]; config.impulse_response.len()];
+        let link = Arc::new(Self {
+            config,
+            played: Mutex::new(Played {
+                fifo: fifo.into(),
+                tail: tail.into(),
+                started: false,
+            }),
+            reading: AtomicBool::new(false),
+        });
+        LINKS.lock().unwrap().push(Arc::downgrade(&link));
+        link
+    }
+
+    /// Get all links that are still in use.
+    pub(crate) fn query() -> Vec<Arc<Self>> {
+        let mut links = LINKS.lock().unwrap();
+        links.retain(|link| link.strong_count() != 0);
+        links.iter().filter_map(Weak::upgrade).collect()
+    }
+
+    /// Start the latency, once the speakers are configured.
+    pub(crate) fn start(&self) {
+        self.played.lock().unwrap().started = true;
+    }
+
+    /// Play interleaved audio with `channels` channels into the link.
+    pub(crate) fn play(&self, audio: &[Ch32], channels: u8) {
+        let mut played = self.played.lock().unwrap();
+        let frames = audio.chunks(channels.into()).map(super::read_frame);
+        let response = &self.config.impulse_response;
+        if response.is_empty() {
+            played.fifo.extend(frames);
+        } else {
+            convolve(&mut played, frames, response);
+        }
+        let max = self.config.latency as usize
+            + MAX_PERIODS * usize::from(crate::consts::PERIOD);
+        let excess = played.fifo.len().saturating_sub(max);
+        played.fifo.drain(..excess);
+    }
+}
+
+/// Add frames to the FIFO, with their echoes through an impulse response.
+fn convolve(
+    played: &mut Played,
+    frames: impl Iterator<Item = Surround32>,
+    response: &[f32],
+) {
+    let Played { fifo, tail, .. } = played;
+    for frame in frames {
+        // Add the echoes of the frame to the frames it's heard in.
+        for (echo, gain) in tail.iter_mut().zip(response) {
+            for (echo, chan) in echo.iter_mut().zip(frame.channels()) {
+                *echo += f32::from(*chan) * gain;
+            }
+        }
+        let heard = tail.pop_front().unwrap_or_default();
+        tail.push_back([0.0; 6]);
+        fifo.push_back(Surround32::from_channels(&heard.map(Ch32::new)));
+    }
+}
+
+/// Records the audio played into a link.
+pub(crate) struct LinkReader {
+    /// The link to read from.
+    link: Arc<Link>,
+    /// State of the noise and dropout random number generator.
+    random: u64,
+    /// Frames left in the current period.
+    left: u16,
+    /// Whether the current period has dropped out.
+    dropped: bool,
+}
+
+impl LinkReader {
+    /// Create the reader of a link, or `None` if it already has one.
+    pub(crate) fn new(link: Arc<Link>) -> Option<Self> {
+        if link.reading.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+        Some(Self {
+            link,
+            random: SEED,
+            left: 0,
+            dropped: false,
+        })
+    }
+
+    /// Generate a random number from 0 to 1 (xorshift).
+    fn random(&mut self) -> f32 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        (self.random >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+impl Drop for LinkReader {
+    fn drop(&mut self) {
+        self.link.reading.store(false, Ordering::Release);
+    }
+}
+
+impl Iterator for LinkReader {
+    type Item = Surround32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let config = &self.link.config;
+        let (noise, dropout) = (config.noise, config.dropout);
+        if self.left == 0 {
+            self.left = crate::consts::PERIOD;
+            self.dropped = dropout > 0.0 && self.random() < dropout;
+        }
+        self.left -= 1;
+
+        // Underruns are recorded as silence.
+        let mut played = self.link.played.lock().unwrap();
+        let frame = played.started.then(|| played.fifo.pop_front()).flatten();
+        drop(played);
+        let mut frame = match frame {
+            Some(frame) if !self.dropped => frame,
+            _ => Surround32::default(),
+        };
+        if noise > 0.0 {
+            for chan in frame.channels_mut() {
+                let noise = (self.random() * 2.0 - 1.0) * noise;
+                *chan = Ch32::new(f32::from(*chan) + noise);
+            }
+        }
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::PERIOD;
+
+    fn link(latency: u32, impulse_response: Vec<f32>) -> Arc<Link> {
+        Link::new(Loopback {
+            latency,
+            impulse_response,
+            ..Loopback::default()
+        })
+    }
+
+    /// Play `audio` as mono into `link`.
+    fn play(link: &Link, audio: impl IntoIterator<Item = f32>) {
+        let audio: Vec<Ch32> = audio.into_iter().map(Ch32::new).collect();
+        link.play(&audio, 1);
+    }
+
+    /// Record `frames` frames from `reader` as mono.
+    fn record(reader: &mut LinkReader, frames: usize) -> Vec<f32> {
+        let mut mono = [Ch32::default()];
+        reader
+            .take(frames)
+            .map(|frame| {
+                super::super::write_frame(frame, &mut mono);
+                f32::from(mono[0])
+            })
+            .collect()
+    }
+
+    fn fifo_len(link: &Link) -> usize {
+        link.played.lock().unwrap().fifo.len()
+    }
+
+    #[test]
+    fn latency() {
+        let link = link(100, Vec::new());
+        let mut reader = LinkReader::new(link.clone()).unwrap();
+        link.start();
+        play(&link, [0.5; 50]);
+        let recorded = record(&mut reader, 200);
+        assert!(recorded[..100].iter().all(|s| *s == 0.0));
+        assert!(recorded[100..150].iter().all(|s| *s == 0.5));
+        // Underruns are silence.
+        assert!(recorded[150..].iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn silence_until_started() {
+        let link = link(100, Vec::new());
+        let mut reader = LinkReader::new(link.clone()).unwrap();
+        assert!(record(&mut reader, 300).iter().all(|s| *s == 0.0));
+        assert_eq!(fifo_len(&link), 100);
+        link.start();
+        play(&link, [0.5; 10]);
+        let recorded = record(&mut reader, 110);
+        assert_eq!(recorded[99..101], [0.0, 0.5]);
+    }
+
+    #[test]
+    fn fifo_bounds() {
+        let link = link(100, Vec::new());
+        let max = 100 + MAX_PERIODS * usize::from(PERIOD);
+        play(&link, (0..max - 100).map(|i| i as f32 / 1024.0));
+        assert_eq!(fifo_len(&link), max);
+        // Going over drops the oldest frames (the latency's silence first).
+        play(&link, (max - 100..max + 200).map(|i| i as f32 / 1024.0));
+        assert_eq!(fifo_len(&link), max);
+        let mut reader = LinkReader::new(link.clone()).unwrap();
+        link.start();
+        let recorded = record(&mut reader, max);
+        let expected: Vec<f32> =
+            (200..max + 200).map(|i| i as f32 / 1024.0).collect();
+        assert_eq!(recorded, expected);
+        assert_eq!(fifo_len(&link), 0);
+    }
+
+    #[test]
+    fn one_reader() {
+        let link = link(0, Vec::new());
+        let reader = LinkReader::new(link.clone()).unwrap();
+        assert!(LinkReader::new(link.clone()).is_none());
+        drop(reader);
+        assert!(LinkReader::new(link).is_some());
+    }
+
+    #[test]
+    fn impulse_response() {
+        let link = link(0, vec![0.5, 0.0, 0.25]);
+        let mut reader = LinkReader::new(link.clone()).unwrap();
+        link.start();
+        // The echoes of a period carry over into the next.
+        play(&link, [0.0, 0.0, 1.0]);
+        play(&link, [0.0, 1.0, 0.0, 0.0]);
+        let recorded = record(&mut reader, 7);
+        assert_eq!(recorded, [0.0, 0.0, 0.5, 0.0, 0.75, 0.0, 0.25]);
+    }
+}
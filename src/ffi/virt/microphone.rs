@@ -0,0 +1,226 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    fmt::{Display, Error, Formatter},
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use fon::{
+    chan::{Ch32, Channel},
+    surround::Surround32,
+    Frame, Stream,
+};
+
+use super::{write_frame, Link, LinkReader, Timer};
+use crate::{wav::WavReader, Clock, SampleFormat, SourceKind, StreamConfig};
+
+/// Virtual microphone, which records a user-supplied signal, a file or a
+/// loopback.
+pub(crate) struct Microphone {
+    /// Name of the device.
+    name: String,
+    /// What the microphone records.
+    kind: SourceKind,
+    /// Paces the periods.
+    timer: Timer,
+    /// The sample rate of the microphone.
+    sample_rate: f64,
+    /// Number of configured channels (0 if not yet configured).
+    channels: u8,
+    /// The number of frames in a period.
+    period: u16,
+    /// The signal to record, silence once it ends.
+    signal: Box<dyn Iterator<Item = Surround32> + Send>,
+    /// Interleaved audio recorded for the current period (empty while lent
+    /// to a stream).
+    buffer: Vec<Ch32>,
+    /// Where a stream returns the buffer when dropped.
+    returned: Arc<Mutex<Vec<Ch32>>>,
+}
+
+impl Display for Microphone {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        f.write_str(&self.name)
+    }
+}
+
+impl Microphone {
+    /// Create a new virtual microphone.
+    pub(crate) fn new<F, S>(clock: Clock, sample_rate: u32, signal: S) -> Self
+    where
+        F: Frame<Chan = Ch32>,
+        S: IntoIterator<Item = F>,
+        S::IntoIter: Send + 'static,
+    {
+        Self {
+            name: "Null".to_string(),
+            kind: SourceKind::Input,
+            timer: Timer::new(clock),
+            sample_rate: sample_rate.into(),
+            channels: 0,
+            period: crate::consts::PERIOD,
+            signal: Box::new(signal.into_iter().map(Frame::convert)),
+            buffer: Vec::new(),
+            returned: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Create a new virtual microphone, which records a WAV file.
+    pub(crate) fn wav(clock: Clock, name: String, reader: WavReader) -> Self {
+        let sample_rate = reader.sample_rate();
+        Self {
+            name: format!("WAV: {}", name),
+            ..Self::new(clock, sample_rate, reader)
+        }
+    }
+
+    /// Create a new virtual microphone, which records loopback speakers, or
+    /// `None` if another microphone already records them.
+    pub(crate) fn loopback(link: Arc<Link>) -> Option<Self> {
+        let (clock, sample_rate) = (link.config.clock, link.config.sample_rate);
+        Some(Self {
+            name: "Loopback".to_string(),
+            kind: SourceKind::Loopback,
+            ..Self::new(clock, sample_rate, LinkReader::new(link)?)
+        })
+    }
+
+    /// Configure the microphone for a specific number of channels.
+    fn set_channels<F>(&mut self)
+    where
+        F: Frame<Chan = Ch32>,
+    {
+        if F::CHAN_COUNT == self.channels.into() {
+            return;
+        }
+        if !matches!(F::CHAN_COUNT, 1 | 2 | 6) {
+            panic!("Unknown microphone configuration")
+        }
+        self.channels = F::CHAN_COUNT as u8;
+        self.timer.set_period(self.period, self.sample_rate);
+        self.buffer.clear();
+    }
+
+    pub(crate) fn record<F: Frame<Chan = Ch32>>(
+        &mut self,
+    ) -> MicrophoneStream<F> {
+        self.set_channels::<F>();
+        MicrophoneStream {
+            buffer: std::mem::take(&mut self.buffer),
+            returned: self.returned.clone(),
+            index: 0,
+            sample_rate: self.sample_rate,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub(crate) fn channels(&self) -> u8 {
+        0b0010_0011
+    }
+
+    pub(crate) fn sample_rate(&self) -> Option<f64> {
+        Some(self.sample_rate)
+    }
+
+    /// Nothing converts between virtual devices and what they play to or
+    /// record from.
+    pub(crate) fn stream_config(&self) -> Option<StreamConfig> {
+        (self.channels != 0).then(|| StreamConfig {
+            sample_rate: self.sample_rate,
+            channels: self.channels.into(),
+            period: self.period.into(),
+            latency: 0,
+            format: SampleFormat::F32,
+            native_rate: true,
+            native_channels: true,
+            native_format: true,
+        })
+    }
+
+    pub(crate) fn kind(&self) -> SourceKind {
+        self.kind
+    }
+}
+
+impl Future for Microphone {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        // If microphone is unconfigured, return Ready to configure and record.
+        if this.channels == 0 {
+            return Poll::Ready(());
+        }
+
+        // Wait for the next period.
+        if this.timer.poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        // "Record" the next period of the signal, into the buffer lent to the
+        // last stream (only allocating if that stream is still alive).
+        let channels = this.channels.into();
+        if this.buffer.is_empty() {
+            this.buffer = std::mem::take(&mut *this.returned.lock().unwrap());
+        }
+        this.buffer
+            .resize(this.period as usize * channels, Ch32::MID);
+        for frame in this.buffer.chunks_mut(channels) {
+            write_frame(this.signal.next().unwrap_or_default(), frame);
+        }
+        Poll::Ready(())
+    }
+}
+
+pub(crate) struct MicrophoneStream<F: Frame<Chan = Ch32>> {
+    /// Interleaved audio recorded for the period.
+    buffer: Vec<Ch32>,
+    /// Where the buffer is returned to the microphone.
+    returned: Arc<Mutex<Vec<Ch32>>>,
+    /// Index of the next frame.
+    index: usize,
+    /// Sample rate of the microphone.
+    sample_rate: f64,
+    _phantom: PhantomData<F>,
+}
+
+impl<F: Frame<Chan = Ch32>> Iterator for MicrophoneStream<F> {
+    type Item = F;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.buffer.get(self.index * F::CHAN_COUNT..)?;
+        if frame.is_empty() {
+            return None;
+        }
+        self.index += 1;
+        Some(F::from_channels(frame))
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Stream<F> for MicrophoneStream<F> {
+    fn sample_rate(&self) -> Option<f64> {
+        Some(self.sample_rate)
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(self.buffer.len() / F::CHAN_COUNT)
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Drop for MicrophoneStream<F> {
+    fn drop(&mut self) {
+        *self.returned.lock().unwrap() = std::mem::take(&mut self.buffer);
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    fmt::{Display, Error, Formatter},
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use fon::{
+    chan::{Ch32, Channel},
+    surround::Surround32,
+    Audio, Frame, Resampler, Sink,
+};
+
+use super::{read_frame, write_frame, Link, Timer};
+use crate::{
+    frames::frames_mut, wav::WavWriter, Clock, SampleFormat, StreamConfig,
+};
+
+/// Where virtual speakers play audio to.
+enum Output {
+    /// Interleaved audio played so far.
+    Memory(Vec<Ch32>),
+    /// A WAV file (`None` after a write error).
+    Wav(String, Option<WavWriter>),
+    /// A loopback microphone.
+    Loopback(Arc<Link>),
+}
+
+/// The period most recently filled by a sink, waiting to be played.
+struct Period {
+    /// Interleaved audio for the period.
+    buffer: Vec<Ch32>,
+    /// Whether the period was filled since it was last played.
+    filled: bool,
+    /// Resampler context for speakers sink.
+    resampler: ([Ch32; 6], f64),
+}
+
+/// Virtual speakers, which play into memory, a file or a loopback.
+pub(crate) struct Speakers {
+    /// Paces the periods.
+    timer: Timer,
+    /// The sample rate of the speakers.
+    sample_rate: f64,
+    /// Number of configured channels (0 if not yet configured).
+    channels: u8,
+    /// The number of frames in a period.
+    period: u16,
+    /// Period shared with the sink.
+    next: Arc<Mutex<Period>>,
+    /// Where audio is played to.
+    output: Output,
+    /// The error that stopped writing to a file, if not yet taken.
+    error: Option<io::Error>,
+}
+
+impl Display for Speakers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match &self.output {
+            Output::Memory(_) => f.write_str("Null"),
+            Output::Wav(name, _) => write!(f, "WAV: {}", name),
+            Output::Loopback(_) => f.write_str("Loopback"),
+        }
+    }
+}
+
+impl Speakers {
+    /// Create new virtual speakers, which play into memory.
+    pub(crate) fn new(clock: Clock, sample_rate: u32) -> Self {
+        Self::with_output(clock, sample_rate, Output::Memory(Vec::new()))
+    }
+
+    /// Create new virtual speakers, which play into a WAV file.
+    pub(crate) fn wav(
+        clock: Clock,
+        sample_rate: u32,
+        name: String,
+        writer: WavWriter,
+    ) -> Self {
+        Self::with_output(clock, sample_rate, Output::Wav(name, Some(writer)))
+    }
+
+    /// Create new virtual speakers, which play into a loopback microphone.
+    pub(crate) fn loopback(link: Arc<Link>) -> Self {
+        let (clock, sample_rate) = (link.config.clock, link.config.sample_rate);
+        Self::with_output(clock, sample_rate, Output::Loopback(link))
+    }
+
+    fn with_output(clock: Clock, sample_rate: u32, output: Output) -> Self {
+        Self {
+            timer: Timer::new(clock),
+            sample_rate: sample_rate.into(),
+            channels: 0,
+            period: crate::consts::PERIOD,
+            next: Arc::new(Mutex::new(Period {
+                buffer: Vec::new(),
+                filled: false,
+                resampler: ([Ch32::MID; 6], 0.0),
+            })),
+            output,
+            error: None,
+        }
+    }
+
+    /// Configure the speakers for a specific number of channels.
+    fn set_channels(&mut self, channels: u8) {
+        if channels == self.channels {
+            return;
+        }
+        if !matches!(channels, 1 | 2 | 6) {
+            panic!("Unknown speaker configuration")
+        }
+        // Convert audio already played to the new number of channels.
+        if let (Output::Memory(played), 1..) = (&mut self.output, self.channels)
+        {
+            let mut output = Vec::new();
+            for frame in played.chunks(self.channels.into()) {
+                let start = output.len();
+                output.resize(start + usize::from(channels), Ch32::MID);
+                write_frame(read_frame(frame), &mut output[start..]);
+            }
+            *played = output;
+        }
+        if let Output::Loopback(link) = &self.output {
+            link.start();
+        }
+        self.channels = channels;
+        self.timer.set_period(self.period, self.sample_rate);
+        let mut next = self.next.lock().unwrap();
+        next.buffer.clear();
+        next.buffer.resize(
+            usize::from(self.period) * usize::from(channels),
+            Ch32::MID,
+        );
+    }
+
+    /// Generate an audio sink for the user to fill.
+    pub(crate) fn play<F>(&mut self) -> SpeakersSink<F>
+    where
+        F: Frame<Chan = Ch32>,
+    {
+        self.set_channels(F::CHAN_COUNT as u8);
+        // Lend the period buffer to the sink, so it isn't reallocated.
+        let mut next = self.next.lock().unwrap();
+        let resampler = Resampler::<F>::new(
+            Surround32::from_channels(&next.resampler.0[..]).convert(),
+            next.resampler.1,
+        );
+        let mut buffer = std::mem::take(&mut next.buffer);
+        buffer.clear();
+        buffer.resize(
+            usize::from(self.period) * usize::from(self.channels),
+            Ch32::MID,
+        );
+        drop(next);
+        SpeakersSink {
+            next: self.next.clone(),
+            buffer,
+            resampler,
+            sample_rate: self.sample_rate,
+        }
+    }
+
+    /// Configure for `channels` interleaved channels, and get the number of
+    /// frames to write for the period.
+    pub(crate) fn play_raw(&mut self, channels: u8) -> usize {
+        self.set_channels(channels);
+        self.period.into()
+    }
+
+    /// Queue interleaved audio for the period, after
+    /// [`Speakers::play_raw()`].
+    pub(crate) fn write_raw(&mut self, audio: &[Ch32]) {
+        let mut next = self.next.lock().unwrap();
+        for (dst, src) in next.buffer.iter_mut().zip(audio) {
+            *dst = *src;
+        }
+        next.filled = true;
+    }
+
+    pub(crate) fn channels(&self) -> u8 {
+        0b0010_0011
+    }
+
+    pub(crate) fn sample_rate(&self) -> Option<f64> {
+        Some(self.sample_rate)
+    }
+
+    /// Nothing converts between virtual devices and what they play to or
+    /// record from.
+    pub(crate) fn stream_config(&self) -> Option<StreamConfig> {
+        (self.channels != 0).then(|| StreamConfig {
+            sample_rate: self.sample_rate,
+            channels: self.channels.into(),
+            period: self.period.into(),
+            // Audio is played as soon as the period's polled, and only
+            // delayed on its way to a loopback microphone.
+            latency: match &self.output {
+                Output::Loopback(link) => link.config.latency as usize,
+                Output::Memory(_) | Output::Wav(..) => 0,
+            },
+            format: SampleFormat::F32,
+            native_rate: true,
+            native_channels: true,
+            native_format: true,
+        })
+    }
+
+    /// Update the header of the file played into (if any), so it's valid up
+    /// to the audio played so far.
+    pub(crate) fn sync(&mut self) {
+        if let Output::Wav(_, writer) = &mut self.output {
+            if let Some(Err(error)) = writer.as_mut().map(WavWriter::finalize) {
+                self.error = Some(error);
+                *writer = None;
+            }
+        }
+    }
+
+    /// Take the error that stopped writing to a file, if any.
+    pub(crate) fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Take the audio played so far, if playing into memory.
+    pub(crate) fn take_output<F>(&mut self) -> Option<Audio<F>>
+    where
+        F: Frame<Chan = Ch32>,
+    {
+        let played = match &mut self.output {
+            Output::Memory(played) => played,
+            Output::Wav(..) | Output::Loopback(_) => return None,
+        };
+        let frames: Vec<F> = if self.channels == 0 {
+            Vec::new()
+        } else {
+            played
+                .chunks(self.channels.into())
+                .map(|frame| read_frame(frame).convert())
+                .collect()
+        };
+        played.clear();
+        Some(Audio::with_frames(self.sample_rate, frames))
+    }
+}
+
+impl Future for Speakers {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        // If speaker is unconfigured, return Ready to configure and play.
+        if this.channels == 0 {
+            return Poll::Ready(());
+        }
+
+        // Wait for the next period.
+        if this.timer.poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        // "Play" the period that was last written to the sink.
+        let mut next = this.next.lock().unwrap();
+        let len = usize::from(this.period) * usize::from(this.channels);
+        next.buffer.resize(len, Ch32::MID);
+        match &mut this.output {
+            Output::Memory(played) => played.extend_from_slice(&next.buffer),
+            Output::Wav(_, writer) => {
+                if let Some(Err(error)) = writer
+                    .as_mut()
+                    .map(|writer| writer.write(&next.buffer, this.channels))
+                {
+                    this.error = Some(error);
+                    *writer = None;
+                }
+            }
+            Output::Loopback(link) => link.play(&next.buffer, this.channels),
+        }
+        next.buffer.fill(Ch32::MID);
+        next.filled = false;
+        Poll::Ready(())
+    }
+}
+
+impl Drop for Speakers {
+    fn drop(&mut self) {
+        // The period last filled by a sink is only played once the next one is
+        // ready, so write it before the file is finalized.
+        if let Output::Wav(_, Some(writer)) = &mut self.output {
+            let next = self.next.lock().unwrap();
+            if next.filled {
+                let _ = writer.write(&next.buffer, self.channels);
+            }
+        }
+    }
+}
+
+pub(crate) struct SpeakersSink<F: Frame<Chan = Ch32>> {
+    /// Where the period is sent once the sink is dropped.
+    next: Arc<Mutex<Period>>,
+    /// Interleaved audio for the period, lent by the speakers.
+    buffer: Vec<Ch32>,
+    /// Resampler context.
+    resampler: Resampler<F>,
+    /// Sample rate of the speakers.
+    sample_rate: f64,
+}
+
+impl<F: Frame<Chan = Ch32>> Sink<F> for SpeakersSink<F> {
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn resampler(&mut self) -> &mut Resampler<F> {
+        &mut self.resampler
+    }
+
+    fn buffer(&mut self) -> &mut [F] {
+        frames_mut(&mut self.buffer)
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Drop for SpeakersSink<F> {
+    fn drop(&mut self) {
+        let mut next = self.next.lock().unwrap();
+        // Return the period buffer to the speakers.
+        next.buffer = std::mem::take(&mut self.buffer);
+        next.filled = true;
+        // Store 5.1 surround sample to resampler.
+        let frame: Surround32 = self.resampler.frame().convert();
+        next.resampler.0.copy_from_slice(frame.channels());
+        // Store partial index from resampler.
+        next.resampler.1 = self.resampler.index() % 1.0;
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::Clock;
+
+/// State shared between a timer and its wakeup thread.
+#[derive(Default)]
+struct Shared {
+    /// Waker to wake once the deadline has passed.
+    waker: Option<Waker>,
+    /// When the waker should be woken.
+    deadline: Option<Instant>,
+    /// Set when the timer is dropped, to stop the thread.
+    quit: bool,
+}
+
+/// Paces the periods of a virtual device.
+pub(super) struct Timer {
+    /// How periods are paced.
+    clock: Clock,
+    /// Length of one period.
+    period: Duration,
+    /// When the next period is due.
+    deadline: Option<Instant>,
+    /// Wakeup thread state (real-time clock only).
+    shared: Option<Arc<(Mutex<Shared>, Condvar)>>,
+}
+
+impl Timer {
+    /// Create a new timer; periods aren't paced until the period is set.
+    pub(super) fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            period: Duration::ZERO,
+            deadline: None,
+            shared: None,
+        }
+    }
+
+    /// Set the length of a period to `frames` at `sample_rate`.
+    pub(super) fn set_period(&mut self, frames: u16, sample_rate: f64) {
+        self.period = Duration::from_secs_f64(f64::from(frames) / sample_rate);
+        self.deadline = None;
+    }
+
+    /// Poll for the next period to become ready.
+    pub(super) fn poll(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.clock == Clock::Virtual {
+            return Poll::Ready(());
+        }
+
+        let now = Instant::now();
+        let deadline = *self.deadline.get_or_insert(now);
+        if now >= deadline {
+            // Don't try to catch up on more than a period when starved.
+            self.deadline = Some((deadline + self.period).max(now));
+            return Poll::Ready(());
+        }
+
+        let shared = self.shared.get_or_insert_with(|| {
+            let shared =
+                Arc::new((Mutex::new(Shared::default()), Condvar::new()));
+            let thread_shared = shared.clone();
+            thread::spawn(move || wakeup_thread(thread_shared));
+            shared
+        });
+        let mut state = shared.0.lock().unwrap();
+        state.waker = Some(cx.waker().clone());
+        state.deadline = Some(deadline);
+        shared.1.notify_one();
+        Poll::Pending
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(shared) = &self.shared {
+            shared.0.lock().unwrap().quit = true;
+            shared.1.notify_one();
+        }
+    }
+}
+
+/// Sleep until each requested deadline, then wake the registered waker.
+fn wakeup_thread(shared: Arc<(Mutex<Shared>, Condvar)>) {
+    let (mutex, condvar) = &*shared;
+    let mut state = mutex.lock().unwrap();
+    loop {
+        if state.quit {
+            return;
+        }
+        let deadline = if let Some(deadline) = state.deadline {
+            deadline
+        } else {
+            state = condvar.wait(state).unwrap();
+            continue;
+        };
+        let now = Instant::now();
+        if now < deadline {
+            state = condvar.wait_timeout(state, deadline - now).unwrap().0;
+            continue;
+        }
+        state.deadline = None;
+        if let Some(waker) = state.waker.take() {
+            // Don't hold the lock while waking, the task may poll right away.
+            drop(state);
+            waker.wake();
+            state = mutex.lock().unwrap();
+        }
+    }
+}
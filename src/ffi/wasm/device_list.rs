@@ -15,7 +15,17 @@ pub(crate) trait SoundDevice: Display + Default {
 
 /// Return a list of available audio devices.
 pub(crate) fn device_list<D: SoundDevice, F: Fn(D) -> T, T>(
+    exclusive: bool,
     abstrakt: F,
 ) -> Vec<T> {
+    // The browser always converts audio.
+    if exclusive {
+        return Vec::new();
+    }
     vec![abstrakt(D::default())]
 }
+
+/// Return the microphone that records the system's output, if any.
+pub(crate) fn system_output<D: SoundDevice>() -> Option<D> {
+    None
+}
@@ -144,8 +144,16 @@ fn state() -> &'static mut State {
 mod device_list;
 mod microphone;
 mod speakers;
+#[path = "../dummy/volume.rs"]
+mod volume;
 
-pub(crate) use device_list::device_list;
 use device_list::SoundDevice;
+pub(crate) use device_list::{device_list, system_output};
 pub(super) use microphone::{Microphone, MicrophoneStream};
 pub(super) use speakers::{Speakers, SpeakersSink};
+pub(crate) use volume::VolumeControl;
+
+/// Real-time thread priority isn't supported on this platform.
+pub(crate) fn set_realtime_priority() -> bool {
+    false
+}
@@ -12,7 +12,6 @@ use std::{
     future::Future,
     marker::PhantomData,
     pin::Pin,
-    sync::atomic::{AtomicBool, Ordering::SeqCst},
     task::{Context, Poll},
 };
 
@@ -25,20 +24,7 @@ use web_sys::{
 
 use super::SoundDevice;
 
-pub(crate) struct Microphone(*mut AtomicBool);
-
-#[allow(unsafe_code)]
-impl Drop for Microphone {
-    fn drop(&mut self) {
-        // Safety
-        if unsafe { (*self.0).load(SeqCst) } {
-            eprintln!("Microphone dropped before dropping stream");
-            std::process::exit(1);
-        }
-
-        unsafe { drop(Box::from_raw(self.0)) };
-    }
-}
+pub(crate) struct Microphone;
 
 impl Display for Microphone {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
@@ -92,7 +78,7 @@ impl Default for Microphone {
         let _ = promise.then(&cb);
         cb.forget();
 
-        Self(Box::leak(Box::new(AtomicBool::new(false))))
+        Self
     }
 }
 
@@ -101,7 +87,6 @@ impl Microphone {
         &mut self,
     ) -> MicrophoneStream<F> {
         MicrophoneStream {
-            microphone: self.0,
             index: 0,
             _phantom: PhantomData,
         }
@@ -110,24 +95,43 @@ impl Microphone {
     pub(crate) fn channels(&self) -> u8 {
         0b0000_0001
     }
+
+    pub(crate) fn channel_positions(&self) -> Vec<crate::ChannelPosition> {
+        Vec::new()
+    }
+
+    pub(crate) fn stream_config(&self) -> Option<crate::StreamConfig> {
+        None
+    }
+
+    pub(crate) fn volume_controls(&self) -> Vec<super::VolumeControl> {
+        Vec::new()
+    }
+
+    pub(crate) fn sample_rate(&self) -> Option<f64> {
+        super::state().sample_rate
+    }
+
+    pub(crate) fn set_sample_rate(&mut self, _sample_rate: u32) {
+        // The sample rate can't be chosen, so audio is resampled instead.
+    }
+
+    pub(crate) fn kind(&self) -> crate::SourceKind {
+        crate::SourceKind::Input
+    }
+
+    pub(crate) fn take_error(&mut self) -> Option<std::io::Error> {
+        None
+    }
 }
 
 impl Future for Microphone {
     type Output = ();
 
-    #[allow(unsafe_code)]
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        // Safety
-        if unsafe { (*self.0).load(SeqCst) } {
-            eprintln!("Tried to poll microphone before dropping stream");
-            std::process::exit(1);
-        }
-        let inner = unsafe { self.0.as_mut().unwrap() };
-
         let state = super::state();
         if state.recorded {
             state.recorded = false;
-            inner.store(true, SeqCst);
             Poll::Ready(())
         } else {
             state.mics_waker = Some(cx.waker().clone());
@@ -137,8 +141,6 @@ impl Future for Microphone {
 }
 
 pub(crate) struct MicrophoneStream<F: Frame<Chan = Ch32>> {
-    //
-    microphone: *mut AtomicBool,
     // Index into buffer
     index: usize,
     //
@@ -170,12 +172,3 @@ impl<F: Frame<Chan = Ch32>> Stream<F> for MicrophoneStream<F> {
         Some(super::BUFFER_SIZE.into())
     }
 }
-
-#[allow(unsafe_code)]
-impl<F: Frame<Chan = Ch32>> Drop for MicrophoneStream<F> {
-    fn drop(&mut self) {
-        let mic = unsafe { self.microphone.as_mut().unwrap() };
-        // Unlock
-        mic.store(false, SeqCst);
-    }
-}
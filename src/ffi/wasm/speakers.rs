@@ -11,9 +11,8 @@ use std::{
     any::TypeId,
     fmt::{Display, Error, Formatter},
     future::Future,
-    marker::PhantomData,
     pin::Pin,
-    sync::atomic::{AtomicBool, Ordering::SeqCst},
+    sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
@@ -27,30 +26,9 @@ use fon::{
 
 use super::SoundDevice;
 
-struct SpeakersInner {
-    /// Interleaved buffer (must be de-interleaved for the web).
-    buffer: Vec<f32>,
-    /// State of resampler.
-    resampler: ([Ch32; 6], f64),
-    ///
-    locked: AtomicBool,
-}
-
 pub(crate) struct Speakers {
-    inner: *mut SpeakersInner,
-}
-
-#[allow(unsafe_code)]
-impl Drop for Speakers {
-    fn drop(&mut self) {
-        // Safety
-        if unsafe { (*self.inner).locked.load(SeqCst) } {
-            eprintln!("Speakers dropped before dropping sink");
-            std::process::exit(1);
-        }
-
-        unsafe { drop(Box::from_raw(self.inner)) };
-    }
+    /// State of resampler, shared with the sink.
+    resampler: Arc<Mutex<([Ch32; 6], f64)>>,
 }
 
 impl SoundDevice for Speakers {
@@ -88,59 +66,68 @@ impl Default for Speakers {
             .unwrap();
 
         Self {
-            inner: Box::leak(Box::new(SpeakersInner {
-                buffer: vec![0.0; super::BUFFER_SIZE.into()],
-                resampler: ([Ch32::MID; 6], 0.0),
-                locked: AtomicBool::new(false),
-            })),
+            resampler: Arc::new(Mutex::new(([Ch32::MID; 6], 0.0))),
         }
     }
 }
 
 impl Speakers {
-    #[allow(unsafe_code)]
     pub(crate) fn play<F: Frame<Chan = Ch32>>(&mut self) -> SpeakersSink<F> {
-        // Always called after ready, so should be safe
-        let inner = unsafe { self.inner.as_mut().unwrap() };
-
-        // Adjust buffer size depending on type.
-        if TypeId::of::<F>() == TypeId::of::<Mono32>() {
-            inner.buffer.resize(super::BUFFER_SIZE.into(), 0.0);
-        } else if TypeId::of::<F>() == TypeId::of::<Stereo32>() {
-            inner.buffer.resize(super::BUFFER_SIZE as usize * 2, 0.0);
-        } else {
+        // Check the frame type is supported by the web.
+        if TypeId::of::<F>() != TypeId::of::<Mono32>()
+            && TypeId::of::<F>() != TypeId::of::<Stereo32>()
+        {
             panic!("Attempted to use Speakers with invalid frame type");
         }
         // Convert the resampler to the target speaker configuration.
+        let (frame, index) = *self.resampler.lock().unwrap();
         let resampler = Resampler::<F>::new(
-            Surround32::from_channels(&inner.resampler.0[..]).convert(),
-            inner.resampler.1,
+            Surround32::from_channels(&frame[..]).convert(),
+            index,
         );
         //
-        SpeakersSink(inner, resampler, PhantomData)
+        SpeakersSink {
+            shared: self.resampler.clone(),
+            buffer: vec![F::default(); super::BUFFER_SIZE.into()],
+            resampler,
+        }
     }
 
     pub(crate) fn channels(&self) -> u8 {
         0b0000_0011
     }
+
+    pub(crate) fn play_raw(&mut self, _channels: u8) -> usize {
+        // Routing channels isn't supported, so there's nothing to write.
+        0
+    }
+
+    pub(crate) fn write_raw(&mut self, _audio: &[Ch32]) {}
+
+    pub(crate) fn channel_positions(&self) -> Vec<crate::ChannelPosition> {
+        Vec::new()
+    }
+
+    pub(crate) fn stream_config(&self) -> Option<crate::StreamConfig> {
+        None
+    }
+
+    pub(crate) fn volume_controls(&self) -> Vec<super::VolumeControl> {
+        Vec::new()
+    }
+
+    pub(crate) fn sample_rate(&self) -> Option<f64> {
+        super::state().sample_rate
+    }
 }
 
 impl Future for Speakers {
     type Output = ();
 
-    #[allow(unsafe_code)]
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        // Safety
-        if unsafe { (*self.inner).locked.load(SeqCst) } {
-            eprintln!("Tried to poll speakers before dropping sink");
-            std::process::exit(1);
-        }
-        let inner = unsafe { self.inner.as_mut().unwrap() };
-
         let state = super::state();
         if state.played {
             state.played = false;
-            inner.locked.store(true, SeqCst);
             Poll::Ready(())
         } else {
             state.speaker_waker = Some(cx.waker().clone());
@@ -149,11 +136,14 @@ impl Future for Speakers {
     }
 }
 
-pub(crate) struct SpeakersSink<F: Frame<Chan = Ch32>>(
-    *mut SpeakersInner,
-    Resampler<F>,
-    PhantomData<F>,
-);
+pub(crate) struct SpeakersSink<F: Frame<Chan = Ch32>> {
+    /// Resampler state of the speakers, updated once the sink is dropped.
+    shared: Arc<Mutex<([Ch32; 6], f64)>>,
+    /// Audio frames for the period.
+    buffer: Vec<F>,
+    /// Resampler context.
+    resampler: Resampler<F>,
+}
 
 impl<F: Frame<Chan = Ch32>> Sink<F> for SpeakersSink<F> {
     fn sample_rate(&self) -> f64 {
@@ -161,60 +151,31 @@ impl<F: Frame<Chan = Ch32>> Sink<F> for SpeakersSink<F> {
     }
 
     fn resampler(&mut self) -> &mut Resampler<F> {
-        &mut self.1
+        &mut self.resampler
     }
 
-    #[allow(unsafe_code)]
     fn buffer(&mut self) -> &mut [F] {
-        let speakers = unsafe { self.0.as_mut().unwrap() };
-
-        let data = speakers.buffer.as_mut_ptr().cast();
-        let count = super::BUFFER_SIZE.into();
-        unsafe { &mut std::slice::from_raw_parts_mut(data, count)[..] }
+        &mut self.buffer
     }
 }
 
-#[allow(unsafe_code)]
 impl<F: Frame<Chan = Ch32>> Drop for SpeakersSink<F> {
     fn drop(&mut self) {
-        let speakers = unsafe { self.0.as_mut().unwrap() };
-
-        // De-interleave.
-        if TypeId::of::<F>() == TypeId::of::<Mono32>() {
-            // Grab global state.
-            let state = super::state();
-
-            // Convert to speaker's native type.
-            for (i, sample) in speakers.buffer.iter().cloned().enumerate() {
-                state.l_buffer[i] = sample;
-                state.r_buffer[i] = sample;
-            }
-        } else if TypeId::of::<F>() == TypeId::of::<Stereo32>() {
-            // Grab global state.
-            let state = super::state();
-
-            // Convert to speaker's native type.
-            for (i, sample) in speakers.buffer.chunks(2).enumerate() {
-                state.l_buffer[i] = sample[0];
-                state.r_buffer[i] = sample[1];
-            }
-        } else {
-            unreachable!();
+        // Grab global state.
+        let state = super::state();
+
+        // De-interleave, converting to speaker's native type.
+        for (i, frame) in self.buffer.iter().enumerate() {
+            let channels = frame.channels();
+            state.l_buffer[i] = f32::from(channels[0]);
+            state.r_buffer[i] = f32::from(channels[channels.len() - 1]);
         }
 
         // Store 5.1 surround sample to resampler.
-        let frame: Surround32 = self.1.frame().convert();
-        speakers.resampler.0 = [
-            frame.channels()[0],
-            frame.channels()[1],
-            frame.channels()[2],
-            frame.channels()[3],
-            frame.channels()[4],
-            frame.channels()[5],
-        ];
+        let mut shared = self.shared.lock().unwrap();
+        let frame: Surround32 = self.resampler.frame().convert();
+        shared.0.copy_from_slice(frame.channels());
         // Store partial index from resampler.
-        speakers.resampler.1 = self.1.index() % 1.0;
-        // Unlock
-        speakers.locked.store(false, SeqCst);
+        shared.1 = self.resampler.index() % 1.0;
     }
 }
@@ -0,0 +1,600 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! FLAC encoding.
+//!
+//! Frames use fixed linear predictors (orders 0 to 4) with Rice coded
+//! residuals, which is cheap enough to encode in real time.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Result, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use fon::chan::Ch32;
+
+/// Frames per block (except the last).
+const BLOCK_SIZE: usize = 4096;
+/// Byte offset of the STREAMINFO block's contents.
+const STREAMINFO_OFFSET: u64 = 8;
+
+/// Writes bits, most significant first.
+#[derive(Default)]
+struct BitWriter {
+    /// Complete bytes.
+    bytes: Vec<u8>,
+    /// Bits not yet making up a byte, in the low bits.
+    bits: u64,
+    /// Number of bits in `bits`.
+    count: u32,
+}
+
+impl BitWriter {
+    /// Write the low `count` bits (at most 32) of `value`.
+    fn write(&mut self, count: u32, value: u64) {
+        if count == 0 {
+            return;
+        }
+        self.bits = (self.bits << count) | (value & ((1 << count) - 1));
+        self.count += count;
+        while self.count >= 8 {
+            self.count -= 8;
+            self.bytes.push((self.bits >> self.count) as u8);
+        }
+    }
+
+    /// Write a signed value in `count` bits (two's complement).
+    fn write_signed(&mut self, count: u32, value: i64) {
+        self.write(count, value as u64);
+    }
+
+    /// Write `zeros` zero bits followed by a one.
+    fn write_unary(&mut self, mut zeros: u32) {
+        while zeros >= 32 {
+            self.write(32, 0);
+            zeros -= 32;
+        }
+        self.write(zeros + 1, 1);
+    }
+
+    /// Pad with zero bits to a byte boundary.
+    fn align(&mut self) {
+        if self.count != 0 {
+            self.write(8 - self.count, 0);
+        }
+    }
+}
+
+/// CRC-8 (polynomial 0x07) of frame headers.
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// CRC-16 (polynomial 0x8005) of frames.
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in bytes {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Residual of a fixed predictor of `order` at sample `i` (at least `order`).
+fn residual(samples: &[i32], order: usize, i: usize) -> i64 {
+    let s = |back: usize| i64::from(samples[i - back]);
+    match order {
+        0 => s(0),
+        1 => s(0) - s(1),
+        2 => s(0) - 2 * s(1) + s(2),
+        3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+        _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+    }
+}
+
+/// Best Rice parameter for residuals with a sum of absolute values `sum`, and
+/// the number of bits they'd be coded in.
+fn rice(residuals: &[i64], sum: u64) -> (u32, u64) {
+    let len = residuals.len() as u64;
+    let mean = sum / len.max(1);
+    let guess = (64 - mean.leading_zeros()).min(14);
+    let bits = |k: u32| -> u64 {
+        residuals
+            .iter()
+            .map(|&r| {
+                let zigzag = ((r << 1) ^ (r >> 63)) as u64;
+                u64::from(k) + 1 + (zigzag >> k)
+            })
+            .sum()
+    };
+    // The best parameter is next to the one guessed from the mean.
+    [guess.saturating_sub(1), guess, (guess + 1).min(14)]
+        .into_iter()
+        .map(|k| (k, bits(k)))
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap()
+}
+
+/// Streams interleaved audio into a FLAC file.
+pub(crate) struct FlacWriter {
+    /// The file being written.
+    file: BufWriter<File>,
+    /// Bits per sample (16 or 24).
+    bits: u8,
+    /// Sample rate of the file.
+    sample_rate: u32,
+    /// Number of channels in the file.
+    channels: u8,
+    /// Interleaved samples waiting for a full block.
+    block: Vec<i32>,
+    /// Number of frames written to the file.
+    frames: u64,
+    /// Number of blocks written to the file.
+    blocks: u64,
+    /// Smallest and largest encoded frame in bytes.
+    frame_sizes: (u32, u32),
+    /// Number of bytes written.
+    size: u64,
+    /// Encoding buffer.
+    bytes: BitWriter,
+}
+
+impl FlacWriter {
+    /// Create a new FLAC file with `bits` (16 or 24) bits per sample, and
+    /// write the header.
+    pub(crate) fn create(
+        path: &Path,
+        bits: u8,
+        sample_rate: u32,
+        channels: u8,
+    ) -> Result<Self> {
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+            bits,
+            sample_rate,
+            channels,
+            block: Vec::with_capacity(BLOCK_SIZE * usize::from(channels)),
+            frames: 0,
+            blocks: 0,
+            frame_sizes: (0, 0),
+            size: 0,
+            bytes: BitWriter::default(),
+        };
+        let streaminfo = writer.streaminfo();
+        writer.file.write_all(b"fLaC")?;
+        // Last metadata block, STREAMINFO, 34 bytes.
+        writer.file.write_all(&[0x80, 0, 0, 34])?;
+        writer.file.write_all(&streaminfo)?;
+        writer.size = 8 + 34;
+        Ok(writer)
+    }
+
+    /// Get the number of bytes written to the file.
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Write interleaved audio with the file's number of channels.
+    pub(crate) fn write(&mut self, audio: &[Ch32]) -> Result<()> {
+        let scale = ((1 << (self.bits - 1)) - 1) as f32;
+        for sample in audio {
+            let sample = f32::from(*sample).clamp(-1.0, 1.0);
+            self.block.push((sample * scale).round() as i32);
+            if self.block.len() == BLOCK_SIZE * usize::from(self.channels) {
+                self.write_block()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode the buffered samples as a frame.
+    fn write_block(&mut self) -> Result<()> {
+        let channels = usize::from(self.channels);
+        let len = self.block.len() / channels;
+        if len == 0 {
+            return Ok(());
+        }
+        let out = &mut self.bytes;
+        out.bytes.clear();
+        // Frame header: sync code, fixed block size.
+        out.write(16, 0xFFF8);
+        // Block size (4096, or in 16 bits at the end of the header), and
+        // sample rate from STREAMINFO.
+        let size_code = if len == BLOCK_SIZE { 0b1100 } else { 0b0111 };
+        out.write(4, size_code);
+        out.write(4, 0);
+        // Independent channels.
+        out.write(4, u64::from(self.channels - 1));
+        out.write(3, if self.bits == 16 { 0b100 } else { 0b110 });
+        out.write(1, 0);
+        // Frame number, UTF-8 coded.
+        let number = self.blocks;
+        if number < 0x80 {
+            out.write(8, number);
+        } else {
+            // Each byte after the first holds 6 bits.
+            let bits = 64 - number.leading_zeros();
+            let bytes = (bits - 1).div_ceil(5);
+            let rest = 6 * (bytes - 1);
+            out.write(8, (0xFF00 >> bytes) & 0xFF | (number >> rest));
+            for i in (0..bytes - 1).rev() {
+                out.write(8, 0x80 | ((number >> (6 * i)) & 0x3F));
+            }
+        }
+        if size_code == 0b0111 {
+            out.write(16, (len - 1) as u64);
+        }
+        let crc = crc8(&out.bytes);
+        out.write(8, crc.into());
+        // Subframes.
+        let mut samples = Vec::with_capacity(len);
+        for channel in 0..channels {
+            samples.clear();
+            samples.extend(self.block.iter().skip(channel).step_by(channels));
+            subframe(out, &samples, self.bits.into());
+        }
+        out.align();
+        let crc = crc16(&out.bytes);
+        out.write(16, crc.into());
+        self.file.write_all(&out.bytes)?;
+        let frame_size = out.bytes.len() as u32;
+        self.frame_sizes = if self.blocks == 0 {
+            (frame_size, frame_size)
+        } else {
+            (
+                self.frame_sizes.0.min(frame_size),
+                self.frame_sizes.1.max(frame_size),
+            )
+        };
+        self.size += u64::from(frame_size);
+        self.frames += len as u64;
+        self.blocks += 1;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Encode the STREAMINFO metadata block's contents.
+    fn streaminfo(&self) -> [u8; 34] {
+        let mut info = BitWriter::default();
+        info.write(16, BLOCK_SIZE as u64);
+        info.write(16, BLOCK_SIZE as u64);
+        info.write(24, self.frame_sizes.0.into());
+        info.write(24, self.frame_sizes.1.into());
+        info.write(20, self.sample_rate.into());
+        info.write(3, u64::from(self.channels - 1));
+        info.write(5, u64::from(self.bits - 1));
+        // Total samples (0 is unknown, so partial files are still valid).
+        info.write(4, self.frames >> 32);
+        info.write(32, self.frames & 0xFFFF_FFFF);
+        // MD5 signature isn't calculated.
+        for _ in 0..4 {
+            info.write(32, 0);
+        }
+        info.bytes.try_into().unwrap()
+    }
+
+    /// Flush to disk, and update the header with the total number of frames.
+    /// Frames only fill blocks, so a partial block is written only if
+    /// `end`, after which no more audio may be written.
+    pub(crate) fn finalize(&mut self, end: bool) -> Result<()> {
+        if end {
+            self.write_block()?;
+        }
+        self.file.flush()?;
+        let streaminfo = self.streaminfo();
+        let file = self.file.get_mut();
+        let position = file.stream_position()?;
+        file.seek(SeekFrom::Start(STREAMINFO_OFFSET))?;
+        file.write_all(&streaminfo)?;
+        file.seek(SeekFrom::Start(position))?;
+        file.flush()
+    }
+}
+
+impl Drop for FlacWriter {
+    fn drop(&mut self) {
+        // Errors are reported by finalizing before the writer is dropped.
+        let _ = self.finalize(true);
+    }
+}
+
+/// Encode a subframe of one channel's samples with `bits` bits per sample.
+fn subframe(out: &mut BitWriter, samples: &[i32], bits: u32) {
+    if samples.iter().all(|&s| s == samples[0]) {
+        out.write(8, 0);
+        out.write_signed(bits, samples[0].into());
+        return;
+    }
+    // Pick the predictor order with the smallest residuals.
+    let max_order = 4.min(samples.len() - 1);
+    let (order, sum) = (0..=max_order)
+        .map(|order| {
+            let sum: u64 = (order..samples.len())
+                .map(|i| residual(samples, order, i).unsigned_abs())
+                .sum();
+            (order, sum)
+        })
+        .min_by_key(|&(_, sum)| sum)
+        .unwrap();
+    let residuals: Vec<i64> = (order..samples.len())
+        .map(|i| residual(samples, order, i))
+        .collect();
+    let (parameter, residual_bits) = rice(&residuals, sum);
+    let fixed_bits = order as u64 * u64::from(bits) + 10 + residual_bits;
+    if fixed_bits >= samples.len() as u64 * u64::from(bits) {
+        // Verbatim.
+        out.write(8, 0b0000_0010);
+        for &sample in samples {
+            out.write_signed(bits, sample.into());
+        }
+        return;
+    }
+    out.write(8, (0b1000 | order as u64) << 1);
+    for &sample in &samples[..order] {
+        out.write_signed(bits, sample.into());
+    }
+    // Rice coding with 4-bit parameter, one partition.
+    out.write(2, 0);
+    out.write(4, 0);
+    out.write(4, parameter.into());
+    for residual in residuals {
+        let zigzag = ((residual << 1) ^ (residual >> 63)) as u64;
+        out.write_unary((zigzag >> parameter) as u32);
+        out.write(parameter, zigzag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads bits, most significant first.
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        /// Position in bits.
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn read(&mut self, count: u32) -> u64 {
+            let mut value = 0;
+            for _ in 0..count {
+                let byte = self.bytes[self.position / 8];
+                let bit = (byte >> (7 - self.position % 8)) & 1;
+                value = (value << 1) | u64::from(bit);
+                self.position += 1;
+            }
+            value
+        }
+
+        fn read_signed(&mut self, count: u32) -> i64 {
+            let value = self.read(count);
+            ((value << (64 - count)) as i64) >> (64 - count)
+        }
+
+        fn read_unary(&mut self) -> u64 {
+            let mut zeros = 0;
+            while self.read(1) == 0 {
+                zeros += 1;
+            }
+            zeros
+        }
+
+        fn byte(&self) -> usize {
+            self.position / 8
+        }
+    }
+
+    /// A decoded FLAC file.
+    #[derive(Debug, Default)]
+    struct Decoded {
+        sample_rate: u32,
+        channels: u8,
+        bits: u8,
+        frames: u64,
+        frame_sizes: (u32, u32),
+        /// Frame numbers of the blocks.
+        numbers: Vec<u64>,
+        /// Interleaved samples.
+        samples: Vec<i32>,
+    }
+
+    /// Decode the subset of FLAC written by `FlacWriter`, checking CRCs.
+    fn decode(bytes: &[u8]) -> Decoded {
+        assert_eq!(&bytes[..8], b"fLaC\x80\0\0\x22");
+        let mut info = BitReader {
+            bytes: &bytes[8..42],
+            position: 0,
+        };
+        assert_eq!(info.read(16), BLOCK_SIZE as u64);
+        assert_eq!(info.read(16), BLOCK_SIZE as u64);
+        let mut decoded = Decoded {
+            frame_sizes: (info.read(24) as u32, info.read(24) as u32),
+            sample_rate: info.read(20) as u32,
+            channels: info.read(3) as u8 + 1,
+            bits: info.read(5) as u8 + 1,
+            frames: info.read(36),
+            ..Decoded::default()
+        };
+        let bits = u32::from(decoded.bits);
+        let channels = usize::from(decoded.channels);
+        let mut rest = &bytes[42..];
+        while !rest.is_empty() {
+            let mut r = BitReader {
+                bytes: rest,
+                position: 0,
+            };
+            assert_eq!(r.read(16), 0xFFF8);
+            let size_code = r.read(4);
+            assert_eq!(r.read(4), 0);
+            assert_eq!(r.read(4) as usize, channels - 1);
+            let bits_code = if bits == 16 { 0b100 } else { 0b110 };
+            assert_eq!(r.read(3), bits_code);
+            assert_eq!(r.read(1), 0);
+            // Frame number, UTF-8 coded.
+            let first = r.read(8);
+            let ones = (first as u8).leading_ones();
+            let mut number = first & (0x7F >> ones);
+            for _ in 1..ones {
+                let byte = r.read(8);
+                assert_eq!(byte & 0xC0, 0x80);
+                number = (number << 6) | (byte & 0x3F);
+            }
+            decoded.numbers.push(number);
+            let len = match size_code {
+                0b1100 => BLOCK_SIZE,
+                0b0111 => r.read(16) as usize + 1,
+                _ => panic!("unexpected block size code {size_code}"),
+            };
+            let header = r.byte();
+            assert_eq!(r.read(8) as u8, crc8(&rest[..header]));
+            let mut block = vec![0; len * channels];
+            for channel in 0..channels {
+                let samples = decode_subframe(&mut r, len, bits);
+                for (i, sample) in samples.into_iter().enumerate() {
+                    block[i * channels + channel] = sample;
+                }
+            }
+            if r.position % 8 != 0 {
+                r.read(8 - (r.position % 8) as u32);
+            }
+            let end = r.byte();
+            assert_eq!(r.read(16) as u16, crc16(&rest[..end]));
+            let size = (end + 2) as u32;
+            assert!(decoded.frame_sizes.0 <= size);
+            assert!(size <= decoded.frame_sizes.1);
+            decoded.samples.extend(block);
+            rest = &rest[end + 2..];
+        }
+        decoded
+    }
+
+    fn decode_subframe(
+        r: &mut BitReader<'_>,
+        len: usize,
+        bits: u32,
+    ) -> Vec<i32> {
+        let kind = r.read(8);
+        if kind == 0 {
+            return vec![r.read_signed(bits) as i32; len];
+        }
+        if kind == 0b10 {
+            return (0..len).map(|_| r.read_signed(bits) as i32).collect();
+        }
+        assert_eq!(kind >> 4, 0b1);
+        let order = ((kind >> 1) & 0b111) as usize;
+        let mut samples: Vec<i32> =
+            (0..order).map(|_| r.read_signed(bits) as i32).collect();
+        assert_eq!(r.read(2), 0);
+        assert_eq!(r.read(4), 0);
+        let parameter = r.read(4) as u32;
+        while samples.len() < len {
+            let zigzag = (r.read_unary() << parameter) | r.read(parameter);
+            let value = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+            // The residual of a zero sample is minus the prediction.
+            samples.push(0);
+            let prediction = -residual(&samples, order, samples.len() - 1);
+            *samples.last_mut().unwrap() = (value + prediction) as i32;
+        }
+        samples
+    }
+
+    /// Encode `audio` to a FLAC file named `name`, and return its bytes.
+    fn encode(
+        name: &str,
+        bits: u8,
+        channels: u8,
+        blocks: u64,
+        audio: &[Ch32],
+    ) -> Vec<u8> {
+        let path = std::env::temp_dir().join(name);
+        let mut writer =
+            FlacWriter::create(&path, bits, 48_000, channels).unwrap();
+        writer.blocks = blocks;
+        writer.write(audio).unwrap();
+        writer.finalize(true).unwrap();
+        let size = writer.size();
+        drop(writer);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bytes.len() as u64, size);
+        bytes
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+    }
+
+    #[test]
+    fn round_trip() {
+        // A chirp, silence and noise, so all kinds of subframe are used.
+        let mut noise = 1u32;
+        let len = BLOCK_SIZE * 3 + 100;
+        let audio: Vec<Ch32> = (0..len * 2)
+            .map(|i| {
+                let t = (i / 2) as f32 / 48_000.0;
+                let sample = match (i / 2) / BLOCK_SIZE {
+                    0 => (t * t * 20_000.0).sin() * 0.5,
+                    1 => 0.0,
+                    _ => {
+                        noise ^= noise << 13;
+                        noise ^= noise >> 17;
+                        noise ^= noise << 5;
+                        noise as f32 / u32::MAX as f32 - 0.5
+                    }
+                };
+                Ch32::new(if i % 2 == 0 { sample } else { -sample })
+            })
+            .collect();
+        for bits in [16, 24] {
+            let bytes = encode("wavy-test-flac.flac", bits, 2, 0, &audio);
+            let decoded = decode(&bytes);
+            assert_eq!(decoded.sample_rate, 48_000);
+            assert_eq!(decoded.channels, 2);
+            assert_eq!(decoded.bits, bits);
+            assert_eq!(decoded.frames, len as u64);
+            assert_eq!(decoded.numbers, [0, 1, 2, 3]);
+            let scale = ((1 << (bits - 1)) - 1) as f32;
+            let expected: Vec<i32> = audio
+                .iter()
+                .map(|s| (f32::from(*s) * scale).round() as i32)
+                .collect();
+            assert_eq!(decoded.samples, expected);
+        }
+    }
+
+    #[test]
+    fn frame_numbers() {
+        for number in [0x7F, 0x80, 0x7FF, 0x800, 0xFFFF, 0x1_0000] {
+            let audio = vec![Ch32::new(0.25); 10];
+            let bytes = encode("wavy-test-numbers.flac", 16, 1, number, &audio);
+            assert_eq!(decode(&bytes).numbers, [number]);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::mem::{align_of, size_of};
+
+use fon::{chan::Ch32, Frame};
+
+/// View interleaved samples as frames, so a period buffer can be filled in
+/// place (trailing samples that don't make a whole frame are left out).
+#[allow(unsafe_code)]
+pub(crate) fn frames_mut<F: Frame<Chan = Ch32>>(
+    samples: &mut [Ch32],
+) -> &mut [F] {
+    // fon's frames are `repr(transparent)` arrays of their channels (which
+    // `Frame::CHAN_COUNT` also relies on).
+    assert_eq!(size_of::<F>(), size_of::<Ch32>() * F::CHAN_COUNT);
+    assert_eq!(align_of::<F>(), align_of::<Ch32>());
+    let len = samples.len() / F::CHAN_COUNT;
+    // Safety: checked above that `len` frames fit, with the same alignment.
+    unsafe { std::slice::from_raw_parts_mut(samples.as_mut_ptr().cast(), len) }
+}
@@ -0,0 +1,197 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use fon::{chan::Ch32, Frame};
+
+/// How long volume and mute changes take, so they don't click.
+const RAMP: Duration = Duration::from_millis(10);
+
+/// Software volume, mute and fade of a device.  The settings are atomic, so
+/// changing them never blocks the audio path; the device ramps smoothly
+/// between them as it hands out periods.
+#[derive(Debug)]
+pub(crate) struct Gain {
+    /// Volume set by the application, as `f32` bits.
+    volume: AtomicU32,
+    /// Whether the device is muted.
+    muted: AtomicBool,
+    /// Whether the device is fading (or faded) out.
+    faded: AtomicBool,
+    /// Whether the next ramp starts from silence.
+    silent: AtomicBool,
+    /// Length of the ramp to the latest settings, in nanoseconds.
+    duration: AtomicU64,
+    /// Number of times the settings were changed.
+    changes: AtomicU32,
+    /// Ramp at the start of the next period.
+    ramp: Ramp,
+    /// Number of changes the ramp follows.
+    seen: u32,
+}
+
+impl Default for Gain {
+    fn default() -> Self {
+        Self {
+            volume: AtomicU32::new(1.0f32.to_bits()),
+            muted: AtomicBool::new(false),
+            faded: AtomicBool::new(false),
+            silent: AtomicBool::new(false),
+            duration: AtomicU64::new(0),
+            changes: AtomicU32::new(0),
+            ramp: Ramp::default(),
+            seen: 0,
+        }
+    }
+}
+
+impl Gain {
+    pub(crate) fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set_volume(&self, volume: f32) {
+        self.volume
+            .store(volume.max(0.0).to_bits(), Ordering::Relaxed);
+        self.retarget(RAMP);
+    }
+
+    pub(crate) fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+        self.retarget(RAMP);
+    }
+
+    /// Ramp up to the volume over `duration`, from silence unless a fade out
+    /// is in progress.
+    pub(crate) fn fade_in(&self, duration: Duration) {
+        if !self.faded.swap(false, Ordering::Relaxed) {
+            self.silent.store(true, Ordering::Relaxed);
+        }
+        self.retarget(duration);
+    }
+
+    /// Ramp down to silence over `duration`.
+    pub(crate) fn fade_out(&self, duration: Duration) {
+        self.faded.store(true, Ordering::Relaxed);
+        self.retarget(duration);
+    }
+
+    /// Check if a fade out has finished.
+    pub(crate) fn is_faded_out(&self) -> bool {
+        self.faded.load(Ordering::Relaxed)
+            && self.changes.load(Ordering::Acquire) == self.seen
+            && self.ramp.remaining == 0
+    }
+
+    /// Get the ramp for a period of `frames` frames at `sample_rate`, and move
+    /// forward to the next period.
+    pub(crate) fn period(&mut self, sample_rate: f64, frames: usize) -> Ramp {
+        let changes = self.changes.load(Ordering::Acquire);
+        if changes != self.seen {
+            self.seen = changes;
+            let target =
+                if self.is_muted() || self.faded.load(Ordering::Relaxed) {
+                    0.0
+                } else {
+                    self.volume()
+                };
+            let duration =
+                Duration::from_nanos(self.duration.load(Ordering::Relaxed));
+            let ramp = &mut self.ramp;
+            if self.silent.swap(false, Ordering::Relaxed) {
+                ramp.current = 0.0;
+            }
+            ramp.target = target;
+            let frames = (duration.as_secs_f64() * sample_rate).round();
+            ramp.remaining = frames.max(1.0) as u64;
+            ramp.step = (ramp.target - ramp.current) / ramp.remaining as f32;
+        }
+        let ramp = self.ramp;
+        self.ramp.skip(frames as u64);
+        ramp
+    }
+
+    /// Ramp to the settings over `duration`, from the next period.
+    fn retarget(&self, duration: Duration) {
+        let nanos = duration.as_nanos().try_into().unwrap_or(u64::MAX);
+        self.duration.store(nanos, Ordering::Relaxed);
+        self.changes.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Gain of each frame of a period, ramping between settings.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Ramp {
+    /// Gain of the last frame.
+    current: f32,
+    /// Gain at the end of the ramp.
+    target: f32,
+    /// Change in gain per frame during the ramp.
+    step: f32,
+    /// Number of frames left in the ramp.
+    remaining: u64,
+}
+
+impl Default for Ramp {
+    fn default() -> Self {
+        Self {
+            current: 1.0,
+            target: 1.0,
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+}
+
+impl Ramp {
+    /// Scale `frames`.
+    pub(crate) fn apply<F: Frame<Chan = Ch32>>(&mut self, frames: &mut [F]) {
+        if self.remaining == 0 && self.current == 1.0 {
+            return;
+        }
+        for frame in frames {
+            let gain = self.next();
+            for chan in frame.channels_mut() {
+                *chan *= gain;
+            }
+        }
+    }
+
+    /// Get the gain of the next frame.
+    pub(crate) fn next(&mut self) -> f32 {
+        if self.remaining != 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 {
+                self.target
+            } else {
+                self.current + self.step
+            };
+        }
+        self.current
+    }
+
+    /// Move forward `frames` frames.
+    fn skip(&mut self, frames: u64) {
+        if frames >= self.remaining {
+            self.current = self.target;
+            self.remaining = 0;
+        } else {
+            self.current += self.step * frames as f32;
+            self.remaining -= frames;
+        }
+    }
+}
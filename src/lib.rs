@@ -115,10 +115,54 @@
     cfg_attr(target_os = "dummy", path = "ffi/dummy/ffi.rs")
 )]
 mod ffi;
+#[path = "ffi/virt/ffi.rs"]
+mod virt;
 
+mod block;
+mod blocking;
+mod channels;
+mod clock;
+#[cfg(feature = "futures")]
+mod compat;
+mod config;
 mod consts;
+mod echo;
+mod flac;
+mod frames;
+mod gain;
+mod loopback;
+mod meter;
 mod microphone;
+mod mixer;
+mod player;
+mod processor;
+mod recorder;
+mod resample;
+mod ring;
+mod routing;
+mod source;
 mod speakers;
+mod thread;
+mod volume;
+mod wav;
 
+pub use block::AudioBlock;
+pub use channels::{ChannelMap, ChannelPosition};
+pub use clock::Clock;
+pub use config::{SampleFormat, StreamConfig};
+pub use echo::{EchoCanceller, EchoReference};
+pub use loopback::Loopback;
+pub use meter::{Levels, Meter};
 pub use microphone::{Microphone, MicrophoneStream};
+pub use mixer::{Mixer, MixerHandle, VoiceId};
+pub use player::{Player, PlayerHandle};
+pub use processor::{AutomaticGainControl, CaptureProcessor, NoiseSuppressor};
+pub use recorder::{RecordFormat, Recorder, Split};
+pub use resample::ResampleQuality;
+pub use ring::{ring, RingIter, RingReader, RingWriter};
+pub use routing::{Route, RouteSink, RoutedSink, RoutedSpeakers};
+pub use source::SourceKind;
 pub use speakers::{Speakers, SpeakersSink};
+pub use thread::{AudioThread, Priority, Timing};
+pub use volume::VolumeControl;
+pub use wav::WavFormat;
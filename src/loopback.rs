@@ -0,0 +1,96 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use crate::{virt, Clock, Microphone, Speakers};
+
+/// Configuration for a pair of virtual devices, where everything played
+/// through the speakers is recorded by the microphone.
+///
+/// While any device of the pair is alive, the pair also shows up in
+/// [`Speakers::query()`] as "Loopback", and in [`Microphone::query()`] while
+/// no microphone records it (each pair has only one microphone, so they don't
+/// split the audio between them).  If the microphone doesn't keep up, audio
+/// more than a few periods past the latency is dropped.
+///
+/// ```
+/// use fon::{mono::Mono32, Sink};
+/// use pasts::prelude::*;
+/// use wavy::{Clock, Loopback, Microphone, Speakers};
+///
+/// async fn run() {
+///     let (mut speakers, mut microphone) = Loopback {
+///         clock: Clock::Virtual,
+///         latency: 100,
+///         ..Loopback::default()
+///     }
+///     .open::<1>();
+///     let query = Speakers::query();
+///     assert!(query.iter().any(|spk| spk.to_string() == "Loopback"));
+///     let query = Microphone::query();
+///     assert!(query.iter().all(|mic| mic.to_string() != "Loopback"));
+///
+///     let mut recorded = Vec::new();
+///     while recorded.len() < 256 {
+///         speakers.next().await.stream(Mono32::new(0.5));
+///         recorded.extend(microphone.next().await);
+///     }
+///     // Audio is delayed by the latency.
+///     assert_eq!(recorded[99], Mono32::new(0.0));
+///     assert_eq!(recorded[100], Mono32::new(0.5));
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Loopback {
+    /// How periods are paced (in real time by default, except on
+    /// WebAssembly).
+    pub clock: Clock,
+    /// Sample rate of both devices.
+    pub sample_rate: u32,
+    /// Delay between playing and recording audio, in frames (from when the
+    /// speakers start, so recording before then doesn't shorten it).
+    pub latency: u32,
+    /// Amplitude of white noise added to recorded audio (0.0 for none).
+    pub noise: f32,
+    /// Probability (0.0 to 1.0) that a period of recorded audio is replaced
+    /// with silence.
+    pub dropout: f32,
+    /// Impulse response of the path from the speakers to the microphone
+    /// (such as one recorded in a room), convolved with each channel after
+    /// the latency.  Empty for a direct path.
+    pub impulse_response: Vec<f32>,
+}
+
+impl Default for Loopback {
+    fn default() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            clock: Clock::RealTime,
+            #[cfg(target_arch = "wasm32")]
+            clock: Clock::Virtual,
+            sample_rate: crate::consts::SAMPLE_RATE.into(),
+            latency: 0,
+            noise: 0.0,
+            dropout: 0.0,
+            impulse_response: Vec::new(),
+        }
+    }
+}
+
+impl Loopback {
+    /// Create the speakers and microphone pair.
+    pub fn open<const N: usize>(self) -> (Speakers<N>, Microphone<N>) {
+        let link = virt::Link::new(self);
+        (
+            Speakers::from_virt(virt::Speakers::loopback(link.clone())),
+            Microphone::from_virt(virt::Microphone::loopback(link).unwrap()),
+        )
+    }
+}
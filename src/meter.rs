@@ -0,0 +1,271 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    f32::consts::PI,
+    fmt::{Debug, Formatter, Result},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering::SeqCst},
+        Arc, OnceLock,
+    },
+};
+
+use fon::chan::Ch32;
+
+/// Most channels of a frame.
+const CHANNELS: usize = 6;
+
+/// Number of samples each interpolated sample is calculated from.
+const TAPS: usize = 8;
+
+/// Windowed sinc filters to interpolate the three samples between the middle
+/// two of [`TAPS`] samples (4× oversampling, as in ITU-R BS.1770).
+fn filters() -> &'static [[f32; TAPS]; 3] {
+    static FILTERS: OnceLock<[[f32; TAPS]; 3]> = OnceLock::new();
+    FILTERS.get_or_init(|| {
+        let mut filters = [[0.0; TAPS]; 3];
+        for (phase, filter) in filters.iter_mut().enumerate() {
+            let offset = (phase + 1) as f32 / 4.0;
+            for (tap, coefficient) in filter.iter_mut().enumerate() {
+                let t = tap as f32 - (TAPS / 2 - 1) as f32 - offset;
+                let sinc = (PI * t).sin() / (PI * t);
+                let window = 0.5 * (1.0 + (PI * t / (TAPS / 2) as f32).cos());
+                *coefficient = sinc * window;
+            }
+            let sum: f32 = filter.iter().sum();
+            for coefficient in filter.iter_mut() {
+                *coefficient /= sum;
+            }
+        }
+        filters
+    })
+}
+
+/// Levels of each channel over a period, in linear amplitude (1.0 is full
+/// scale).
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Levels {
+    /// Number of channels.
+    channels: usize,
+    /// Highest sample.
+    peak: [f32; CHANNELS],
+    /// Root mean square.
+    rms: [f32; CHANNELS],
+    /// Highest sample after 4× oversampling, including peaks between samples
+    /// (delayed by a few samples).
+    true_peak: [f32; CHANNELS],
+}
+
+impl Levels {
+    /// Get the peak of each channel.
+    pub fn peak(&self) -> &[f32] {
+        &self.peak[..self.channels]
+    }
+
+    /// Get the RMS of each channel.
+    pub fn rms(&self) -> &[f32] {
+        &self.rms[..self.channels]
+    }
+
+    /// Get the true peak (estimated with 4× oversampling) of each channel.
+    pub fn true_peak(&self) -> &[f32] {
+        &self.true_peak[..self.channels]
+    }
+}
+
+/// Levels stored as `f32` bits, so they can be read without locking.
+#[derive(Default)]
+struct AtomicLevels {
+    channels: AtomicU32,
+    peak: [AtomicU32; CHANNELS],
+    rms: [AtomicU32; CHANNELS],
+    true_peak: [AtomicU32; CHANNELS],
+}
+
+/// State shared between a meter and the device it measures.
+#[derive(Default)]
+struct Shared {
+    /// Levels of the last period.
+    levels: AtomicLevels,
+    /// Number of samples that clipped.
+    clips: AtomicU64,
+}
+
+/// Level meter of a [`Speakers`](crate::Speakers) or
+/// [`Microphone`](crate::Microphone), updated each period.
+///
+/// Meters can be cloned and sent to other tasks or threads (such as a UI),
+/// and reading them doesn't lock.
+///
+/// ```
+/// use fon::mono::Mono32;
+/// use wavy::{Clock, Speakers};
+///
+/// let mut speakers = Speakers::<1>::null(Clock::Virtual, 48_000);
+/// let meter = speakers.meter();
+/// // A sine at a quarter of the sample rate, sampled away from its peaks.
+/// let period: Vec<Mono32> = [0.7, 0.7, -0.7, -0.7]
+///     .iter()
+///     .cycle()
+///     .take(64)
+///     .map(|s| Mono32::new(*s))
+///     .collect();
+/// speakers.write_blocking(&period);
+/// let levels = meter.levels();
+/// assert_eq!(levels.peak(), &[0.7]);
+/// assert!(levels.true_peak()[0] > 0.9);
+/// assert_eq!(meter.clips(), 0);
+/// ```
+#[derive(Clone, Default)]
+pub struct Meter(Arc<Shared>);
+
+impl Debug for Meter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "Meter({:?}, clips: {})", self.levels(), self.clips())
+    }
+}
+
+impl Meter {
+    /// Get the levels of the last period.
+    pub fn levels(&self) -> Levels {
+        let atomic = &self.0.levels;
+        let load = |values: &[AtomicU32; CHANNELS]| {
+            let mut out = [0.0; CHANNELS];
+            for (out, value) in out.iter_mut().zip(values) {
+                *out = f32::from_bits(value.load(SeqCst));
+            }
+            out
+        };
+        Levels {
+            channels: atomic.channels.load(SeqCst) as usize,
+            peak: load(&atomic.peak),
+            rms: load(&atomic.rms),
+            true_peak: load(&atomic.true_peak),
+        }
+    }
+
+    /// Get the number of samples that clipped (reached full scale) since the
+    /// meter was created or reset.
+    pub fn clips(&self) -> u64 {
+        self.0.clips.load(SeqCst)
+    }
+
+    /// Reset the clip counter, returning its value.
+    pub fn reset_clips(&self) -> u64 {
+        self.0.clips.swap(0, SeqCst)
+    }
+}
+
+/// A meter enabled on a device, with the last samples of each channel kept
+/// by the device (not shared with clones of the meter) to interpolate across
+/// periods.  Samples are stored as `f32` bits, so they're passed from one
+/// period to the next without locking.
+#[derive(Default)]
+pub(crate) struct Metering {
+    /// Where the levels are published.
+    pub(crate) meter: Meter,
+    /// Last samples of each channel, oldest first.
+    history: [[AtomicU32; TAPS]; CHANNELS],
+}
+
+impl Metering {
+    /// Start measuring a period of frames with `channels` channels, following
+    /// on from the last period measured.
+    pub(crate) fn measure(self: &Arc<Self>, channels: usize) -> Measure {
+        let mut samples = [[0.0; TAPS]; CHANNELS];
+        for (samples, atomics) in samples.iter_mut().zip(&self.history) {
+            for (sample, atomic) in samples.iter_mut().zip(atomics) {
+                *sample = f32::from_bits(atomic.load(SeqCst));
+            }
+        }
+        Measure {
+            metering: self.clone(),
+            channels,
+            frames: 0,
+            clips: 0,
+            peak: [0.0; CHANNELS],
+            squares: [0.0; CHANNELS],
+            true_peak: [0.0; CHANNELS],
+            samples,
+            filters: filters(),
+        }
+    }
+}
+
+/// Measurement of a period in progress, published to the meter when dropped.
+pub(crate) struct Measure {
+    /// Where the levels and last samples are published.
+    metering: Arc<Metering>,
+    /// Number of channels.
+    channels: usize,
+    /// Number of frames measured.
+    frames: usize,
+    /// Number of samples that clipped.
+    clips: u64,
+    /// Highest sample of each channel.
+    peak: [f32; CHANNELS],
+    /// Sum of squares of each channel.
+    squares: [f64; CHANNELS],
+    /// Highest interpolated sample of each channel.
+    true_peak: [f32; CHANNELS],
+    /// Last samples of each channel, oldest first.
+    samples: [[f32; TAPS]; CHANNELS],
+    /// Interpolation filters.
+    filters: &'static [[f32; TAPS]; 3],
+}
+
+impl Measure {
+    /// Measure a frame.
+    pub(crate) fn frame(&mut self, channels: &[Ch32]) {
+        self.frames += 1;
+        for (i, chan) in channels.iter().enumerate().take(CHANNELS) {
+            let sample = f32::from(*chan);
+            let level = sample.abs();
+            if level >= 1.0 {
+                self.clips += 1;
+            }
+            self.peak[i] = self.peak[i].max(level);
+            self.squares[i] += f64::from(sample) * f64::from(sample);
+            let history = &mut self.samples[i];
+            history.rotate_left(1);
+            history[TAPS - 1] = sample;
+            for filter in self.filters {
+                let value: f32 =
+                    filter.iter().zip(history.iter()).map(|(c, s)| c * s).sum();
+                self.true_peak[i] = self.true_peak[i].max(value.abs());
+            }
+            self.true_peak[i] = self.true_peak[i].max(level);
+        }
+    }
+}
+
+impl Drop for Measure {
+    fn drop(&mut self) {
+        if self.frames == 0 {
+            return;
+        }
+        let shared = &self.metering.meter.0;
+        let levels = &shared.levels;
+        levels.channels.store(self.channels as u32, SeqCst);
+        for i in 0..CHANNELS {
+            let rms = (self.squares[i] / self.frames as f64).sqrt() as f32;
+            levels.peak[i].store(self.peak[i].to_bits(), SeqCst);
+            levels.rms[i].store(rms.to_bits(), SeqCst);
+            levels.true_peak[i].store(self.true_peak[i].to_bits(), SeqCst);
+        }
+        shared.clips.fetch_add(self.clips, SeqCst);
+        for (samples, atomics) in
+            self.samples.iter().zip(&self.metering.history)
+        {
+            for (sample, atomic) in samples.iter().zip(atomics) {
+                atomic.store(sample.to_bits(), SeqCst);
+            }
+        }
+    }
+}
@@ -7,21 +7,182 @@
 // At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
 // LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
 
-use std::fmt::{Debug, Display, Formatter, Result};
+use std::{
+    fmt::{Debug, Display, Formatter, Result},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 
-use fon::{chan::Ch32, Frame, Stream};
+use fon::{
+    chan::{Ch32, Channel},
+    mono::Mono32,
+    stereo::Stereo32,
+    surround::Surround32,
+    Frame, Stream,
+};
 use pasts::prelude::*;
 
-use crate::ffi;
+use crate::{
+    block::BlockPool,
+    blocking, ffi,
+    frames::frames_mut,
+    gain::{Gain, Ramp},
+    meter::Measure,
+    meter::Metering,
+    resample::Converter,
+    virt,
+    wav::WavReader,
+    AudioBlock, CaptureProcessor, ChannelMap, ChannelPosition, Clock,
+    EchoCanceller, Meter, ResampleQuality, SourceKind, StreamConfig,
+    VolumeControl,
+};
+
+/// Device backing a [`Microphone`], chosen at runtime.
+enum MicrophoneDevice {
+    /// A sound card, through the platform's audio API.
+    Ffi(Box<ffi::Microphone>),
+    /// A virtual device.
+    Virt(Box<virt::Microphone>),
+}
+
+impl Default for MicrophoneDevice {
+    fn default() -> Self {
+        Self::Ffi(Box::default())
+    }
+}
+
+impl Display for MicrophoneDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::Ffi(microphone) => microphone.fmt(f),
+            Self::Virt(microphone) => microphone.fmt(f),
+        }
+    }
+}
+
+impl MicrophoneDevice {
+    fn channels(&self) -> u8 {
+        match self {
+            Self::Ffi(microphone) => microphone.channels(),
+            Self::Virt(microphone) => microphone.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> Option<f64> {
+        match self {
+            Self::Ffi(microphone) => microphone.sample_rate(),
+            Self::Virt(microphone) => microphone.sample_rate(),
+        }
+    }
+
+    fn stream_config(&self) -> Option<StreamConfig> {
+        match self {
+            Self::Ffi(microphone) => microphone.stream_config(),
+            Self::Virt(microphone) => microphone.stream_config(),
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        match self {
+            Self::Ffi(microphone) => microphone.set_sample_rate(sample_rate),
+            // Virtual devices record at a fixed sample rate.
+            Self::Virt(_) => {}
+        }
+    }
+
+    fn channel_positions(&self) -> Vec<ChannelPosition> {
+        match self {
+            Self::Ffi(microphone) => microphone.channel_positions(),
+            Self::Virt(_) => Vec::new(),
+        }
+    }
+
+    fn volume_controls(&self) -> Vec<VolumeControl> {
+        match self {
+            Self::Ffi(microphone) => microphone
+                .volume_controls()
+                .into_iter()
+                .map(VolumeControl)
+                .collect(),
+            Self::Virt(_) => Vec::new(),
+        }
+    }
+
+    fn kind(&self) -> SourceKind {
+        match self {
+            Self::Ffi(microphone) => microphone.kind(),
+            Self::Virt(microphone) => microphone.kind(),
+        }
+    }
+
+    fn take_error(&mut self) -> Option<std::io::Error> {
+        match self {
+            Self::Ffi(microphone) => microphone.take_error(),
+            Self::Virt(_) => None,
+        }
+    }
+
+    fn record<F: Frame<Chan = Ch32>>(&mut self) -> StreamDevice<F> {
+        match self {
+            Self::Ffi(microphone) => StreamDevice::Ffi(microphone.record()),
+            Self::Virt(microphone) => StreamDevice::Virt(microphone.record()),
+        }
+    }
+}
+
+impl Future for MicrophoneDevice {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, e: &mut Exec<'_>) -> Poll<()> {
+        match self.get_mut() {
+            Self::Ffi(microphone) => Pin::new(microphone).poll(e),
+            Self::Virt(microphone) => Pin::new(microphone).poll(e),
+        }
+    }
+}
 
 /// Record audio from connected microphone.  Notifier produces an audio stream,
 /// which contains the samples recorded since the previous call.
+///
+/// Microphones are `Send`, so they can be opened on one thread and used on
+/// another (such as an [`AudioThread`](crate::AudioThread)).
+#[derive(Default)]
+pub struct Microphone<const N: usize> {
+    /// The device audio is recorded from.
+    device: MicrophoneDevice,
+    /// Interleaved frames recorded, but not yet read without a stream.
+    recorded: Vec<Ch32>,
+    /// Number of frames read without a stream.
+    position: u64,
+    /// Buffers to reuse for blocks.
+    pool: BlockPool,
+    /// Software volume, ramped from period to period.
+    gain: Box<Gain>,
+    /// Level meter, if enabled.
+    meter: Option<Arc<Metering>>,
+    /// Sample rate requested, if any.
+    requested_rate: Option<u32>,
+    /// Converts periods to the requested sample rate.
+    converter: Box<Converter>,
+    /// Channel mixing of audio recorded, if set.
+    channel_map: Option<Box<ChannelMap>>,
+    /// Echo cancellation and processors.
+    processing: Box<Processing>,
+}
+
+/// Processing of audio recorded, before it's resampled.
 #[derive(Default)]
-pub struct Microphone<const N: usize>(pub(super) ffi::Microphone);
+struct Processing {
+    /// Removes the echo of speakers from audio recorded, if set.
+    echo_canceller: Option<EchoCanceller>,
+    /// Processing of audio recorded, in order.
+    processors: Vec<Box<dyn CaptureProcessor>>,
+}
 
 impl<const N: usize> Display for Microphone<N> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        self.0.fmt(f)
+        self.device.fmt(f)
     }
 }
 
@@ -32,13 +193,190 @@ impl<const N: usize> Debug for Microphone<N> {
 }
 
 impl Microphone<0> {
-    /// Query available audio sources.
+    /// Query available audio sources.  Sources that record what's played
+    /// through an output are tagged with [`SourceKind::Loopback`] or
+    /// [`SourceKind::Monitor`] (see [`Microphone::kind()`]).
+    ///
+    /// Sound cards are opened shared where they support it (through `dsnoop`
+    /// on Linux), so several `Microphone`s, in this process or others, can
+    /// record from the same card at once.
     pub fn query() -> Vec<Self> {
-        ffi::device_list(Self)
+        let mut list = ffi::device_list(false, |microphone| {
+            Self::new(MicrophoneDevice::Ffi(Box::new(microphone)))
+        });
+        for link in virt::Link::query() {
+            if let Some(microphone) = virt::Microphone::loopback(link) {
+                list.push(Self::from_virt(microphone));
+            }
+        }
+        list
+    }
+
+    /// Query sound cards to record from directly, without any conversion by
+    /// the platform (`hw` devices on Linux, opened with every automatic
+    /// conversion disabled).  Cards are opened in their own sample format,
+    /// and [`Microphone::config()`] fails for numbers of channels they don't
+    /// have.  Unlike [`Microphone::query()`], nothing else can record from
+    /// these cards while they're open.
+    ///
+    /// Resampling is [`ResampleQuality::Off`], so audio is recorded at the
+    /// card's sample rate (see [`Microphone::stream_config()`]).  A rate set
+    /// with [`Microphone::set_sample_rate()`] must be one the card records
+    /// at exactly, otherwise [`Microphone::config()`] fails.
+    ///
+    /// ```no_run
+    /// use wavy::Microphone;
+    ///
+    /// let mut microphone = Microphone::query_exclusive().pop().unwrap();
+    /// microphone.set_sample_rate(96_000);
+    /// let microphone = microphone.config::<2>().unwrap();
+    /// ```
+    pub fn query_exclusive() -> Vec<Self> {
+        ffi::device_list(true, |microphone| {
+            let microphone = MicrophoneDevice::Ffi(Box::new(microphone));
+            let mut microphone = Self::new(microphone);
+            microphone.set_resample_quality(ResampleQuality::Off);
+            microphone
+        })
+    }
+
+    /// Open a source that records what's played through the system's default
+    /// output ("record what you hear"), if there is one.
+    ///
+    /// On Linux, this is the monitor of the default output when PulseAudio or
+    /// PipeWire is running, otherwise the capture end of the first
+    /// `snd-aloop` device (`hw:Loopback,1`), which records what's played into
+    /// `hw:Loopback,0`.
+    ///
+    /// ```
+    /// # // Runs with `WAVY_TEST_ALOOP=1`, `snd-aloop` loaded and no sound
+    /// # // server.
+    /// # if std::env::var_os("WAVY_TEST_ALOOP").is_none() {
+    /// #     return;
+    /// # }
+    /// use fon::{mono::Mono32, Frame};
+    /// use wavy::{Microphone, SourceKind, Speakers};
+    ///
+    /// // Without a sound server, load the kernel's loopback module first:
+    /// // `sudo modprobe snd-aloop`
+    /// let microphone = Microphone::system_output().unwrap();
+    /// assert_eq!(microphone.kind(), SourceKind::Loopback);
+    ///
+    /// // Play a tone into `hw:Loopback,0`, and record it back.
+    /// let mut microphone = microphone.config::<1>().unwrap();
+    /// let mut speakers = Speakers::query()
+    ///     .into_iter()
+    ///     .find(|speakers| speakers.to_string().starts_with("Loopback"))
+    ///     .unwrap()
+    ///     .config::<1>()
+    ///     .unwrap();
+    /// let tone: Vec<Mono32> = (0..480)
+    ///     .map(|i| Mono32::new(0.5 * (i as f32 * 0.1).sin()))
+    ///     .collect();
+    /// let mut recorded = [Mono32::default(); 480];
+    /// microphone.read_blocking(&mut recorded);
+    /// let mut peak = 0.0f32;
+    /// for _ in 0..100 {
+    ///     speakers.write_blocking(&tone);
+    ///     let read = microphone.try_read(&mut recorded);
+    ///     for frame in &recorded[..read] {
+    ///         peak = peak.max(f32::from(frame.channels()[0]).abs());
+    ///     }
+    /// }
+    /// assert!(peak > 0.4);
+    /// ```
+    pub fn system_output() -> Option<Self> {
+        let microphone = ffi::system_output::<ffi::Microphone>()?;
+        Some(Self::new(MicrophoneDevice::Ffi(Box::new(microphone))))
     }
 }
 
 impl<const N: usize> Microphone<N> {
+    /// Get what this microphone records.
+    ///
+    /// ```
+    /// use wavy::{Clock, Loopback, Microphone, SourceKind};
+    ///
+    /// let (_speakers, microphone) = Loopback {
+    ///     clock: Clock::Virtual,
+    ///     ..Loopback::default()
+    /// }
+    /// .open::<1>();
+    /// // Only one microphone records a loopback at a time.
+    /// drop(microphone);
+    /// let query = Microphone::query();
+    /// let loopback = query.iter().find(|mic| mic.to_string() == "Loopback");
+    /// assert_eq!(loopback.unwrap().kind(), SourceKind::Loopback);
+    /// ```
+    pub fn kind(&self) -> SourceKind {
+        self.device.kind()
+    }
+
+    fn new(device: MicrophoneDevice) -> Self {
+        Self {
+            device,
+            recorded: Vec::new(),
+            position: 0,
+            pool: BlockPool::default(),
+            gain: Box::default(),
+            meter: None,
+            requested_rate: None,
+            converter: Box::default(),
+            channel_map: None,
+            processing: Box::default(),
+        }
+    }
+
+    /// Wrap a virtual device.
+    pub(crate) fn from_virt(microphone: virt::Microphone) -> Self {
+        Self::new(MicrophoneDevice::Virt(Box::new(microphone)))
+    }
+
+    /// Create a microphone that isn't connected to a sound card.  Periods are
+    /// paced by `clock` at `sample_rate`, and record `signal` (followed by
+    /// silence once it ends).
+    ///
+    /// ```
+    /// use fon::mono::Mono32;
+    /// use pasts::prelude::*;
+    /// use wavy::{Clock, Microphone};
+    ///
+    /// async fn run() {
+    ///     let signal = std::iter::repeat(Mono32::new(0.25)).take(100);
+    ///     let mut microphone =
+    ///         Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+    ///     let mut recorded = Vec::new();
+    ///     while recorded.len() < 128 {
+    ///         recorded.extend(microphone.next().await);
+    ///     }
+    ///     assert!(recorded[..100].iter().all(|f| *f == Mono32::new(0.25)));
+    ///     assert!(recorded[100..].iter().all(|f| *f == Mono32::new(0.0)));
+    /// }
+    ///
+    /// pasts::Executor::default().spawn(run());
+    /// ```
+    pub fn null<F, S>(clock: Clock, sample_rate: u32, signal: S) -> Self
+    where
+        F: Frame<Chan = Ch32>,
+        S: IntoIterator<Item = F>,
+        S::IntoIter: Send + 'static,
+    {
+        Self::from_virt(virt::Microphone::new(clock, sample_rate, signal))
+    }
+
+    /// Create a microphone that records the WAV file at `path` (followed by
+    /// silence once it ends).  The sample rate is the file's sample rate, and
+    /// periods are paced by `clock`.
+    pub fn wav<P: AsRef<Path>>(clock: Clock, path: P) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let reader = WavReader::open(path)?;
+        Ok(Self::from_virt(virt::Microphone::wav(
+            clock,
+            path.display().to_string(),
+            reader,
+        )))
+    }
+
     /// Try a reconfiguration of microphone.
     pub fn config<const C: usize>(
         self,
@@ -47,12 +385,282 @@ impl<const N: usize> Microphone<N> {
         Microphone<C>: MicrophoneProperties,
     {
         let bit = C - 1;
-        if (self.0.channels() & (1 << bit)) != 0 {
-            Ok(Microphone(self.0))
+        if (self.device.channels() & (1 << bit)) != 0 {
+            let mut microphone = Microphone::new(self.device);
+            microphone.gain = self.gain;
+            microphone.meter = self.meter;
+            microphone.requested_rate = self.requested_rate;
+            microphone.converter.set_quality(self.converter.quality());
+            microphone.channel_map =
+                self.channel_map.filter(|map| map.outputs() == C);
+            microphone.processing = self.processing;
+            Ok(microphone)
         } else {
             Err(self)
         }
     }
+
+    /// Get the sample rate of the microphone, or `None` if it isn't known
+    /// until the microphone is configured by the first period.
+    ///
+    /// This is the requested sample rate if one was set with
+    /// [`Microphone::set_sample_rate()`] (unless resampling is off).
+    pub fn sample_rate(&self) -> Option<f64> {
+        match self.requested_rate {
+            Some(sample_rate)
+                if self.converter.quality() != ResampleQuality::Off =>
+            {
+                Some(sample_rate.into())
+            }
+            _ => self.device.sample_rate(),
+        }
+    }
+
+    /// Get the configuration negotiated with the device, and what the
+    /// platform guarantees about it, or `None` until the microphone is
+    /// configured by the first period.
+    pub fn stream_config(&self) -> Option<StreamConfig> {
+        self.device.stream_config()
+    }
+
+    /// Take the error that stopped the microphone recording: a sound card
+    /// that can't be configured (such as one opened with
+    /// [`Microphone::query_exclusive()`] that rejects the [requested sample
+    /// rate](Microphone::set_sample_rate)).  The microphone records again
+    /// once another sample rate is requested.
+    pub fn take_error(&mut self) -> Option<std::io::Error> {
+        self.device.take_error()
+    }
+
+    /// Request a sample rate to record at.
+    ///
+    /// The sound card is asked for this rate when it's next configured (on
+    /// the first period, or after changing the number of channels); if it
+    /// records at another rate, audio is resampled to the requested rate,
+    /// keeping the converter's state between periods.  Virtual devices always
+    /// resample.
+    ///
+    /// ```
+    /// use fon::{mono::Mono32, Stream};
+    /// use pasts::prelude::*;
+    /// use wavy::{Clock, Microphone};
+    ///
+    /// async fn run() {
+    ///     let signal = std::iter::repeat(Mono32::new(0.25));
+    ///     let mut microphone =
+    ///         Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+    ///     microphone.set_sample_rate(16_000);
+    ///     assert_eq!(microphone.sample_rate(), Some(16_000.0));
+    ///     let stream = microphone.next().await;
+    ///     assert_eq!(stream.sample_rate(), Some(16_000.0));
+    ///
+    ///     // A period is a third as long after resampling.
+    ///     let mut frames = [Mono32::default(); 1_600];
+    ///     microphone.read_blocking(&mut frames);
+    ///     assert!(frames.iter().all(|f| *f == Mono32::new(0.25)));
+    /// }
+    ///
+    /// pasts::Executor::default().spawn(run());
+    /// ```
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.requested_rate = Some(sample_rate);
+        self.device.set_sample_rate(sample_rate);
+    }
+
+    /// Get how audio is converted to the requested sample rate.
+    pub fn resample_quality(&self) -> ResampleQuality {
+        self.converter.quality()
+    }
+
+    /// Set how audio is converted to the sample rate requested with
+    /// [`Microphone::set_sample_rate()`], if the sound card records at
+    /// another rate.  With [`ResampleQuality::Off`], audio is recorded at the
+    /// sound card's sample rate.
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.converter.set_quality(quality);
+    }
+
+    /// Get the software volume of the microphone.
+    pub fn volume(&self) -> f32 {
+        self.gain.volume()
+    }
+
+    /// Set the software volume of the microphone, as a linear gain applied to
+    /// audio recorded.  Changes are ramped over a few milliseconds so they
+    /// don't click.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.gain.set_volume(volume);
+    }
+
+    /// Check if the microphone is muted.
+    pub fn is_muted(&self) -> bool {
+        self.gain.is_muted()
+    }
+
+    /// Mute or unmute the microphone, without changing the volume.  Like volume
+    /// changes, muting is ramped so it doesn't click.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.gain.set_muted(muted);
+    }
+
+    /// Fade in to the volume over `duration`, starting from silence (or from
+    /// the current level, if fading out).
+    pub fn fade_in(&mut self, duration: Duration) {
+        self.gain.fade_in(duration);
+    }
+
+    /// Fade out to silence over `duration`.  The microphone stays silent until
+    /// faded in again.
+    ///
+    /// Fades move on by each period recorded, however much of it is read.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use fon::{mono::Mono32, Frame};
+    /// use pasts::prelude::*;
+    /// use wavy::{Clock, Microphone};
+    ///
+    /// async fn run() {
+    ///     let signal = std::iter::repeat(Mono32::new(0.5));
+    ///     let mut microphone =
+    ///         Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+    ///     // Start recording, and fade out over 48 frames.
+    ///     microphone.next().await;
+    ///     microphone.fade_out(Duration::from_millis(1));
+    ///     // Only read the first frame of a 64-frame period.
+    ///     let first = microphone.next().await.next().unwrap();
+    ///     assert!(f32::from(first.channels()[0]) > 0.4);
+    ///     assert!(microphone.is_faded_out());
+    ///     let next = microphone.next().await.next().unwrap();
+    ///     assert_eq!(next, Mono32::new(0.0));
+    /// }
+    ///
+    /// pasts::Executor::default().spawn(run());
+    /// ```
+    pub fn fade_out(&mut self, duration: Duration) {
+        self.gain.fade_out(duration);
+    }
+
+    /// Check if a fade out has finished, so the microphone can be stopped
+    /// without clicking.
+    pub fn is_faded_out(&self) -> bool {
+        self.gain.is_faded_out()
+    }
+
+    /// Enable metering of the levels recorded (after the software volume),
+    /// returning the meter.  Metering is off until this is first called, and
+    /// later calls return the same meter.
+    ///
+    /// Each period is metered whole, even if its stream isn't read to the end.
+    ///
+    /// ```
+    /// use fon::mono::Mono32;
+    /// use wavy::{Clock, Microphone};
+    ///
+    /// let signal = std::iter::repeat(Mono32::new(-0.5));
+    /// let mut microphone =
+    ///     Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+    /// let meter = microphone.meter();
+    /// microphone.read_block_blocking();
+    /// assert_eq!(meter.levels().peak(), &[0.5]);
+    /// assert_eq!(meter.levels().rms(), &[0.5]);
+    /// assert_eq!(meter.clips(), 0);
+    /// ```
+    pub fn meter(&mut self) -> Meter {
+        self.meter
+            .get_or_insert_with(Default::default)
+            .meter
+            .clone()
+    }
+
+    /// Get the echo canceller removing the echo of speakers from audio
+    /// recorded, if set (to adjust its delay).
+    pub fn echo_canceller(&mut self) -> Option<&mut EchoCanceller> {
+        self.processing.echo_canceller.as_mut()
+    }
+
+    /// Set (or with `None`, remove) the echo canceller removing the echo of
+    /// speakers from audio recorded.  It runs after channel mixing, before
+    /// conversion to a requested sample rate.
+    pub fn set_echo_canceller(&mut self, canceller: Option<EchoCanceller>) {
+        self.processing.echo_canceller = canceller;
+    }
+
+    /// Add a processor (such as a [`NoiseSuppressor`](crate::NoiseSuppressor)
+    /// or an [`AutomaticGainControl`](crate::AutomaticGainControl)) to run on
+    /// each period recorded, after the echo canceller and the processors
+    /// added before it.
+    pub fn add_processor<P: CaptureProcessor + 'static>(
+        &mut self,
+        processor: P,
+    ) {
+        self.processing.processors.push(Box::new(processor));
+    }
+
+    /// Remove the processors added to the microphone.
+    pub fn clear_processors(&mut self) {
+        self.processing.processors.clear();
+    }
+
+    /// Get the channel map audio recorded is mixed with.
+    pub fn channel_map(&self) -> Option<ChannelMap> {
+        self.channel_map.as_deref().copied()
+    }
+
+    /// Set how the sound card's channels are mixed to the microphone's
+    /// channels.  The sound card records the map's number of input channels
+    /// (1, 2 or 6), so a map can pick channels that the microphone's number
+    /// of channels wouldn't record.  The map is kept by
+    /// [`Microphone::config()`] if it still has the right number of outputs.
+    ///
+    /// Returns `false`, leaving the channel map unchanged, if the sound card
+    /// can't record the map's number of inputs.
+    ///
+    /// # Panics
+    /// If the map doesn't have `N` output channels.
+    ///
+    /// ```
+    /// use fon::{mono::Mono32, surround::Surround32};
+    /// use wavy::{ChannelMap, Clock, Microphone};
+    ///
+    /// let frame = Surround32::new(0.0, 0.0, 0.0, 0.5, 0.0, 0.0);
+    /// let signal = std::iter::repeat(frame);
+    /// let mut microphone =
+    ///     Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+    /// // Record channel 3 as mono.
+    /// let map = ChannelMap::new(6, 1).route(3, 0);
+    /// assert!(microphone.set_channel_map(Some(map)));
+    /// let mut frames = [Mono32::default(); 64];
+    /// microphone.read_blocking(&mut frames);
+    /// assert!(frames.iter().all(|f| *f == Mono32::new(0.5)));
+    /// ```
+    pub fn set_channel_map(&mut self, map: Option<ChannelMap>) -> bool {
+        if let Some(map) = map {
+            assert_eq!(map.outputs(), N, "Channel map has wrong outputs");
+            let supported = matches!(map.inputs(), 1 | 2 | 6)
+                && self.device.channels() & (1 << (map.inputs() - 1)) != 0;
+            if !supported {
+                return false;
+            }
+        }
+        self.channel_map = map.map(Box::new);
+        true
+    }
+
+    /// Get the position of each channel of the sound card, in the order of
+    /// frames' channels (empty until configured by the first period, for
+    /// virtual devices, and on platforms that don't report them).
+    pub fn channel_positions(&self) -> Vec<ChannelPosition> {
+        self.device.channel_positions()
+    }
+
+    /// Get the hardware capture volume controls of the sound card the
+    /// microphone is on (empty for virtual devices, and on platforms without
+    /// them).
+    pub fn volume_controls(&self) -> Vec<VolumeControl> {
+        self.device.volume_controls()
+    }
 }
 
 pub trait MicrophoneProperties {
@@ -60,15 +668,256 @@ pub trait MicrophoneProperties {
 }
 
 impl MicrophoneProperties for Microphone<1> {
-    type Sample = fon::mono::Mono32;
+    type Sample = Mono32;
 }
 
 impl MicrophoneProperties for Microphone<2> {
-    type Sample = fon::stereo::Stereo32;
+    type Sample = Stereo32;
 }
 
 impl MicrophoneProperties for Microphone<6> {
-    type Sample = fon::surround::Surround32;
+    type Sample = Surround32;
+}
+
+impl<const N: usize> Microphone<N>
+where
+    Microphone<N>: MicrophoneProperties,
+{
+    /// Read as many `frames` as have been recorded without blocking, and
+    /// return how many were read.
+    ///
+    /// Frames are recorded at [`Microphone::sample_rate()`].
+    pub fn try_read(
+        &mut self,
+        frames: &mut [<Self as MicrophoneProperties>::Sample],
+    ) -> usize {
+        let mut read = 0;
+        loop {
+            read += self.unstage(&mut frames[read..]);
+            if read == frames.len()
+                || blocking::poll_now(|cx| self.poll_read(cx)).is_pending()
+            {
+                return read;
+            }
+        }
+    }
+
+    /// Fill all of `frames`, blocking the thread until they've been recorded.
+    /// This doesn't require an async executor.
+    ///
+    /// Frames are recorded at [`Microphone::sample_rate()`].
+    ///
+    /// ```
+    /// use fon::mono::Mono32;
+    /// use wavy::{Clock, Microphone};
+    ///
+    /// let signal = std::iter::repeat(Mono32::new(0.25)).take(100);
+    /// let mut microphone =
+    ///     Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+    /// let mut frames = [Mono32::default(); 150];
+    /// microphone.read_blocking(&mut frames);
+    /// assert!(frames[..100].iter().all(|f| *f == Mono32::new(0.25)));
+    /// assert!(frames[100..].iter().all(|f| *f == Mono32::new(0.0)));
+    /// ```
+    pub fn read_blocking(
+        &mut self,
+        frames: &mut [<Self as MicrophoneProperties>::Sample],
+    ) {
+        let mut read = 0;
+        loop {
+            read += self.try_read(&mut frames[read..]);
+            if read == frames.len() {
+                return;
+            }
+            blocking::wait(|cx| self.poll_read(cx));
+        }
+    }
+
+    /// Move recorded frames into `frames`, returning how many were moved.
+    fn unstage(
+        &mut self,
+        frames: &mut [<Self as MicrophoneProperties>::Sample],
+    ) -> usize {
+        let count = (self.recorded.len() / N).min(frames.len());
+        for (dst, src) in frames.iter_mut().zip(self.recorded.chunks(N)) {
+            *dst = Frame::from_channels(src);
+        }
+        self.recorded.drain(..count * N);
+        self.position += count as u64;
+        count
+    }
+
+    /// Take the frames recorded so far as an owned [`AudioBlock`], waiting
+    /// for the next period if there are none.
+    ///
+    /// Blocks return their buffer to the microphone when dropped, so they
+    /// can be sent to another task without allocating in steady state.
+    ///
+    /// ```
+    /// use std::future::poll_fn;
+    ///
+    /// use fon::mono::Mono32;
+    /// use wavy::{Clock, Microphone};
+    ///
+    /// async fn run() {
+    ///     let signal = std::iter::repeat(Mono32::new(0.25));
+    ///     let mut microphone =
+    ///         Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+    ///     let first = poll_fn(|cx| microphone.poll_block(cx)).await;
+    ///     let second = poll_fn(|cx| microphone.poll_block(cx)).await;
+    ///     assert_eq!(first.len(), 64);
+    ///     assert_eq!(second.position(), 64);
+    ///     assert!(second.frames().all(|f| f == Mono32::new(0.25)));
+    /// }
+    ///
+    /// pasts::Executor::default().spawn(run());
+    /// ```
+    pub fn poll_block(
+        &mut self,
+        cx: &mut Exec<'_>,
+    ) -> Poll<AudioBlock<<Self as MicrophoneProperties>::Sample>> {
+        while self.recorded.is_empty() {
+            if self.poll_read(cx).is_pending() {
+                return Pending;
+            }
+        }
+        let sample_rate = self
+            .sample_rate()
+            .unwrap_or_else(|| crate::consts::SAMPLE_RATE.into());
+        let block = self.pool.block(&self.recorded, sample_rate, self.position);
+        self.position += block.len() as u64;
+        self.recorded.clear();
+        Ready(block)
+    }
+
+    /// Take the frames recorded so far as an owned [`AudioBlock`], blocking
+    /// the thread until the next period if there are none.
+    pub fn read_block_blocking(
+        &mut self,
+    ) -> AudioBlock<<Self as MicrophoneProperties>::Sample> {
+        blocking::wait(|cx| self.poll_block(cx))
+    }
+
+    /// Record the next period once the microphone is ready.
+    pub(crate) fn poll_read(&mut self, cx: &mut Exec<'_>) -> Poll<()> {
+        let stream = match Pin::new(&mut *self).poll_next(cx) {
+            Ready(stream) => stream,
+            Pending => return Pending,
+        };
+        for frame in stream {
+            self.recorded.extend_from_slice(frame.channels());
+        }
+        Ready(())
+    }
+}
+
+impl<const N: usize> Microphone<N> {
+    /// Record a period, mixed with the channel map if set.
+    fn record<F>(&mut self) -> StreamDevice<F>
+    where
+        F: Frame<Chan = Ch32>,
+    {
+        let map = match self.channel_map.as_deref().copied() {
+            Some(map) => map,
+            None => return self.device.record(),
+        };
+        match map.inputs() {
+            1 => mix(self.device.record::<Mono32>(), map, &self.pool),
+            2 => mix(self.device.record::<Stereo32>(), map, &self.pool),
+            _ => mix(self.device.record::<Surround32>(), map, &self.pool),
+        }
+    }
+
+    /// Remove the echo of the speakers from a period, and run the processors
+    /// on it, if an echo canceller or processors are set.
+    fn process<F>(&mut self, device: StreamDevice<F>) -> StreamDevice<F>
+    where
+        F: Frame<Chan = Ch32>,
+    {
+        let Processing {
+            echo_canceller,
+            processors,
+        } = &mut *self.processing;
+        if echo_canceller.is_none() && processors.is_empty() {
+            return device;
+        }
+        let sample_rate = device.sample_rate();
+        let rate =
+            sample_rate.unwrap_or_else(|| crate::consts::SAMPLE_RATE.into());
+        // Process a mixed period in place, or copy the period into a buffer
+        // from the pool.
+        let mut block = match device {
+            StreamDevice::Buffered(block, 0, _) => block,
+            device => {
+                let mut buffer = self.pool.buffer();
+                for frame in device {
+                    buffer.extend_from_slice(frame.channels());
+                }
+                self.pool.lend(buffer, rate, 0)
+            }
+        };
+        let audio = block.samples_mut();
+        if let Some(canceller) = echo_canceller {
+            let config = self.device.stream_config();
+            let latency = config.map_or(0, |config| config.latency);
+            canceller.process(audio, F::CHAN_COUNT, rate, latency);
+        }
+        for processor in processors.iter_mut() {
+            processor.process(audio, F::CHAN_COUNT, rate);
+        }
+        StreamDevice::Buffered(block, 0, sample_rate)
+    }
+
+    /// Convert a period to the requested sample rate, if it's recorded at
+    /// another.
+    fn resample<F>(&mut self, mut device: StreamDevice<F>) -> StreamDevice<F>
+    where
+        F: Frame<Chan = Ch32>,
+    {
+        let (source_rate, sample_rate) =
+            match (device.sample_rate(), self.sample_rate()) {
+                (Some(source), Some(target)) if source != target => {
+                    (source, target)
+                }
+                _ => return device,
+            };
+        let len = device.len().unwrap_or(crate::consts::PERIOD.into());
+        // The converter's phase may carry up to a frame over from the last
+        // period.
+        let capacity =
+            ((len + 1) as f64 * sample_rate / source_rate).ceil() as usize;
+        let mut buffer = self.pool.buffer();
+        buffer.resize(capacity * F::CHAN_COUNT, Ch32::MID);
+        let written = self.converter.stream(
+            Some(source_rate),
+            sample_rate,
+            &mut device,
+            frames_mut::<F>(&mut buffer),
+        );
+        buffer.truncate(written * F::CHAN_COUNT);
+        let block = self.pool.lend(buffer, sample_rate, 0);
+        StreamDevice::Buffered(block, 0, Some(sample_rate))
+    }
+}
+
+/// Mix a period recorded as `S` frames to `F` frames, in a buffer from
+/// `pool`.
+fn mix<S, F>(
+    stream: StreamDevice<S>,
+    map: ChannelMap,
+    pool: &BlockPool,
+) -> StreamDevice<F>
+where
+    S: Frame<Chan = Ch32>,
+    F: Frame<Chan = Ch32>,
+{
+    let sample_rate = stream.sample_rate();
+    let mut buffer = pool.buffer();
+    for frame in stream {
+        buffer.extend_from_slice(map.mix::<S, F>(frame).channels());
+    }
+    let rate = sample_rate.unwrap_or_else(|| crate::consts::SAMPLE_RATE.into());
+    StreamDevice::Buffered(pool.lend(buffer, rate, 0), 0, sample_rate)
 }
 
 impl<const N: usize> Notifier for Microphone<N>
@@ -79,16 +928,88 @@ where
 
     fn poll_next(self: Pin<&mut Self>, e: &mut Exec<'_>) -> Poll<Self::Event> {
         let this = self.get_mut();
-        if let Ready(()) = Pin::new(&mut this.0).poll(e) {
-            Ready(MicrophoneStream(this.0.record()))
+        if let Ready(()) = Pin::new(&mut this.device).poll(e) {
+            let device = this.record();
+            let device = this.process(device);
+            let device = this.resample(device);
+            // The volume ramps by the whole period, however much of it's
+            // streamed.
+            let rate = device
+                .sample_rate()
+                .unwrap_or_else(|| crate::consts::SAMPLE_RATE.into());
+            let frames = device.len().unwrap_or_default();
+            Ready(MicrophoneStream {
+                gain: this.gain.period(rate, frames),
+                device,
+                measure: this.meter.as_ref().map(|meter| meter.measure(N)),
+            })
         } else {
             Pending
         }
     }
 }
 
+/// Device backing a [`MicrophoneStream`].
+enum StreamDevice<F: Frame<Chan = Ch32>> {
+    Ffi(ffi::MicrophoneStream<F>),
+    Virt(virt::MicrophoneStream<F>),
+    /// A period mixed, processed or converted to another sample rate, in a
+    /// buffer from the microphone's pool, and the index of the next frame.
+    Buffered(AudioBlock<F>, usize, Option<f64>),
+}
+
+impl<F: Frame<Chan = Ch32>> Iterator for StreamDevice<F> {
+    type Item = F;
+
+    fn next(&mut self) -> Option<F> {
+        match self {
+            Self::Ffi(stream) => stream.next(),
+            Self::Virt(stream) => stream.next(),
+            Self::Buffered(block, index, _) => {
+                let start = *index * F::CHAN_COUNT;
+                let frame = block.samples().get(start..)?;
+                if frame.is_empty() {
+                    return None;
+                }
+                *index += 1;
+                Some(F::from_channels(frame))
+            }
+        }
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Stream<F> for StreamDevice<F> {
+    fn sample_rate(&self) -> Option<f64> {
+        match self {
+            Self::Ffi(stream) => stream.sample_rate(),
+            Self::Virt(stream) => stream.sample_rate(),
+            Self::Buffered(_, _, sample_rate) => *sample_rate,
+        }
+    }
+
+    fn len(&self) -> Option<usize> {
+        match self {
+            Self::Ffi(stream) => stream.len(),
+            Self::Virt(stream) => stream.len(),
+            Self::Buffered(block, index, _) => Some(block.len() - index),
+        }
+    }
+}
+
 /// A stream of recorded audio samples from a microphone.
-pub struct MicrophoneStream<F: Frame<Chan = Ch32>>(ffi::MicrophoneStream<F>);
+///
+/// The stream owns a copy of the last recorded period, so it may be kept
+/// after the microphone is polled again.  The microphone's volume is applied
+/// as frames are streamed, and if metering is enabled the rest of the period
+/// is metered when the stream is dropped.
+pub struct MicrophoneStream<F: Frame<Chan = Ch32>> {
+    /// Device the audio was recorded from.
+    device: StreamDevice<F>,
+    /// Software volume of the microphone over the period.
+    gain: Ramp,
+    /// Levels of the period, if metering is enabled.
+    measure: Option<Measure>,
+}
 
 impl<F: Frame<Chan = Ch32>> Debug for MicrophoneStream<F> {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> Result {
@@ -100,16 +1021,32 @@ impl<F: Frame<Chan = Ch32>> Iterator for MicrophoneStream<F> {
     type Item = F;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next()
+        let mut frame = self.device.next()?;
+        let gain = self.gain.next();
+        for chan in frame.channels_mut() {
+            *chan *= gain;
+        }
+        if let Some(measure) = &mut self.measure {
+            measure.frame(frame.channels());
+        }
+        Some(frame)
     }
 }
 
 impl<F: Frame<Chan = Ch32>> Stream<F> for MicrophoneStream<F> {
     fn sample_rate(&self) -> Option<f64> {
-        self.0.sample_rate()
+        self.device.sample_rate()
     }
 
     fn len(&self) -> Option<usize> {
-        self.0.len()
+        self.device.len()
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Drop for MicrophoneStream<F> {
+    fn drop(&mut self) {
+        if self.measure.is_some() {
+            self.for_each(drop);
+        }
     }
 }
@@ -0,0 +1,452 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Formatter, Result},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    task::Waker,
+};
+
+use fon::{chan::Ch32, Frame, Sink};
+use pasts::prelude::*;
+
+use crate::{resample::Converter, ResampleQuality};
+
+/// Identifies a voice added to a [`Mixer`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VoiceId(u64);
+
+/// Controls of a voice, stored as `f32` bits so the mixer reads them without
+/// locking.
+struct Controls {
+    /// Linear gain.
+    gain: AtomicU32,
+    /// Pan, from -1.0 (left) to 1.0 (right).
+    pan: AtomicU32,
+    /// Set when the voice is removed through a handle.
+    removed: AtomicBool,
+}
+
+/// A sound source playing through a [`Mixer`].
+struct Voice<F: Frame<Chan = Ch32>> {
+    /// Identifier given out when the voice was added.
+    id: VoiceId,
+    /// Frames of the voice, converted to the mixer's frame type.
+    source: Box<dyn Iterator<Item = F> + Send>,
+    /// Sample rate of the source.
+    sample_rate: f64,
+    /// Gain and pan, shared with the handles.
+    controls: Arc<Controls>,
+    /// Converts the source to the sink's sample rate.
+    converter: Converter,
+    /// Whether the source ran out of frames.
+    ended: bool,
+    /// Frames of silence left to stream after the source ended, so the
+    /// converter outputs its last frames.
+    tail: usize,
+}
+
+impl<F: Frame<Chan = Ch32>> Voice<F> {
+    /// Convert the next frames of the voice to `sample_rate`, overwriting the
+    /// start of `output`, and return how many frames were written (fewer than
+    /// the length of `output` once the voice is done).
+    fn stream(&mut self, sample_rate: f64, output: &mut [F]) -> usize {
+        let tail = self.converter.tail();
+        let (source, ended, remaining) =
+            (&mut self.source, &mut self.ended, &mut self.tail);
+        let frames = std::iter::from_fn(|| {
+            if !*ended {
+                if let Some(frame) = source.next() {
+                    return Some(frame);
+                }
+                *ended = true;
+                *remaining = tail;
+            }
+            if *remaining == 0 {
+                return None;
+            }
+            *remaining -= 1;
+            Some(F::default())
+        });
+        self.converter.stream(
+            Some(self.sample_rate),
+            sample_rate,
+            frames,
+            output,
+        )
+    }
+
+    /// Get the gain of each channel, after panning.
+    fn amplitude(&self) -> F {
+        let gain = f32::from_bits(self.controls.gain.load(Ordering::Relaxed));
+        let pan = f32::from_bits(self.controls.pan.load(Ordering::Relaxed));
+        let left = gain * (1.0 - pan).min(1.0);
+        let right = gain * (1.0 + pan).min(1.0);
+        let mut amplitude = F::from_channel(Ch32::new(gain));
+        // Stereo is left, right; 5.1 is rear left, front left, center, front
+        // right, rear right, LFE (center and LFE aren't panned).
+        let (lefts, rights): (&[usize], &[usize]) = match F::CHAN_COUNT {
+            2 => (&[0], &[1]),
+            6 => (&[0, 1], &[3, 4]),
+            _ => (&[], &[]),
+        };
+        for &i in lefts {
+            amplitude.channels_mut()[i] = Ch32::new(left);
+        }
+        for &i in rights {
+            amplitude.channels_mut()[i] = Ch32::new(right);
+        }
+        amplitude
+    }
+}
+
+/// State shared between the mixer and its handles, only locked briefly by
+/// the mixer at the start and end of a period.
+struct Shared<F: Frame<Chan = Ch32>> {
+    /// Voices added since the last period, not yet taken by the mixer.
+    added: Vec<Voice<F>>,
+    /// Controls of the voices that are playing.
+    playing: Vec<(VoiceId, Arc<Controls>)>,
+    /// Identifier for the next voice.
+    next_id: u64,
+    /// Voices that finished, not yet produced by a handle.
+    finished: VecDeque<VoiceId>,
+    /// Handles waiting for a voice to finish.
+    wakers: Vec<Waker>,
+}
+
+/// Mixes any number of voices into a [`SpeakersSink`](crate::SpeakersSink)
+/// each period.
+///
+/// Voices are added and controlled through a [`MixerHandle`], which can be
+/// sent to other tasks or threads.  Each voice is resampled from its own
+/// sample rate (see [`Mixer::set_resample_quality()`]), and has its own gain
+/// and pan.
+///
+/// ```
+/// use fon::mono::Mono32;
+/// use pasts::prelude::*;
+/// use wavy::{Clock, Mixer, Speakers};
+///
+/// async fn run() {
+///     let mut speakers = Speakers::<1>::null(Clock::Virtual, 48_000);
+///     let mut mixer = Mixer::<Mono32>::new();
+///     let mut handle = mixer.handle();
+///     let quiet = [Mono32::new(0.25); 32];
+///     let voice = handle.add(48_000, [Mono32::new(0.5); 32]);
+///     handle.add(48_000, quiet);
+///     handle.set_gain(voice, 0.5);
+///
+///     mixer.mix(&mut speakers.next().await);
+///     assert_eq!(handle.next().await, voice);
+///
+///     speakers.next().await;
+///     let output = speakers.take_output().unwrap();
+///     assert!(output.iter().take(32).all(|f| *f == Mono32::new(0.5)));
+///     assert!(output.iter().skip(32).all(|f| *f == Mono32::new(0.0)));
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+pub struct Mixer<F: Frame<Chan = Ch32>> {
+    /// State shared with the handles.
+    shared: Arc<Mutex<Shared<F>>>,
+    /// Voices being mixed.
+    voices: Vec<Voice<F>>,
+    /// How voices are resampled.
+    quality: ResampleQuality,
+    /// Frames of a voice converted for the period, before they're added.
+    scratch: Vec<F>,
+}
+
+impl<F: Frame<Chan = Ch32>> Debug for Mixer<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let voices = self.shared.lock().unwrap().playing.len();
+        write!(f, "Mixer(voices: {})", voices)
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Default for Mixer<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Mixer<F> {
+    /// Create a mixer with no voices.
+    pub fn new() -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Shared {
+                added: Vec::new(),
+                playing: Vec::new(),
+                next_id: 0,
+                finished: VecDeque::new(),
+                wakers: Vec::new(),
+            })),
+            voices: Vec::new(),
+            quality: ResampleQuality::default(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Get how voices at other sample rates than the sink are converted.
+    pub fn resample_quality(&self) -> ResampleQuality {
+        self.quality
+    }
+
+    /// Set how voices at other sample rates than the sink are converted, for
+    /// the voices playing and those added later.
+    ///
+    /// ```
+    /// use fon::{mono::Mono32, Audio, Frame};
+    /// use wavy::{Mixer, ResampleQuality};
+    ///
+    /// let mut mixer = Mixer::<Mono32>::new();
+    /// mixer.set_resample_quality(ResampleQuality::Sinc(32));
+    /// let handle = mixer.handle();
+    /// let voice = handle.add(24_000, [Mono32::new(0.5); 480]);
+    ///
+    /// let mut audio = Audio::<Mono32>::with_silence(48_000, 1024);
+    /// mixer.mix(&mut audio.sink(..));
+    /// assert!(!handle.is_playing(voice));
+    /// // Settled after the filter's latency, and silent after the voice.
+    /// let middle = audio.get(480).unwrap().channels()[0];
+    /// assert!((f32::from(middle) - 0.5).abs() < 0.01);
+    /// assert_eq!(audio.get(1000).unwrap(), Mono32::default());
+    /// ```
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.quality = quality;
+        for voice in &mut self.voices {
+            voice.converter.set_quality(quality);
+        }
+    }
+
+    /// Get a handle to add and control voices.
+    pub fn handle(&self) -> MixerHandle<F> {
+        MixerHandle(self.shared.clone())
+    }
+
+    /// Overwrite the buffer of `sink` with the sum of all voices, resampled
+    /// to the sink's sample rate.  Voices that run out of frames are removed,
+    /// and produced by the [`MixerHandle`] notifier.
+    pub fn mix<S: Sink<F>>(&mut self, sink: &mut S) {
+        let start = self.voices.len();
+        self.voices.append(&mut self.shared.lock().unwrap().added);
+        for voice in &mut self.voices[start..] {
+            voice.converter.set_quality(self.quality);
+        }
+        self.voices
+            .retain(|voice| !voice.controls.removed.load(Ordering::Relaxed));
+        let sample_rate = sink.sample_rate();
+        let buffer = sink.buffer();
+        for frame in buffer.iter_mut() {
+            *frame = F::default();
+        }
+        self.scratch.resize(buffer.len(), F::default());
+        let mut finished = Vec::new();
+        for voice in &mut self.voices {
+            let amplitude = voice.amplitude();
+            let written = voice.stream(sample_rate, &mut self.scratch);
+            for (frame, src) in buffer.iter_mut().zip(&self.scratch[..written])
+            {
+                *frame += *src * amplitude;
+            }
+            if written < buffer.len() {
+                finished.push(voice.id);
+            }
+        }
+        if finished.is_empty() {
+            return;
+        }
+        self.voices.retain(|voice| !finished.contains(&voice.id));
+        let mut shared = self.shared.lock().unwrap();
+        shared.playing.retain(|(id, _)| !finished.contains(id));
+        shared.finished.extend(finished);
+        for waker in shared.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Handle to add and control the voices of a [`Mixer`].  Notifier produces
+/// voices that finished playing.
+///
+/// Handles can be cloned; each finished voice is produced by only one of
+/// them.
+///
+/// Every handle waiting for a voice is woken when one finishes.
+///
+/// ```
+/// use std::{
+///     sync::{
+///         atomic::{AtomicBool, Ordering},
+///         Arc,
+///     },
+///     task::{Wake, Waker},
+/// };
+///
+/// use fon::{mono::Mono32, Audio};
+/// use pasts::prelude::*;
+/// use wavy::Mixer;
+///
+/// struct Flag(AtomicBool);
+///
+/// impl Wake for Flag {
+///     fn wake(self: Arc<Self>) {
+///         self.0.store(true, Ordering::SeqCst);
+///     }
+/// }
+///
+/// let mut mixer = Mixer::<Mono32>::new();
+/// let mut handles = [mixer.handle(), mixer.handle()];
+/// handles[0].add(48_000, [Mono32::new(0.5); 8]);
+/// let flags = [(); 2].map(|_| Arc::new(Flag(AtomicBool::new(false))));
+/// for (handle, flag) in handles.iter_mut().zip(&flags) {
+///     let waker = Waker::from(flag.clone());
+///     let mut cx = Exec::from_waker(&waker);
+///     assert!(Pin::new(handle).poll_next(&mut cx).is_pending());
+/// }
+///
+/// let mut audio = Audio::<Mono32>::with_silence(48_000, 16);
+/// mixer.mix(&mut audio.sink(..));
+/// assert!(flags.iter().all(|flag| flag.0.load(Ordering::SeqCst)));
+/// ```
+pub struct MixerHandle<F: Frame<Chan = Ch32>>(Arc<Mutex<Shared<F>>>);
+
+impl<F: Frame<Chan = Ch32>> Clone for MixerHandle<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Debug for MixerHandle<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let voices = self.0.lock().unwrap().playing.len();
+        write!(f, "MixerHandle(voices: {})", voices)
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> MixerHandle<F> {
+    /// Start playing the frames of `source` (which may be any owned
+    /// [`Stream`](fon::Stream)) recorded at `sample_rate`, at unity gain and
+    /// centered.
+    pub fn add<G, S>(&self, sample_rate: u32, source: S) -> VoiceId
+    where
+        G: Frame<Chan = Ch32>,
+        S: IntoIterator<Item = G>,
+        S::IntoIter: Send + 'static,
+    {
+        let source = source.into_iter().map(Frame::convert);
+        let controls = Arc::new(Controls {
+            gain: AtomicU32::new(1.0f32.to_bits()),
+            pan: AtomicU32::new(0.0f32.to_bits()),
+            removed: AtomicBool::new(false),
+        });
+        let mut shared = self.0.lock().unwrap();
+        let id = VoiceId(shared.next_id);
+        shared.next_id += 1;
+        shared.playing.push((id, controls.clone()));
+        shared.added.push(Voice {
+            id,
+            source: Box::new(source),
+            sample_rate: sample_rate.into(),
+            controls,
+            converter: Converter::default(),
+            ended: false,
+            tail: 0,
+        });
+        id
+    }
+
+    /// Stop playing a voice, without producing it from the notifier.  Returns
+    /// false if the voice already finished.
+    pub fn remove(&self, voice: VoiceId) -> bool {
+        let mut shared = self.0.lock().unwrap();
+        let index = shared.playing.iter().position(|(id, _)| *id == voice);
+        if let Some(index) = index {
+            let (_, controls) = shared.playing.swap_remove(index);
+            controls.removed.store(true, Ordering::Relaxed);
+        }
+        index.is_some()
+    }
+
+    /// Check if a voice is still playing.
+    pub fn is_playing(&self, voice: VoiceId) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .playing
+            .iter()
+            .any(|(id, _)| *id == voice)
+    }
+
+    /// Set the linear gain of a voice.
+    pub fn set_gain(&self, voice: VoiceId, gain: f32) {
+        self.with_controls(voice, |c| {
+            c.gain.store(gain.to_bits(), Ordering::Relaxed)
+        });
+    }
+
+    /// Set the pan of a voice, from -1.0 (left) to 1.0 (right).  Has no
+    /// effect on mono output, nor on the center and LFE of 5.1 surround.
+    ///
+    /// ```
+    /// use fon::{chan::Ch32, surround::Surround32, Audio, Frame};
+    /// use wavy::Mixer;
+    ///
+    /// let mut mixer = Mixer::<Surround32>::new();
+    /// let handle = mixer.handle();
+    /// let frame = Surround32::from_channel(Ch32::new(0.5));
+    /// let voice = handle.add(48_000, [frame; 8]);
+    /// handle.set_pan(voice, -1.0);
+    ///
+    /// let mut audio = Audio::<Surround32>::with_silence(48_000, 4);
+    /// mixer.mix(&mut audio.sink(..));
+    /// // Rear left, front left, center, front right, rear right, LFE.
+    /// let expected = [0.5, 0.5, 0.5, 0.0, 0.0, 0.5].map(Ch32::new);
+    /// assert_eq!(audio.get(3).unwrap().channels(), &expected);
+    /// ```
+    pub fn set_pan(&self, voice: VoiceId, pan: f32) {
+        let pan = pan.clamp(-1.0, 1.0);
+        self.with_controls(voice, |c| {
+            c.pan.store(pan.to_bits(), Ordering::Relaxed)
+        });
+    }
+
+    /// Modify the controls of a voice, if it's still playing.
+    fn with_controls(&self, voice: VoiceId, f: impl FnOnce(&Controls)) {
+        let shared = self.0.lock().unwrap();
+        if let Some((_, controls)) =
+            shared.playing.iter().find(|(id, _)| *id == voice)
+        {
+            f(controls);
+        }
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Notifier for MixerHandle<F> {
+    type Event = VoiceId;
+
+    fn poll_next(self: Pin<&mut Self>, e: &mut Exec<'_>) -> Poll<VoiceId> {
+        let mut shared = self.0.lock().unwrap();
+        if let Some(voice) = shared.finished.pop_front() {
+            Ready(voice)
+        } else {
+            let waker = e.waker();
+            if !shared.wakers.iter().any(|w| w.will_wake(waker)) {
+                shared.wakers.push(waker.clone());
+            }
+            Pending
+        }
+    }
+}
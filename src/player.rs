@@ -0,0 +1,552 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Formatter},
+    io::{Error, Read, Result, Seek},
+    path::Path,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    task::Waker,
+    thread,
+    time::Duration,
+};
+
+use fon::{chan::Ch32, Frame, Sink};
+use pasts::prelude::*;
+
+use crate::{resample::Converter, wav::WavReader, ResampleQuality};
+
+/// Frames decoded at a time.
+const BLOCK_SIZE: usize = 4096;
+/// Frames decoded ahead of playback.
+const READ_AHEAD: usize = 16 * BLOCK_SIZE;
+
+/// Decoded frames, starting at a position in the file.
+struct Block<F> {
+    /// Position of the first frame.
+    start: u64,
+    /// The frames.
+    frames: Vec<F>,
+}
+
+/// Playback state shared between the player, its handles and the decoder.
+struct State<F> {
+    /// Blocks decoded ahead of playback.
+    queue: VecDeque<Block<F>>,
+    /// Number of frames in `queue`.
+    queued: usize,
+    /// Buffers to reuse for blocks.
+    spare: Vec<Vec<F>>,
+    /// Position the decoder should move to.
+    seek: Option<u64>,
+    /// Incremented on each seek, so the player drops what it was playing.
+    generation: u64,
+    /// Whether the decoder reached the end of the file (and isn't looping),
+    /// or stopped early because of an error.
+    ended: bool,
+    /// The error that stopped the decoder, not yet produced by a handle.
+    error: Option<Error>,
+    /// Whether playback reached the end of the file.
+    at_end: bool,
+    /// Whether audio is playing (rather than paused).
+    playing: bool,
+    /// Whether playback restarts at the end of the file.
+    looping: bool,
+    /// Position of the next frame played.
+    position: u64,
+    /// Whether reaching the end hasn't been produced by a handle yet.
+    finished: bool,
+    /// Handle waiting for the end.
+    waker: Option<Waker>,
+    /// Whether the player was dropped, so the decoder should stop.
+    quit: bool,
+}
+
+/// State shared between the player, its handles and the decoder.
+struct Shared<F> {
+    /// Playback state.
+    state: Mutex<State<F>>,
+    /// Wakes the decoder when there's space in the queue, or on a seek.
+    decoder: Condvar,
+    /// Sample rate of the file.
+    sample_rate: u32,
+    /// Number of frames in the file.
+    len: u64,
+}
+
+impl<F> Shared<F> {
+    fn lock(&self) -> MutexGuard<'_, State<F>> {
+        self.state.lock().unwrap()
+    }
+
+    /// Move to `position`, dropping decoded blocks.
+    fn seek(&self, state: &mut State<F>, position: u64) {
+        let position = position.min(self.len);
+        let blocks = state.queue.drain(..).map(|block| block.frames);
+        let blocks: Vec<_> = blocks.collect();
+        state.spare.extend(blocks);
+        state.queued = 0;
+        state.seek = Some(position);
+        state.generation += 1;
+        state.ended = false;
+        state.error = None;
+        state.at_end = false;
+        state.position = position;
+        self.decoder.notify_one();
+    }
+}
+
+/// Decode `reader` into the queue until the player is dropped.
+fn decode<F, R>(shared: Arc<Shared<F>>, mut reader: WavReader<R>)
+where
+    F: Frame<Chan = Ch32>,
+    R: Read + Seek,
+{
+    let mut position = 0;
+    loop {
+        let mut state = shared.lock();
+        while !state.quit
+            && state.seek.is_none()
+            && (state.ended || state.queued >= READ_AHEAD)
+        {
+            state = shared.decoder.wait(state).unwrap();
+        }
+        if state.quit {
+            return;
+        }
+        if let Some(seek) = state.seek.take() {
+            drop(state);
+            position = seek;
+            if let Err(error) = reader.seek(seek) {
+                fail(&shared, error);
+            }
+            continue;
+        }
+        let mut frames = state.spare.pop().unwrap_or_default();
+        drop(state);
+
+        frames.clear();
+        frames
+            .extend(reader.by_ref().take(BLOCK_SIZE).map(|f| f.convert::<F>()));
+        let start = position;
+        position += frames.len() as u64;
+        let complete = frames.len() == BLOCK_SIZE;
+        let error = reader.take_error();
+
+        let mut state = shared.lock();
+        if state.seek.is_some() {
+            state.spare.push(frames);
+            continue;
+        }
+        let rewind = !complete && state.looping && error.is_none();
+        state.ended = !complete && !rewind;
+        if error.is_some() {
+            state.error = error;
+        }
+        if frames.is_empty() {
+            state.spare.push(frames);
+        } else {
+            state.queued += frames.len();
+            state.queue.push_back(Block { start, frames });
+        }
+        drop(state);
+        if rewind {
+            position = 0;
+            if let Err(error) = reader.seek(0) {
+                fail(&shared, error);
+            }
+        }
+    }
+}
+
+/// Stop decoding because of `error`, which is produced by a handle once
+/// playback reaches where the decoder stopped.
+fn fail<F>(shared: &Shared<F>, error: Error) {
+    let mut state = shared.lock();
+    if state.seek.is_none() {
+        state.ended = true;
+        state.error = Some(error);
+    }
+}
+
+/// Plays a sound file into a [`SpeakersSink`](crate::SpeakersSink) each
+/// period, resampled to the sink's sample rate (see
+/// [`Player::set_resample_quality()`]).
+///
+/// The file is decoded ahead of playback on a background thread, so reading
+/// it never blocks the audio.  Playback is controlled through a
+/// [`PlayerHandle`], which can be sent to other tasks or threads.
+///
+/// ```
+/// use fon::mono::Mono32;
+/// use pasts::prelude::*;
+/// use wavy::{Clock, Player, Speakers, WavFormat};
+///
+/// async fn run() {
+///     let path = std::env::temp_dir().join("wavy-doc-player.wav");
+///     let mut speakers: Speakers<1> =
+///         Speakers::wav(Clock::Virtual, 24_000, &path, WavFormat::Float32)
+///             .unwrap();
+///     speakers.write_blocking(&[Mono32::new(0.5); 2400]);
+///     speakers.flush_blocking();
+///     drop(speakers);
+///
+///     // Played at 48 kHz, each frame of the file is output about twice.
+///     let mut player = Player::<Mono32>::wav(&path).unwrap();
+///     let mut handle = player.handle();
+///     let mut speakers = Speakers::<1>::null(Clock::Virtual, 48_000);
+///     while handle.is_playing() {
+///         player.stream(&mut speakers.next().await);
+///     }
+///     handle.next().await.unwrap();
+///     assert_eq!(handle.position(), handle.duration());
+///     speakers.next().await;
+///     let output = speakers.take_output().unwrap();
+///     let played = output.iter().filter(|f| **f == Mono32::new(0.5));
+///     assert!((4790..=4800).contains(&played.count()));
+///     std::fs::remove_file(path).unwrap();
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+pub struct Player<F: Frame<Chan = Ch32>> {
+    /// State shared with handles and the decoder.
+    shared: Arc<Shared<F>>,
+    /// Block being played.
+    block: Vec<F>,
+    /// Index of the next frame of `block`.
+    index: usize,
+    /// Position of the first frame of `block`.
+    start: u64,
+    /// Generation of the state the block was taken from.
+    generation: u64,
+    /// Converts the file to the sink's sample rate.
+    converter: Converter,
+    /// Frames of silence streamed after the end of the file, so the
+    /// converter outputs its last frames.
+    flushed: usize,
+}
+
+impl<F: Frame<Chan = Ch32>> Debug for Player<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Player(rate: {})", self.shared.sample_rate)
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Drop for Player<F> {
+    fn drop(&mut self) {
+        self.shared.lock().quit = true;
+        self.shared.decoder.notify_one();
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Player<F> {
+    /// Play the WAV file at `path`, starting from the beginning.
+    pub fn wav<P: AsRef<Path>>(path: P) -> Result<Self>
+    where
+        F: Send + 'static,
+    {
+        Self::new(WavReader::open(path.as_ref())?)
+    }
+
+    /// Play a WAV file from `reader`, starting from the beginning.
+    pub fn wav_reader<R>(reader: R) -> Result<Self>
+    where
+        F: Send + 'static,
+        R: Read + Seek + Send + 'static,
+    {
+        Self::new(WavReader::new(reader)?)
+    }
+
+    /// Start decoding `reader` on a new thread.
+    fn new<R>(reader: WavReader<R>) -> Result<Self>
+    where
+        F: Send + 'static,
+        R: Read + Seek + Send + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                queued: 0,
+                spare: Vec::new(),
+                seek: None,
+                generation: 0,
+                ended: false,
+                error: None,
+                at_end: false,
+                playing: true,
+                looping: false,
+                position: 0,
+                finished: false,
+                waker: None,
+                quit: false,
+            }),
+            decoder: Condvar::new(),
+            sample_rate: reader.sample_rate(),
+            len: reader.len(),
+        });
+        let decoder = shared.clone();
+        thread::Builder::new()
+            .name("wavy-player".to_string())
+            .spawn(move || decode(decoder, reader))?;
+        Ok(Self {
+            shared,
+            block: Vec::new(),
+            index: 0,
+            start: 0,
+            generation: 0,
+            converter: Converter::default(),
+            flushed: 0,
+        })
+    }
+
+    /// Get how files at other sample rates than the sink are converted.
+    pub fn resample_quality(&self) -> ResampleQuality {
+        self.converter.quality()
+    }
+
+    /// Set how files at other sample rates than the sink are converted.
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.converter.set_quality(quality);
+    }
+
+    /// Get a handle to control playback.
+    pub fn handle(&self) -> PlayerHandle<F> {
+        PlayerHandle(self.shared.clone())
+    }
+
+    /// Overwrite the buffer of `sink` with the next frames of the file,
+    /// resampled to the sink's sample rate (or silence, if paused).  If
+    /// decoding falls behind, the rest of the buffer is left silent.
+    pub fn stream<S: Sink<F>>(&mut self, sink: &mut S) {
+        let output_rate = sink.sample_rate();
+        let buffer = sink.buffer();
+        for frame in buffer.iter_mut() {
+            *frame = F::default();
+        }
+        let shared = self.shared.clone();
+        let mut state = shared.lock();
+        if state.generation != self.generation {
+            self.generation = state.generation;
+            let block = std::mem::take(&mut self.block);
+            state.spare.push(block);
+            self.index = 0;
+            self.start = state.position;
+            self.converter.reset();
+            self.flushed = 0;
+        }
+        if !state.playing {
+            return;
+        }
+        let tail = self.converter.tail();
+        let mut converter = std::mem::take(&mut self.converter);
+        let mut flushed = self.flushed;
+        let frames = std::iter::from_fn(|| {
+            if let Some(frame) = self.advance(&mut state) {
+                return Some(frame);
+            }
+            // Once the file ended, flush the converter with silence (otherwise
+            // wait for the decoder).
+            if state.ended && flushed < tail {
+                flushed += 1;
+                return Some(F::default());
+            }
+            None
+        });
+        let sample_rate = f64::from(shared.sample_rate);
+        let written =
+            converter.stream(Some(sample_rate), output_rate, frames, buffer);
+        self.converter = converter;
+        self.flushed = flushed;
+        state.position = self.start + self.index as u64;
+        if written < buffer.len()
+            && state.ended
+            && state.queue.is_empty()
+            && self.index == self.block.len()
+            && flushed == tail
+        {
+            // Reached the end of the file (or where decoding failed).
+            state.playing = false;
+            state.at_end = true;
+            state.finished = true;
+            if state.error.is_none() {
+                state.position = shared.len;
+            }
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Take the next decoded frame, or `None` if there isn't one yet (or
+    /// the file ended).
+    fn advance(&mut self, state: &mut State<F>) -> Option<F> {
+        if self.index == self.block.len() {
+            let block = state.queue.pop_front()?;
+            state.queued -= block.frames.len();
+            let old = std::mem::replace(&mut self.block, block.frames);
+            state.spare.push(old);
+            self.index = 0;
+            self.start = block.start;
+            self.shared.decoder.notify_one();
+        }
+        let frame = self.block[self.index];
+        self.index += 1;
+        Some(frame)
+    }
+}
+
+/// Handle to control a [`Player`].  Notifier produces when playback reaches
+/// the end of the file (never, if looping), or an error if the file couldn't
+/// be read, in which case playback stops where decoding did.
+///
+/// Handles can be cloned; reaching the end is produced by only one of them.
+///
+/// ```
+/// use std::{
+///     io::{Cursor, Error, ErrorKind, Read, Result, Seek, SeekFrom},
+///     time::Duration,
+/// };
+///
+/// use fon::mono::Mono32;
+/// use pasts::prelude::*;
+/// use wavy::{Clock, Player, Speakers, WavFormat};
+///
+/// /// A file that can't be read past 1000 bytes of audio (250 frames).
+/// struct Failing(Cursor<Vec<u8>>);
+///
+/// impl Read for Failing {
+///     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+///         let left = 44 + 1000 - self.0.position() as usize;
+///         if left == 0 {
+///             return Err(Error::other("disk removed"));
+///         }
+///         let len = left.min(buf.len());
+///         self.0.read(&mut buf[..len])
+///     }
+/// }
+///
+/// impl Seek for Failing {
+///     fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+///         self.0.seek(pos)
+///     }
+/// }
+///
+/// async fn run() {
+///     let path = std::env::temp_dir().join("wavy-doc-player-error.wav");
+///     let mut speakers: Speakers<1> =
+///         Speakers::wav(Clock::Virtual, 24_000, &path, WavFormat::Float32)
+///             .unwrap();
+///     speakers.write_blocking(&[Mono32::new(0.5); 2400]);
+///     speakers.flush_blocking();
+///     drop(speakers);
+///     let file = Failing(Cursor::new(std::fs::read(&path).unwrap()));
+///     std::fs::remove_file(path).unwrap();
+///
+///     let mut player = Player::<Mono32>::wav_reader(file).unwrap();
+///     let mut handle = player.handle();
+///     let mut speakers = Speakers::<1>::null(Clock::Virtual, 24_000);
+///     while handle.is_playing() {
+///         player.stream(&mut speakers.next().await);
+///     }
+///     let error = handle.next().await.unwrap_err();
+///     assert_eq!(error.kind(), ErrorKind::Other);
+///     // Playback stops where the file stopped being readable.
+///     let position = handle.position().as_secs_f64() * 24_000.0;
+///     assert_eq!(position.round(), 250.0);
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+pub struct PlayerHandle<F>(Arc<Shared<F>>);
+
+impl<F> Clone for PlayerHandle<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<F> Debug for PlayerHandle<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PlayerHandle(rate: {})", self.0.sample_rate)
+    }
+}
+
+impl<F> PlayerHandle<F> {
+    /// Resume playback (from the beginning, if it reached the end).
+    pub fn play(&self) {
+        let mut state = self.0.lock();
+        if state.at_end {
+            self.0.seek(&mut state, 0);
+        }
+        state.playing = true;
+    }
+
+    /// Pause playback.
+    pub fn pause(&self) {
+        self.0.lock().playing = false;
+    }
+
+    /// Check if audio is playing (rather than paused or at the end).
+    pub fn is_playing(&self) -> bool {
+        self.0.lock().playing
+    }
+
+    /// Move playback to `position` from the start of the file.
+    pub fn seek(&self, position: Duration) {
+        let frame = position.as_secs_f64() * f64::from(self.0.sample_rate);
+        self.0.seek(&mut self.0.lock(), frame.round() as u64);
+    }
+
+    /// Set whether playback restarts at the end of the file.
+    pub fn set_looping(&self, looping: bool) {
+        let mut state = self.0.lock();
+        state.looping = looping;
+        // The decoder may have stopped at the end already.
+        if looping && state.ended && !state.at_end {
+            state.ended = false;
+            state.seek = Some(0);
+            self.0.decoder.notify_one();
+        }
+    }
+
+    /// Check if playback restarts at the end of the file.
+    pub fn is_looping(&self) -> bool {
+        self.0.lock().looping
+    }
+
+    /// Get the position of playback from the start of the file.
+    pub fn position(&self) -> Duration {
+        let position = self.0.lock().position;
+        Duration::from_secs_f64(position as f64 / f64::from(self.0.sample_rate))
+    }
+
+    /// Get the length of the file.
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(
+            self.0.len as f64 / f64::from(self.0.sample_rate),
+        )
+    }
+}
+
+impl<F> Notifier for PlayerHandle<F> {
+    type Event = Result<()>;
+
+    fn poll_next(self: Pin<&mut Self>, e: &mut Exec<'_>) -> Poll<Result<()>> {
+        let mut state = self.0.lock();
+        if state.finished {
+            state.finished = false;
+            Ready(state.error.take().map_or(Ok(()), Err))
+        } else {
+            state.waker = Some(e.waker().clone());
+            Pending
+        }
+    }
+}
@@ -0,0 +1,338 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use fon::chan::Ch32;
+
+/// Time the power of the audio is smoothed over by the noise suppressor, in
+/// seconds.
+const NOISE_POWER_TIME: f64 = 0.01;
+
+/// Length of the windows the quietest power is found in, which the noise
+/// floor is from, in seconds.
+const NOISE_WINDOW: f64 = 0.75;
+
+/// Lowest noise floor (-100 dBFS), so it can rise back from digital silence.
+const NOISE_FLOOR_MIN: f32 = 1.0e-10;
+
+/// Time the noise suppressor takes to stop attenuating, in seconds.
+const NOISE_ATTACK_TIME: f64 = 0.001;
+
+/// Time the noise suppressor takes to attenuate, in seconds.
+const NOISE_RELEASE_TIME: f64 = 0.05;
+
+/// Time the power of the audio is smoothed over by the automatic gain
+/// control, in seconds.
+const AGC_POWER_TIME: f64 = 0.1;
+
+/// Time the automatic gain control takes to lower the gain, in seconds.
+const AGC_ATTACK_TIME: f64 = 0.01;
+
+/// How fast the automatic gain control raises the gain, in decibels per
+/// second.
+const AGC_RISE: f64 = 6.0;
+
+/// Power below which the automatic gain control holds the gain (-60 dBFS),
+/// so silence isn't amplified.
+const AGC_SILENCE: f32 = 1.0e-6;
+
+/// Get the coefficient of a one-pole smoother with `time` (in seconds) as
+/// its time constant.
+fn coefficient(time: f64, sample_rate: f64) -> f32 {
+    (1.0 - (-1.0 / (time * sample_rate)).exp()) as f32
+}
+
+/// Get the mean power of an interleaved frame.
+fn power(frame: &[Ch32]) -> f32 {
+    let sum: f32 = frame.iter().map(|&s| f32::from(s).powi(2)).sum();
+    sum / frame.len() as f32
+}
+
+/// Processing of the audio a [`Microphone`](crate::Microphone) records, a
+/// period at a time (added with
+/// [`Microphone::add_processor()`](crate::Microphone::add_processor)).
+///
+/// Processors run in the order they're added, after channel mixing and echo
+/// cancellation, and before conversion to a requested sample rate and the
+/// microphone's software volume.
+///
+/// ```
+/// use fon::{chan::Ch32, mono::Mono32};
+/// use pasts::prelude::*;
+/// use wavy::{CaptureProcessor, Clock, Microphone};
+///
+/// /// Inverts the polarity of audio.
+/// struct Invert;
+///
+/// impl CaptureProcessor for Invert {
+///     fn process(&mut self, audio: &mut [Ch32], _: usize, _: f64) {
+///         for sample in audio {
+///             *sample = Ch32::new(-f32::from(*sample));
+///         }
+///     }
+/// }
+///
+/// async fn run() {
+///     let signal = std::iter::repeat(Mono32::new(0.25));
+///     let mut microphone =
+///         Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+///     microphone.add_processor(Invert);
+///     let stream = microphone.next().await;
+///     assert!(stream.into_iter().all(|f| f == Mono32::new(-0.25)));
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+pub trait CaptureProcessor: Send {
+    /// Process a period of interleaved audio with `channels` channels,
+    /// recorded at `sample_rate`, in place.
+    fn process(
+        &mut self,
+        audio: &mut [Ch32],
+        channels: usize,
+        sample_rate: f64,
+    );
+}
+
+/// Suppression of steady background noise (such as fans or hiss) between
+/// louder sounds, with a downward expander: the noise floor is tracked (as
+/// the quietest level in the last second or so), and audio near it is
+/// attenuated, by up to the reduction at the noise floor.
+/// Noise under louder sounds (such as speech) isn't removed.
+///
+/// Channels are attenuated together.
+///
+/// ```
+/// use fon::{mono::Mono32, Frame};
+/// use pasts::prelude::*;
+/// use wavy::{Clock, Microphone, NoiseSuppressor};
+///
+/// async fn run() {
+///     // Noise at -50 dBFS, under a tone at -9 dBFS every other half
+///     // second.
+///     let signal = (0..48_000u32).map(|i| {
+///         let random = i.wrapping_mul(2_654_435_761) >> 8;
+///         let noise = random as f32 / (1 << 24) as f32 * 2.0 - 1.0;
+///         let tone = if i < 24_000 { 0.0 } else { (i as f32 * 0.1).sin() };
+///         Mono32::new(noise * 0.0055 + 0.5 * tone)
+///     });
+///     let signal = signal.collect::<Vec<_>>().into_iter().cycle();
+///     let mut microphone =
+///         Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+///     microphone.add_processor(NoiseSuppressor::new(20.0));
+///
+///     // Measure the last noise, and the last tone, in dBFS.
+///     let (mut noise, mut tone) = (0.0, 0.0);
+///     for period in 0..2000 {
+///         for frame in microphone.next().await {
+///             let power = f32::from(frame.channels()[0]).powi(2);
+///             match period {
+///                 1600..=1849 => noise += power / (250.0 * 64.0),
+///                 1900.. => tone += power / (100.0 * 64.0),
+///                 _ => {}
+///             }
+///         }
+///     }
+///     assert!(10.0 * noise.log10() < -65.0);
+///     assert!(10.0 * tone.log10() > -10.0);
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct NoiseSuppressor {
+    /// Most attenuation, in decibels.
+    reduction: f32,
+    /// Smoothed power of the audio.
+    power: f32,
+    /// Quietest power in the last window.
+    floor: f32,
+    /// Quietest power so far in this window.
+    minimum: f32,
+    /// Number of frames so far in this window.
+    elapsed: usize,
+    /// Gain applied.
+    gain: f32,
+}
+
+impl Default for NoiseSuppressor {
+    fn default() -> Self {
+        Self::new(20.0)
+    }
+}
+
+impl NoiseSuppressor {
+    /// Create a noise suppressor attenuating noise by `reduction` decibels.
+    pub fn new(reduction: f32) -> Self {
+        Self {
+            reduction: reduction.abs(),
+            power: 0.0,
+            floor: f32::MAX,
+            minimum: f32::MAX,
+            elapsed: 0,
+            gain: 1.0,
+        }
+    }
+
+    /// Get how much noise is attenuated, in decibels.
+    pub fn reduction(&self) -> f32 {
+        self.reduction
+    }
+}
+
+impl CaptureProcessor for NoiseSuppressor {
+    fn process(
+        &mut self,
+        audio: &mut [Ch32],
+        channels: usize,
+        sample_rate: f64,
+    ) {
+        let smooth = coefficient(NOISE_POWER_TIME, sample_rate);
+        let attack = coefficient(NOISE_ATTACK_TIME, sample_rate);
+        let release = coefficient(NOISE_RELEASE_TIME, sample_rate);
+        let window = (NOISE_WINDOW * sample_rate) as usize;
+        // Audio this far above the noise floor isn't attenuated.
+        let threshold = self.reduction / 2.0;
+        for frame in audio.chunks_mut(channels) {
+            self.power += smooth * (power(frame) - self.power);
+            self.minimum = self.minimum.min(self.power);
+            self.elapsed += 1;
+            if self.elapsed >= window {
+                self.floor = self.minimum;
+                self.minimum = f32::MAX;
+                self.elapsed = 0;
+            }
+            // The noise is the quietest power of this and the last window.
+            let floor = self.floor.min(self.minimum).max(NOISE_FLOOR_MIN);
+            let above = 10.0 * (self.power / floor).log10();
+            let target = if threshold > 0.0 {
+                ((above - threshold) * self.reduction / threshold)
+                    .clamp(-self.reduction, 0.0)
+            } else {
+                0.0
+            };
+            let target = 10.0f32.powf(target / 20.0);
+            let speed = if target > self.gain { attack } else { release };
+            self.gain += speed * (target - self.gain);
+            for sample in frame {
+                *sample = Ch32::new(f32::from(*sample) * self.gain);
+            }
+        }
+    }
+}
+
+/// Automatic gain control, which brings the level of audio to a target
+/// (such as speech near and far from the microphone to the same loudness).
+///
+/// The gain falls quickly when audio gets louder, rises slowly when it gets
+/// quieter, and holds during silence; it's lowered instantly if audio would
+/// clip.  Channels share the gain.
+///
+/// ```
+/// use fon::{mono::Mono32, Frame};
+/// use pasts::prelude::*;
+/// use wavy::{AutomaticGainControl, Clock, Microphone};
+///
+/// async fn run() {
+///     // A tone at -43 dBFS.
+///     let signal = (0..).map(|i| Mono32::new(0.01 * (i as f32 * 0.1).sin()));
+///     let mut microphone =
+///         Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+///     microphone.add_processor(AutomaticGainControl::new(-20.0, 30.0));
+///     let mut power = 0.0;
+///     for period in 0..4000 {
+///         for frame in microphone.next().await {
+///             if period >= 3900 {
+///                 power += f32::from(frame.channels()[0]).powi(2);
+///             }
+///         }
+///     }
+///     let level = 10.0 * (power / (100.0 * 64.0)).log10();
+///     assert!((level + 20.0).abs() < 1.0);
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct AutomaticGainControl {
+    /// Level to bring audio to, in dBFS (RMS).
+    target: f32,
+    /// Most gain, in decibels.
+    max_gain: f32,
+    /// Smoothed power of the audio.
+    power: f32,
+    /// Gain applied.
+    gain: f32,
+}
+
+impl Default for AutomaticGainControl {
+    fn default() -> Self {
+        Self::new(-20.0, 30.0)
+    }
+}
+
+impl AutomaticGainControl {
+    /// Create an automatic gain control bringing audio to `target` dBFS
+    /// (RMS), amplifying or attenuating by up to `max_gain` decibels.
+    pub fn new(target: f32, max_gain: f32) -> Self {
+        Self {
+            target,
+            max_gain: max_gain.abs(),
+            power: 0.0,
+            gain: 1.0,
+        }
+    }
+
+    /// Get the level audio is brought to, in dBFS.
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    /// Get the most gain, in decibels.
+    pub fn max_gain(&self) -> f32 {
+        self.max_gain
+    }
+}
+
+impl CaptureProcessor for AutomaticGainControl {
+    fn process(
+        &mut self,
+        audio: &mut [Ch32],
+        channels: usize,
+        sample_rate: f64,
+    ) {
+        let smooth = coefficient(AGC_POWER_TIME, sample_rate);
+        let attack = coefficient(AGC_ATTACK_TIME, sample_rate);
+        let rise = 10.0f64.powf(AGC_RISE / 20.0 / sample_rate) as f32;
+        let target = 10.0f32.powf(self.target / 20.0);
+        let most = 10.0f32.powf(self.max_gain / 20.0);
+        for frame in audio.chunks_mut(channels) {
+            self.power += smooth * (power(frame) - self.power);
+            if self.power > AGC_SILENCE {
+                let wanted =
+                    (target / self.power.sqrt()).clamp(1.0 / most, most);
+                if wanted < self.gain {
+                    self.gain += attack * (wanted - self.gain);
+                } else {
+                    self.gain = (self.gain * rise).min(wanted);
+                }
+            }
+            // Don't clip.
+            let peak = frame
+                .iter()
+                .map(|&s| f32::from(s).abs())
+                .fold(0.0, f32::max);
+            if peak * self.gain > 1.0 {
+                self.gain = 1.0 / peak;
+            }
+            for sample in frame {
+                *sample = Ch32::new(f32::from(*sample) * self.gain);
+            }
+        }
+    }
+}
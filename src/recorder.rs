@@ -0,0 +1,511 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    fmt::{Debug, Formatter},
+    io::Result,
+    mem,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use fon::{chan::Ch32, Frame, Stream};
+use pasts::prelude::*;
+
+use crate::{
+    blocking, flac::FlacWriter, microphone::MicrophoneProperties,
+    wav::WavWriter, Microphone, WavFormat,
+};
+
+/// Largest WAV file written before splitting, as sizes in the header are 32
+/// bits.
+const WAV_LIMIT: u64 = u32::MAX as u64 - (1 << 20);
+
+/// File format of a [`Recorder`].
+///
+/// FLAC files are written in blocks of 4096 frames, each checked with a
+/// CRC-16 (and its header with a CRC-8), after a STREAMINFO block with the
+/// total number of frames:
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use fon::mono::Mono32;
+/// use wavy::{Clock, Microphone, RecordFormat, Recorder};
+///
+/// fn crc(bytes: &[u8], bits: u32, poly: u32) -> u32 {
+///     let top = 1 << (bits - 1);
+///     let mask = (1 << bits) - 1;
+///     bytes.iter().fold(0, |mut crc, byte| {
+///         crc ^= u32::from(*byte) << (bits - 8);
+///         for _ in 0..8 {
+///             crc = if crc & top != 0 { (crc << 1) ^ poly } else { crc << 1 };
+///         }
+///         crc & mask
+///     })
+/// }
+///
+/// let path = std::env::temp_dir().join("wavy-flac-doctest.flac");
+/// let signal = (0..).map(|i| Mono32::new(0.5 * (i as f32 * 0.01).sin()));
+/// let microphone = Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+/// let mut recorder = Recorder::new(microphone, &path, RecordFormat::Flac16);
+/// recorder.record_blocking(Duration::from_millis(125)).unwrap();
+/// recorder.stop().unwrap();
+/// let file = std::fs::read(&path).unwrap();
+///
+/// // STREAMINFO: 4096 frame blocks, 48 kHz, mono, 16 bits.
+/// assert_eq!(&file[..8], b"fLaC\x80\0\0\x22");
+/// let info = &file[8..42];
+/// assert_eq!(info[..4], [0x10, 0x00, 0x10, 0x00]);
+/// let info_bits = u64::from_be_bytes(info[10..18].try_into().unwrap());
+/// assert_eq!(info_bits >> 44, 48_000);
+/// assert_eq!((info_bits >> 41) & 0x7, 0);
+/// assert_eq!((info_bits >> 36) & 0x1F, 15);
+/// let total = info_bits & 0xF_FFFF_FFFF;
+///
+/// // Find each frame by its CRC-16, and check its header's CRC-8.
+/// let (mut start, mut frames) = (42, Vec::new());
+/// while start < file.len() {
+///     let end = (start + 2..=file.len())
+///         .find(|&end| {
+///             (end == file.len() || file[end..].starts_with(&[0xFF, 0xF8]))
+///                 && crc(&file[start..end], 16, 0x8005) == 0
+///         })
+///         .unwrap();
+///     frames.push(&file[start..end]);
+///     start = end;
+/// }
+/// assert_eq!(frames.len(), 2);
+/// assert_eq!(crc(&frames[0][..6], 8, 0x07), 0);
+/// assert_eq!(crc(&frames[1][..8], 8, 0x07), 0);
+/// // The last block has its size in the header.
+/// let last = u64::from(u16::from_be_bytes([frames[1][5], frames[1][6]]));
+/// assert_eq!(total, 4096 + last + 1);
+/// assert!(total >= 6000);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// WAV with the given sample encoding.
+    Wav(WavFormat),
+    /// FLAC with 16 bits per sample.
+    Flac16,
+    /// FLAC with 24 bits per sample.
+    Flac24,
+}
+
+/// When a [`Recorder`] starts a new file.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Split {
+    /// Record into one file (WAV files are still split before they reach the
+    /// format's 4 GiB limit).
+    #[default]
+    Never,
+    /// Start a new file once a file reaches this many bytes (files may go
+    /// over by up to a period).
+    Size(u64),
+    /// Start a new file after this much audio.
+    Duration(Duration),
+}
+
+/// A file being written.
+enum Encoder {
+    Wav(WavWriter),
+    Flac(FlacWriter),
+}
+
+impl Encoder {
+    fn size(&self) -> u64 {
+        match self {
+            Self::Wav(writer) => writer.size(),
+            Self::Flac(writer) => writer.size(),
+        }
+    }
+
+    fn write(&mut self, audio: &[Ch32], channels: u8) -> Result<()> {
+        match self {
+            Self::Wav(writer) => writer.write(audio, channels),
+            Self::Flac(writer) => writer.write(audio),
+        }
+    }
+
+    /// Update the header and flush to disk, so the file is valid if the
+    /// process stops before it's finished.
+    fn sync(&mut self) -> Result<()> {
+        match self {
+            Self::Wav(writer) => writer.finalize(),
+            Self::Flac(writer) => writer.finalize(false),
+        }
+    }
+
+    fn finish(mut self) -> Result<()> {
+        match &mut self {
+            Self::Wav(writer) => writer.finalize(),
+            Self::Flac(writer) => writer.finalize(true),
+        }
+    }
+}
+
+/// Records a [`Microphone`] to WAV or FLAC files, streaming each period to
+/// disk.  Notifier produces once each period is written, or an error if it
+/// couldn't be.
+///
+/// Headers are updated every second, so if the process stops before
+/// [`Recorder::stop()`], files are still valid up to the last update.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use fon::mono::Mono32;
+/// use wavy::{Clock, Microphone, RecordFormat, Recorder, Split, WavFormat};
+///
+/// let dir = std::env::temp_dir().join("wavy-recorder-doctest");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let signal = std::iter::repeat(Mono32::new(0.25));
+/// let microphone = Microphone::<1>::null(Clock::Virtual, 48_000, signal);
+/// let mut recorder = Recorder::new(
+///     microphone,
+///     dir.join("take.wav"),
+///     RecordFormat::Wav(WavFormat::Pcm16),
+/// )
+/// .split(Split::Duration(Duration::from_secs(1)));
+/// recorder.record_blocking(Duration::from_millis(1500)).unwrap();
+/// let files = recorder.stop().unwrap();
+/// assert_eq!(files, [dir.join("take.wav"), dir.join("take-2.wav")]);
+///
+/// let mut first = Microphone::<1>::wav(Clock::Virtual, &files[0]).unwrap();
+/// let mut frames = [Mono32::default(); 64];
+/// first.read_blocking(&mut frames);
+/// assert!(frames.iter().all(|f| *f == Mono32::new(0.25)));
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct Recorder<const N: usize> {
+    /// The microphone recorded from.
+    microphone: Microphone<N>,
+    /// Path of the first file.
+    path: PathBuf,
+    /// File format.
+    format: RecordFormat,
+    /// When to start a new file.
+    split: Split,
+    /// The file being written (`None` until the first period).
+    encoder: Option<Encoder>,
+    /// Paths of the files written.
+    files: Vec<PathBuf>,
+    /// Sample rate of the files (0 until the first period).
+    sample_rate: u32,
+    /// Number of frames recorded.
+    frames: u64,
+    /// Number of frames written to the current file.
+    file_frames: u64,
+    /// Number of frames written since the header was last updated.
+    unsynced: u64,
+    /// Interleaved samples of the last period.
+    buffer: Vec<Ch32>,
+}
+
+impl<const N: usize> Debug for Recorder<N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Recorder({:?}, {:?})", self.path, self.format)
+    }
+}
+
+impl<const N: usize> Recorder<N>
+where
+    Microphone<N>: MicrophoneProperties,
+{
+    /// Record `microphone` into a file at `path` (created with the first
+    /// period).
+    ///
+    /// When the recording is split, later files have a number added to the
+    /// name: `take.wav`, `take-2.wav`, `take-3.wav` and so on.
+    pub fn new<P: AsRef<Path>>(
+        microphone: Microphone<N>,
+        path: P,
+        format: RecordFormat,
+    ) -> Self {
+        Self {
+            microphone,
+            path: path.as_ref().to_path_buf(),
+            format,
+            split: Split::Never,
+            encoder: None,
+            files: Vec::new(),
+            sample_rate: 0,
+            frames: 0,
+            file_frames: 0,
+            unsynced: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Set when to start a new file.
+    pub fn split(mut self, split: Split) -> Self {
+        self.split = split;
+        self
+    }
+
+    /// Get the microphone recorded from, to change its volume for example.
+    pub fn microphone(&mut self) -> &mut Microphone<N> {
+        &mut self.microphone
+    }
+
+    /// Get how much audio has been recorded.
+    pub fn duration(&self) -> Duration {
+        if self.sample_rate == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(
+            self.frames as f64 / f64::from(self.sample_rate),
+        )
+    }
+
+    /// Get the paths of the files written so far.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Record for at least `duration`, blocking the thread.  This doesn't
+    /// require an async executor.
+    pub fn record_blocking(&mut self, duration: Duration) -> Result<()> {
+        let end = self.duration() + duration;
+        while self.duration() < end {
+            blocking::wait(|cx| Pin::new(&mut *self).poll_next(cx))?;
+        }
+        Ok(())
+    }
+
+    /// Finish the last file, and return the paths of all the files written.
+    pub fn stop(mut self) -> Result<Vec<PathBuf>> {
+        if let Some(encoder) = self.encoder.take() {
+            encoder.finish()?;
+        }
+        Ok(mem::take(&mut self.files))
+    }
+
+    /// Get the path of file number `index` (from 0).
+    fn file_path(&self, index: usize) -> PathBuf {
+        if index == 0 {
+            return self.path.clone();
+        }
+        let stem = self.path.file_stem().unwrap_or_default();
+        let mut name = stem.to_os_string();
+        name.push(format!("-{}", index + 1));
+        if let Some(extension) = self.path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        self.path.with_file_name(name)
+    }
+
+    /// Check if the current file is full, or how many more frames it takes.
+    fn room(&self, encoder: &Encoder) -> Option<u64> {
+        if let RecordFormat::Wav(_) = self.format {
+            if encoder.size() >= WAV_LIMIT {
+                return None;
+            }
+        }
+        match self.split {
+            Split::Never => Some(u64::MAX),
+            Split::Size(size) => (encoder.size() < size).then_some(u64::MAX),
+            Split::Duration(duration) => {
+                let limit = (duration.as_secs_f64()
+                    * f64::from(self.sample_rate))
+                .round()
+                .max(1.0) as u64;
+                limit.checked_sub(self.file_frames).filter(|room| *room > 0)
+            }
+        }
+    }
+
+    /// Finish the current file (if any), and start the next.
+    fn next_file(&mut self) -> Result<()> {
+        if let Some(encoder) = self.encoder.take() {
+            encoder.finish()?;
+        }
+        let path = self.file_path(self.files.len());
+        let channels = N as u8;
+        let encoder = match self.format {
+            RecordFormat::Wav(format) => Encoder::Wav(WavWriter::create(
+                &path,
+                format,
+                self.sample_rate,
+            )?),
+            RecordFormat::Flac16 => Encoder::Flac(FlacWriter::create(
+                &path,
+                16,
+                self.sample_rate,
+                channels,
+            )?),
+            RecordFormat::Flac24 => Encoder::Flac(FlacWriter::create(
+                &path,
+                24,
+                self.sample_rate,
+                channels,
+            )?),
+        };
+        self.encoder = Some(encoder);
+        self.files.push(path);
+        self.file_frames = 0;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Write interleaved audio, splitting files as needed.
+    fn write(&mut self, mut audio: &[Ch32]) -> Result<()> {
+        while !audio.is_empty() {
+            let room = match &self.encoder {
+                Some(encoder) => self.room(encoder),
+                None => None,
+            };
+            let room = match room {
+                Some(room) => room,
+                None => {
+                    self.next_file()?;
+                    continue;
+                }
+            };
+            let frames =
+                (audio.len() / N).min(room.try_into().unwrap_or(usize::MAX));
+            let encoder = self.encoder.as_mut().unwrap();
+            encoder.write(&audio[..frames * N], N as u8)?;
+            audio = &audio[frames * N..];
+            self.frames += frames as u64;
+            self.file_frames += frames as u64;
+            self.unsynced += frames as u64;
+            if self.unsynced >= self.sample_rate.into() {
+                encoder.sync()?;
+                self.unsynced = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> Notifier for Recorder<N>
+where
+    Microphone<N>: MicrophoneProperties,
+{
+    type Event = Result<()>;
+
+    fn poll_next(self: Pin<&mut Self>, e: &mut Exec<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        let stream = match Pin::new(&mut this.microphone).poll_next(e) {
+            Ready(stream) => stream,
+            Pending => return Pending,
+        };
+        if this.sample_rate == 0 {
+            let sample_rate = stream
+                .sample_rate()
+                .unwrap_or_else(|| crate::consts::SAMPLE_RATE.into());
+            this.sample_rate = sample_rate.round() as u32;
+        }
+        let mut buffer = mem::take(&mut this.buffer);
+        buffer.clear();
+        for frame in stream {
+            buffer.extend_from_slice(frame.channels());
+        }
+        let result = this.write(&buffer);
+        this.buffer = buffer;
+        Ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fon::mono::Mono32;
+
+    use super::*;
+    use crate::{wav::WavReader, Clock};
+
+    /// A mono WAV recorder at 100 Hz into `name` in its own directory.
+    fn recorder(name: &str, split: Split) -> Recorder<1> {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let signal = std::iter::repeat(Mono32::new(0.5));
+        let microphone = Microphone::<1>::null(Clock::Virtual, 100, signal);
+        let format = RecordFormat::Wav(WavFormat::Pcm16);
+        let mut recorder =
+            Recorder::new(microphone, dir.join("take.wav"), format)
+                .split(split);
+        recorder.sample_rate = 100;
+        recorder
+    }
+
+    /// Stop `recorder`, and return the number of frames in each file.
+    fn stop(recorder: Recorder<1>) -> Vec<u64> {
+        let dir = recorder.path.parent().unwrap().to_path_buf();
+        let lens = recorder
+            .stop()
+            .unwrap()
+            .iter()
+            .map(|path| WavReader::open(path).unwrap().len())
+            .collect();
+        std::fs::remove_dir_all(dir).unwrap();
+        lens
+    }
+
+    #[test]
+    fn split_duration_within_period() {
+        let split = Split::Duration(Duration::from_millis(100));
+        let mut recorder = recorder("wavy-test-split-duration", split);
+        recorder.write(&[Ch32::new(0.5); 25]).unwrap();
+        assert_eq!(recorder.file_frames, 5);
+        assert_eq!(recorder.duration(), Duration::from_millis(250));
+        let dir = recorder.path.parent().unwrap().to_path_buf();
+        assert_eq!(
+            recorder.files(),
+            ["take.wav", "take-2.wav", "take-3.wav"].map(|f| dir.join(f))
+        );
+        assert_eq!(stop(recorder), [10, 10, 5]);
+    }
+
+    #[test]
+    fn split_duration_on_boundary() {
+        let split = Split::Duration(Duration::from_millis(100));
+        let mut recorder = recorder("wavy-test-split-boundary", split);
+        recorder.write(&[Ch32::new(0.5); 10]).unwrap();
+        // The next file isn't created until there's audio for it.
+        assert_eq!(recorder.files().len(), 1);
+        recorder.write(&[Ch32::new(0.5); 10]).unwrap();
+        assert_eq!(stop(recorder), [10, 10]);
+    }
+
+    #[test]
+    fn split_duration_under_a_frame() {
+        let split = Split::Duration(Duration::from_micros(1));
+        let mut recorder = recorder("wavy-test-split-frame", split);
+        recorder.write(&[Ch32::new(0.5); 3]).unwrap();
+        assert_eq!(stop(recorder), [1, 1, 1]);
+    }
+
+    #[test]
+    fn split_size() {
+        // The header is 44 bytes, and each frame 2 bytes.
+        let mut recorder = recorder("wavy-test-split-size", Split::Size(64));
+        recorder.write(&[Ch32::new(0.5); 8]).unwrap();
+        recorder.write(&[Ch32::new(0.5); 8]).unwrap();
+        // The file goes over by up to a period.
+        assert_eq!(recorder.files().len(), 1);
+        recorder.write(&[Ch32::new(0.5); 8]).unwrap();
+        assert_eq!(stop(recorder), [16, 8]);
+    }
+
+    #[test]
+    fn file_path() {
+        let mut recorder = recorder("wavy-test-file-path", Split::Never);
+        let dir = recorder.path.parent().unwrap().to_path_buf();
+        assert_eq!(recorder.file_path(0), dir.join("take.wav"));
+        assert_eq!(recorder.file_path(9), dir.join("take-10.wav"));
+        recorder.path = dir.join("take");
+        assert_eq!(recorder.file_path(1), dir.join("take-2"));
+        assert!(stop(recorder).is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::f64::consts::PI;
+
+use fon::{chan::Ch32, Frame};
+
+/// Number of fractional positions the sinc filter is tabulated at (positions
+/// in between are interpolated).
+const PHASES: usize = 256;
+
+/// How audio at a different sample rate than the device is converted.
+///
+/// ```should_panic
+/// use fon::{mono::Mono32, Audio};
+/// use wavy::{Mixer, ResampleQuality};
+///
+/// let mut mixer = Mixer::<Mono32>::new();
+/// mixer.set_resample_quality(ResampleQuality::Off);
+/// mixer.handle().add(44_100, [Mono32::new(0.5); 8]);
+/// // Audio at 44.1 kHz would play too fast at 48 kHz.
+/// let mut audio = Audio::<Mono32>::with_silence(48_000, 16);
+/// mixer.mix(&mut audio.sink(..));
+/// ```
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Don't convert: audio is played at the device's sample rate as-is, so
+    /// it must already be at that rate (streaming audio at another rate
+    /// panics, rather than playing it at the wrong pitch).
+    Off,
+    /// Linear interpolation.  Cheap, but high frequencies alias.
+    #[default]
+    Linear,
+    /// Windowed sinc interpolation with this many taps (rounded up to an
+    /// even number, from 4 to 128).  More taps filter aliasing more sharply,
+    /// at the cost of CPU time and `taps / 2` frames of latency; 32 is
+    /// plenty for most audio.
+    Sinc(u8),
+}
+
+impl ResampleQuality {
+    /// Number of input frames each output frame is calculated from.
+    fn taps(self) -> usize {
+        match self {
+            ResampleQuality::Off => 1,
+            ResampleQuality::Linear => 2,
+            ResampleQuality::Sinc(taps) => {
+                (usize::from(taps).clamp(4, 128) + 1) & !1
+            }
+        }
+    }
+}
+
+/// Sample rate converter, keeping its state between periods.
+#[derive(Debug, Default)]
+pub(crate) struct Converter {
+    /// Conversion quality.
+    quality: ResampleQuality,
+    /// Last input frames (interleaved), oldest first.
+    window: Vec<Ch32>,
+    /// Position of the next output frame after the middle of the window,
+    /// in input frames (at least 1.0 when input is needed).
+    phase: f64,
+    /// Filter coefficients at each of `PHASES + 1` positions.
+    table: Vec<f32>,
+    /// Input frames per output frame the table was calculated for.
+    table_step: f64,
+}
+
+impl Converter {
+    pub(crate) fn quality(&self) -> ResampleQuality {
+        self.quality
+    }
+
+    pub(crate) fn set_quality(&mut self, quality: ResampleQuality) {
+        if quality != self.quality {
+            *self = Self {
+                quality,
+                ..Self::default()
+            };
+        }
+    }
+
+    /// Number of frames of silence to stream after the end of a source, so
+    /// its last frames are output.
+    pub(crate) fn tail(&self) -> usize {
+        match self.quality {
+            ResampleQuality::Off => 0,
+            quality => quality.taps() / 2,
+        }
+    }
+
+    /// Forget the frames streamed so far, as when a source moves to another
+    /// position.
+    pub(crate) fn reset(&mut self) {
+        self.window.fill(Ch32::default());
+        self.phase = 0.0;
+    }
+
+    /// Calculate the sinc filter table, low-pass filtering below the output
+    /// Nyquist frequency when downsampling.
+    fn tabulate(&mut self, taps: usize, step: f64) {
+        self.table_step = step;
+        self.table.clear();
+        let cutoff = (1.0 / step).min(1.0);
+        let half = taps as f64 / 2.0;
+        let center = taps / 2 - 1;
+        for phase in 0..=PHASES {
+            let offset = phase as f64 / PHASES as f64;
+            let start = self.table.len();
+            for tap in 0..taps {
+                let x = tap as f64 - center as f64 - offset;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * cutoff * x).sin() / (PI * cutoff * x)
+                };
+                // Blackman window.
+                let w = (x + half) / (2.0 * half);
+                let window = 0.42 - 0.5 * (2.0 * PI * w).cos()
+                    + 0.08 * (4.0 * PI * w).cos();
+                self.table.push((sinc * window) as f32);
+            }
+            let sum: f32 = self.table[start..].iter().sum();
+            for coefficient in &mut self.table[start..] {
+                *coefficient /= sum;
+            }
+        }
+    }
+
+    /// Convert `stream` from `source_rate` (or the output rate, if `None`) to
+    /// `output_rate`, overwriting the start of `output`, and return how many
+    /// frames were written.  Stops early if the stream ends.
+    ///
+    /// Panics if resampling is off and the rates don't match.
+    pub(crate) fn stream<F, S, I>(
+        &mut self,
+        source_rate: Option<f64>,
+        output_rate: f64,
+        stream: I,
+        output: &mut [F],
+    ) -> usize
+    where
+        F: Frame<Chan = Ch32>,
+        S: Frame,
+        I: IntoIterator<Item = S>,
+    {
+        let mut stream = stream.into_iter().map(|frame| frame.convert::<F>());
+        let step = source_rate.unwrap_or(output_rate) / output_rate;
+        if self.quality == ResampleQuality::Off && step != 1.0 {
+            panic!(
+                "Audio at {} Hz streamed at {} Hz with resampling off",
+                source_rate.unwrap_or(output_rate),
+                output_rate,
+            );
+        }
+        if step == 1.0 {
+            // Same rate (or not converting), so nothing to interpolate.
+            self.window.clear();
+            let mut written = 0;
+            for (dst, src) in output.iter_mut().zip(stream) {
+                *dst = src;
+                written += 1;
+            }
+            return written;
+        }
+        let taps = self.quality.taps();
+        let channels = F::CHAN_COUNT;
+        if self.window.len() != taps * channels {
+            self.window = vec![Ch32::default(); taps * channels];
+            self.phase = 0.0;
+        }
+        if let ResampleQuality::Sinc(_) = self.quality {
+            if self.table.is_empty() || self.table_step != step {
+                self.tabulate(taps, step);
+            }
+        }
+        let center = taps / 2 - 1;
+        for (written, dst) in output.iter_mut().enumerate() {
+            while self.phase >= 1.0 {
+                let frame = match stream.next() {
+                    Some(frame) => frame,
+                    None => return written,
+                };
+                self.window.rotate_left(channels);
+                let start = self.window.len() - channels;
+                self.window[start..].copy_from_slice(frame.channels());
+                self.phase -= 1.0;
+            }
+            let mut frame = F::default();
+            if let ResampleQuality::Sinc(_) = self.quality {
+                let position = self.phase * PHASES as f64;
+                let index = position as usize;
+                let fraction = (position - index as f64) as f32;
+                let a = &self.table[index * taps..][..taps];
+                let b = &self.table[(index + 1) * taps..][..taps];
+                let window = self.window.chunks(channels);
+                let mut sum = [0.0f32; 6];
+                for ((a, b), src) in a.iter().zip(b).zip(window) {
+                    let coefficient = a + (b - a) * fraction;
+                    for (sum, src) in sum.iter_mut().zip(src) {
+                        *sum += f32::from(*src) * coefficient;
+                    }
+                }
+                for (dst, sum) in frame.channels_mut().iter_mut().zip(sum) {
+                    *dst = Ch32::new(sum);
+                }
+            } else {
+                let prev = &self.window[center * channels..][..channels];
+                let next = &self.window[(center + 1) * channels..][..channels];
+                let phase = Ch32::new(self.phase as f32);
+                frame = F::from_channels(prev)
+                    .lerp(F::from_channels(next), F::from_channel(phase));
+            }
+            *dst = frame;
+            self.phase += step;
+        }
+        output.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fon::mono::Mono32;
+
+    use super::*;
+
+    /// A sine wave at `hz` sampled at `rate`.
+    fn sine(hz: f64, rate: f64) -> impl Iterator<Item = Mono32> {
+        (0..).map(move |i| {
+            Mono32::new((2.0 * PI * hz * i as f64 / rate).sin() as f32 * 0.5)
+        })
+    }
+
+    /// Convert 1000 frames from 44.1 kHz to `rate`, in periods of
+    /// `period` frames.
+    fn convert(quality: ResampleQuality, rate: f64, period: usize) -> Vec<f32> {
+        let mut converter = Converter::default();
+        converter.set_quality(quality);
+        let mut input = sine(1_000.0, 44_100.0);
+        let mut output = vec![Mono32::default(); 1_000];
+        for chunk in output.chunks_mut(period) {
+            let written =
+                converter.stream(Some(44_100.0), rate, &mut input, chunk);
+            assert_eq!(written, chunk.len());
+        }
+        output.iter().map(|f| f32::from(f.channels()[0])).collect()
+    }
+
+    #[test]
+    fn phase_carried_across_periods() {
+        for quality in [ResampleQuality::Linear, ResampleQuality::Sinc(16)] {
+            for rate in [48_000.0, 32_000.0] {
+                let whole = convert(quality, rate, 1_000);
+                for period in [1, 7, 64, 441] {
+                    let periods = convert(quality, rate, period);
+                    assert_eq!(
+                        whole, periods,
+                        "{:?} {} {}",
+                        quality, rate, period
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn linear_interpolates() {
+        let mut converter = Converter::default();
+        let ramp = (0..).map(|i| Mono32::new(i as f32 / 16.0));
+        let mut output = [Mono32::default(); 8];
+        converter.stream(Some(24_000.0), 48_000.0, ramp, &mut output);
+        // Output starts from the silence before the stream, then alternates
+        // between input frames and halfway between them.
+        let expected = [0.0, 0.0, 0.0, 0.0, 0.0, 0.5, 1.0, 1.5];
+        for (frame, expected) in output.iter().zip(expected) {
+            assert_eq!(f32::from(frame.channels()[0]), expected / 16.0);
+        }
+    }
+
+    #[test]
+    fn sinc_passes_dc() {
+        let mut converter = Converter::default();
+        converter.set_quality(ResampleQuality::Sinc(32));
+        let constant = std::iter::repeat(Mono32::new(0.5));
+        let mut output = [Mono32::default(); 100];
+        converter.stream(Some(44_100.0), 48_000.0, constant, &mut output);
+        // Once the window is full of input, the filter has unity gain.
+        for frame in &output[35..] {
+            let sample = f32::from(frame.channels()[0]);
+            assert!((sample - 0.5).abs() < 1e-5, "{}", sample);
+        }
+    }
+
+    #[test]
+    fn same_rate_copies() {
+        let mut converter = Converter::default();
+        converter.set_quality(ResampleQuality::Off);
+        let input = [0.25, -0.5, 0.75].map(Mono32::new);
+        let mut output = [Mono32::default(); 4];
+        let written = converter.stream(None, 48_000.0, input, &mut output);
+        assert_eq!(written, 3);
+        assert_eq!(output[..3], input);
+    }
+
+    #[test]
+    fn stops_when_stream_ends() {
+        let mut converter = Converter::default();
+        let input = [Mono32::new(0.5); 10];
+        let mut output = [Mono32::default(); 100];
+        let written =
+            converter.stream(Some(44_100.0), 48_000.0, input, &mut output);
+        // Output frame `n` needs `n * 44.1 / 48` input frames, after the
+        // first which is from before the stream.
+        assert_eq!(written, 12);
+    }
+
+    #[test]
+    #[should_panic]
+    fn off_panics_on_other_rate() {
+        let mut converter = Converter::default();
+        converter.set_quality(ResampleQuality::Off);
+        let mut output = [Mono32::default(); 4];
+        converter.stream(
+            Some(44_100.0),
+            48_000.0,
+            [Mono32::new(0.5); 4],
+            &mut output,
+        );
+    }
+}
@@ -0,0 +1,460 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    fmt::{Debug, Formatter, Result},
+    marker::PhantomData,
+    sync::{
+        atomic::{self, AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::Waker,
+};
+
+use fon::{
+    chan::{Ch32, Channel},
+    Frame, Sink, Stream,
+};
+use pasts::prelude::*;
+
+/// Create a lock-free single-producer single-consumer ring buffer, holding up
+/// to `capacity` frames of audio at `sample_rate`.
+///
+/// The [`RingWriter`] and [`RingReader`] can be sent to different threads, for
+/// example to feed a [`SpeakersSink`](crate::SpeakersSink) on an
+/// [`AudioThread`](crate::AudioThread) from a decoder, or to get audio from a
+/// [`MicrophoneStream`](crate::MicrophoneStream) to an async task.  The audio
+/// thread only takes a lock to wake a task that's waiting for it.
+///
+/// ```
+/// use fon::mono::Mono32;
+/// use pasts::prelude::*;
+/// use wavy::{ring, Clock, Speakers};
+///
+/// async fn run() {
+///     let (mut writer, mut reader) = ring::<Mono32>(48_000, 100);
+///     let mut speakers = Speakers::<1>::null(Clock::Virtual, 48_000);
+///     assert_eq!(writer.write(&[Mono32::new(0.5); 150]), 100);
+///
+///     // A period is 64 frames, so the second one underruns.
+///     for _ in 0..2 {
+///         reader.fill(&mut speakers.next().await);
+///     }
+///     assert_eq!(reader.underruns(), 1);
+///     assert_eq!(writer.next().await, 100);
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+pub fn ring<F: Frame<Chan = Ch32>>(
+    sample_rate: u32,
+    capacity: usize,
+) -> (RingWriter<F>, RingReader<F>) {
+    let shared = Arc::new(Shared {
+        buffer: (0..capacity * F::CHAN_COUNT)
+            .map(|_| AtomicU32::new(0))
+            .collect(),
+        capacity,
+        sample_rate: sample_rate.into(),
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+        underruns: AtomicUsize::new(0),
+        overruns: AtomicUsize::new(0),
+        reader: Waiter::default(),
+        writer: Waiter::default(),
+    });
+    (
+        RingWriter(shared.clone(), PhantomData),
+        RingReader(shared, PhantomData),
+    )
+}
+
+/// A task waiting on the other end of the ring.
+#[derive(Default)]
+struct Waiter {
+    /// Whether a waker has been registered, so the lock can be skipped.
+    waiting: AtomicBool,
+    /// Waker of the task.
+    waker: Mutex<Option<Waker>>,
+}
+
+impl Waiter {
+    /// Register a waker.  The ring must be checked again afterwards.
+    fn register(&self, waker: &Waker) {
+        *self.waker.lock().unwrap() = Some(waker.clone());
+        self.waiting.store(true, Ordering::SeqCst);
+        // Check the ring only after the waker is visible to the other end.
+        atomic::fence(Ordering::SeqCst);
+    }
+
+    /// Wake the task, if one is waiting.
+    fn wake(&self) {
+        // Check for a waker only after the ring is updated.
+        atomic::fence(Ordering::SeqCst);
+        if self.waiting.swap(false, Ordering::SeqCst) {
+            if let Some(waker) = self.waker.lock().unwrap().take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// State shared between the ends of the ring.
+struct Shared {
+    /// Interleaved channels, stored as `f32` bits.
+    buffer: Box<[AtomicU32]>,
+    /// Number of frames the ring holds.
+    capacity: usize,
+    /// Sample rate of the audio in the ring.
+    sample_rate: f64,
+    /// Total number of frames written, modulo twice the capacity.
+    written: AtomicUsize,
+    /// Total number of frames read, modulo twice the capacity.
+    read: AtomicUsize,
+    /// Number of periods that weren't completely filled.
+    underruns: AtomicUsize,
+    /// Number of frames dropped because the ring was full.
+    overruns: AtomicUsize,
+    /// Reader waiting for frames.
+    reader: Waiter,
+    /// Writer waiting for space.
+    writer: Waiter,
+}
+
+impl Shared {
+    /// Number of frames that can be read.
+    fn len(&self) -> usize {
+        let written = self.written.load(Ordering::Acquire);
+        let read = self.read.load(Ordering::Acquire);
+        (written + self.wrap() - read) % self.wrap()
+    }
+
+    /// Where the indices wrap around, a multiple of the capacity.
+    fn wrap(&self) -> usize {
+        2 * self.capacity.max(1)
+    }
+
+    /// Move `index` forward by `frames`, wrapping at a multiple of the
+    /// capacity so that slots stay in order.
+    fn advance(&self, index: usize, frames: usize) -> usize {
+        (index + frames) % self.wrap()
+    }
+}
+
+/// Writing end of a [`ring`].  Notifier produces the number of frames that
+/// can be written, once there's space.
+pub struct RingWriter<F: Frame<Chan = Ch32>>(Arc<Shared>, PhantomData<F>);
+
+impl<F: Frame<Chan = Ch32>> Debug for RingWriter<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "RingWriter(space: {})", self.space())
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> RingWriter<F> {
+    /// Get the number of frames that can be written without overrunning.
+    pub fn space(&self) -> usize {
+        self.0.capacity - self.0.len()
+    }
+
+    /// Write as many `frames` as fit, and return how many were written.
+    pub fn write(&mut self, frames: &[F]) -> usize {
+        self.write_iter(&mut frames.iter().copied())
+    }
+
+    /// Write all frames of `stream` (such as a
+    /// [`MicrophoneStream`](crate::MicrophoneStream)) without resampling,
+    /// dropping those that don't fit, which are counted as overruns.
+    ///
+    /// ```
+    /// use fon::{mono::Mono32, Audio};
+    /// use wavy::ring;
+    ///
+    /// let (mut writer, _reader) = ring::<Mono32>(48_000, 100);
+    /// let audio = Audio::<Mono32>::with_silence(48_000, 150);
+    /// writer.stream(&audio);
+    /// assert_eq!(writer.space(), 0);
+    /// assert_eq!(writer.overruns(), 50);
+    /// ```
+    pub fn stream<S: Stream<F>>(&mut self, stream: S) {
+        let mut frames = stream.into_iter();
+        self.write_iter(&mut frames);
+        self.0.overruns.fetch_add(frames.count(), Ordering::Relaxed);
+    }
+
+    /// Write frames from `frames` into the free slots until the ring is full
+    /// or `frames` runs out, and return how many were written.
+    fn write_iter(&mut self, frames: &mut impl Iterator<Item = F>) -> usize {
+        let space = self.space();
+        let start = self.0.written.load(Ordering::Relaxed);
+        let mut count = 0;
+        for frame in frames.take(space) {
+            let slot = (self.0.advance(start, count) % self.0.capacity)
+                * F::CHAN_COUNT;
+            for (dst, chan) in
+                self.0.buffer[slot..].iter().zip(frame.channels())
+            {
+                dst.store(f32::from(*chan).to_bits(), Ordering::Relaxed);
+            }
+            count += 1;
+        }
+        // Publish the frames to the reader.
+        self.0
+            .written
+            .store(self.0.advance(start, count), Ordering::Release);
+        self.0.reader.wake();
+        count
+    }
+
+    /// Get the number of frames dropped by [`RingWriter::stream()`] because
+    /// the ring was full.
+    pub fn overruns(&self) -> usize {
+        self.0.overruns.load(Ordering::Relaxed)
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Notifier for RingWriter<F> {
+    type Event = usize;
+
+    fn poll_next(self: Pin<&mut Self>, e: &mut Exec<'_>) -> Poll<usize> {
+        for register in [false, true] {
+            let space = self.space();
+            if space != 0 {
+                return Ready(space);
+            }
+            if !register {
+                self.0.writer.register(e.waker());
+            }
+        }
+        Pending
+    }
+}
+
+/// Reading end of a [`ring`].  Notifier produces the number of frames that
+/// can be read, once there are any.
+///
+/// `&mut RingReader` is a [`Stream`] of the frames that can be read.
+pub struct RingReader<F: Frame<Chan = Ch32>>(Arc<Shared>, PhantomData<F>);
+
+impl<F: Frame<Chan = Ch32>> Debug for RingReader<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "RingReader(len: {})", self.len())
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> RingReader<F> {
+    /// Get the number of frames that can be read.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if there are no frames to read.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read as many `frames` as are available, and return how many were read.
+    pub fn read(&mut self, frames: &mut [F]) -> usize {
+        let count = self.0.len().min(frames.len());
+        for frame in &mut frames[..count] {
+            *frame = self.pop();
+        }
+        self.0.writer.wake();
+        count
+    }
+
+    /// Stream the frames that can be read into `sink` (such as a
+    /// [`SpeakersSink`](crate::SpeakersSink)), counting an underrun if there
+    /// aren't enough to fill it.
+    pub fn fill<S: Sink<F>>(&mut self, sink: &mut S) {
+        let ratio = self.0.sample_rate / sink.sample_rate();
+        let needed = (sink.buffer().len() as f64 * ratio).ceil() as usize;
+        if self.0.len() < needed {
+            self.0.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+        sink.stream(self);
+    }
+
+    /// Get the number of times [`RingReader::fill()`] ran out of frames.
+    pub fn underruns(&self) -> usize {
+        self.0.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Read a frame, which must be available (the writer isn't woken).
+    fn pop(&mut self) -> F {
+        let index = self.0.read.load(Ordering::Relaxed);
+        let slot = (index % self.0.capacity) * F::CHAN_COUNT;
+        let mut chans = [Ch32::MID; 8];
+        for (dst, src) in chans.iter_mut().zip(&self.0.buffer[slot..]) {
+            *dst = Ch32::new(f32::from_bits(src.load(Ordering::Relaxed)));
+        }
+        // Free the slot for the writer.
+        self.0
+            .read
+            .store(self.0.advance(index, 1), Ordering::Release);
+        F::from_channels(&chans[..F::CHAN_COUNT])
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Notifier for RingReader<F> {
+    type Event = usize;
+
+    fn poll_next(self: Pin<&mut Self>, e: &mut Exec<'_>) -> Poll<usize> {
+        for register in [false, true] {
+            let len = self.len();
+            if len != 0 {
+                return Ready(len);
+            }
+            if !register {
+                self.0.reader.register(e.waker());
+            }
+        }
+        Pending
+    }
+}
+
+impl<'a, F: Frame<Chan = Ch32>> IntoIterator for &'a mut RingReader<F> {
+    type Item = F;
+    type IntoIter = RingIter<'a, F>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        RingIter(self)
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Stream<F> for &mut RingReader<F> {
+    fn sample_rate(&self) -> Option<f64> {
+        Some(self.0.sample_rate)
+    }
+
+    fn len(&self) -> Option<usize> {
+        Some(RingReader::len(self))
+    }
+}
+
+/// Iterator over the frames that can be read from a [`RingReader`].  The
+/// writer is woken for the space freed once it's dropped.
+#[derive(Debug)]
+pub struct RingIter<'a, F: Frame<Chan = Ch32>>(&'a mut RingReader<F>);
+
+impl<F: Frame<Chan = Ch32>> Iterator for RingIter<'_, F> {
+    type Item = F;
+
+    fn next(&mut self) -> Option<F> {
+        if RingReader::is_empty(self.0) {
+            None
+        } else {
+            Some(self.0.pop())
+        }
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Drop for RingIter<'_, F> {
+    fn drop(&mut self) {
+        self.0 .0.writer.wake();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::Wake;
+
+    use fon::{mono::Mono32, stereo::Stereo32, Audio};
+
+    use super::*;
+
+    /// Counts how many times it's woken.
+    #[derive(Default)]
+    struct Counter(AtomicUsize);
+
+    impl Wake for Counter {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn stereo(i: usize) -> Stereo32 {
+        Stereo32::new(i as f32 / 16.0, -(i as f32) / 16.0)
+    }
+
+    #[test]
+    fn wraparound() {
+        let (mut writer, mut reader) = ring::<Stereo32>(48_000, 4);
+        let mut frames = [Stereo32::default(); 4];
+        for round in 0..5 {
+            let first = round * 3;
+            let input: Vec<_> = (first..first + 3).map(stereo).collect();
+            assert_eq!(writer.write(&input), 3);
+            assert_eq!(writer.space(), 1);
+            assert_eq!(reader.read(&mut frames), 3);
+            assert_eq!(&frames[..3], &input[..]);
+            assert!(reader.is_empty());
+        }
+    }
+
+    #[test]
+    fn index_wraparound() {
+        let (mut writer, mut reader) = ring::<Mono32>(48_000, 3);
+        writer.0.written.store(4, Ordering::Relaxed);
+        writer.0.read.store(4, Ordering::Relaxed);
+        let input = [0.25, 0.5, 0.75].map(Mono32::new);
+        assert_eq!(writer.write(&input), 3);
+        assert_eq!(reader.len(), 3);
+        assert_eq!(writer.space(), 0);
+        let mut frames = [Mono32::default(); 3];
+        assert_eq!(reader.read(&mut frames), 3);
+        assert_eq!(frames, input);
+        assert_eq!(writer.space(), 3);
+    }
+
+    #[test]
+    fn overruns() {
+        let (mut writer, mut reader) = ring::<Mono32>(48_000, 10);
+        let audio = Audio::<Mono32>::with_silence(48_000, 25);
+        writer.stream(&audio);
+        assert_eq!(writer.overruns(), 15);
+        // Writing with `write()` doesn't count overruns.
+        assert_eq!(writer.write(&[Mono32::default(); 5]), 0);
+        assert_eq!(writer.overruns(), 15);
+        reader.read(&mut [Mono32::default(); 4]);
+        writer.stream(&audio);
+        assert_eq!(writer.overruns(), 36);
+        assert_eq!(reader.underruns(), 0);
+    }
+
+    #[test]
+    fn underruns() {
+        let (mut writer, mut reader) = ring::<Mono32>(48_000, 16);
+        let mut audio = Audio::<Mono32>::with_silence(48_000, 8);
+        writer.write(&[Mono32::new(0.5); 12]);
+        reader.fill(&mut audio.sink(..));
+        assert_eq!(reader.underruns(), 0);
+        assert_eq!(reader.len(), 4);
+        reader.fill(&mut audio.sink(..));
+        assert_eq!(reader.underruns(), 1);
+        assert!(reader.is_empty());
+        reader.fill(&mut audio.sink(..));
+        assert_eq!(reader.underruns(), 2);
+        assert_eq!(writer.overruns(), 0);
+    }
+
+    #[test]
+    fn wake_writer_once() {
+        let (mut writer, mut reader) = ring::<Mono32>(48_000, 16);
+        let counter = Arc::new(Counter::default());
+        let waker = Waker::from(counter.clone());
+        writer.write(&[Mono32::new(0.5); 16]);
+        writer.0.writer.register(&waker);
+        reader.read(&mut [Mono32::default(); 8]);
+        assert_eq!(counter.0.load(Ordering::Relaxed), 1);
+        writer.0.writer.register(&waker);
+        reader.fill(&mut Audio::<Mono32>::with_silence(48_000, 8).sink(..));
+        assert_eq!(counter.0.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,330 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    fmt::{Debug, Display, Formatter, Result},
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use fon::{
+    chan::{Ch32, Channel},
+    Audio, Frame, Resampler, Sink, Stream,
+};
+use pasts::prelude::*;
+
+use crate::{resample::Converter, speakers::SpeakersDevice, ResampleQuality};
+
+/// Speakers opened with all of a sound card's channels, so streams can be
+/// routed to any range of them (with a [`Route`]).  Notifier produces a
+/// [`RoutedSink`] each period.
+///
+/// Channels no route writes to in a period are silent.
+///
+/// ```
+/// use fon::{stereo::Stereo32, surround::Surround32, Audio, Sink};
+/// use pasts::prelude::*;
+/// use wavy::{Clock, Route, Speakers};
+///
+/// async fn run() {
+///     let speakers = Speakers::<2>::null(Clock::Virtual, 48_000);
+///     let mut speakers = speakers.routed();
+///     assert_eq!(speakers.channels(), 6);
+///     // Program on the first two channels, and a click on the last two.
+///     let mut program = Route::<Stereo32>::new(0);
+///     let mut click = Route::<Stereo32>::new(4);
+///     let music = vec![Stereo32::new(0.5, -0.5); 64];
+///     let mut music = Audio::with_frames(48_000, music);
+///     let tick = vec![Stereo32::new(1.0, 1.0); 64];
+///     let mut tick = Audio::with_frames(48_000, tick);
+///
+///     let mut sink = speakers.next().await;
+///     sink.route(&mut program).stream(music.drain());
+///     sink.route(&mut click).stream(tick.drain());
+///     drop(sink);
+///     speakers.next().await;
+///
+///     let output = speakers.take_output::<Surround32>().unwrap();
+///     let expected = Surround32::new(0.5, -0.5, 0.0, 0.0, 1.0, 1.0);
+///     assert!(output.iter().all(|f| *f == expected));
+/// }
+///
+/// pasts::Executor::default().spawn(run());
+/// ```
+pub struct RoutedSpeakers {
+    /// The device audio is played through.
+    device: SpeakersDevice,
+    /// Number of channels the device is opened with.
+    channels: usize,
+    /// Interleaved audio of the last sink, sent to the device before the
+    /// next period.
+    pending: Arc<Mutex<Vec<Ch32>>>,
+    /// Buffer of the last sink once it's sent, lent to the next sink.
+    buffer: Vec<Ch32>,
+}
+
+impl Display for RoutedSpeakers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        self.device.fmt(f)
+    }
+}
+
+impl Debug for RoutedSpeakers {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "RoutedSpeakers({}, channels: {})", self, self.channels)
+    }
+}
+
+impl RoutedSpeakers {
+    /// Open `device` with the most channels it supports.
+    pub(crate) fn new(device: SpeakersDevice) -> Self {
+        let supported = device.channels();
+        let channels = (8 - supported.leading_zeros() as usize).max(1);
+        Self {
+            device,
+            channels,
+            pending: Arc::default(),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Get the number of channels of the sound card.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Get the sample rate, or `None` if it isn't known until the speakers
+    /// are configured by the first period.
+    pub fn sample_rate(&self) -> Option<f64> {
+        self.device.sample_rate()
+    }
+
+    /// Take the audio played so far through
+    /// [`Speakers::null()`](crate::Speakers::null) speakers, converted to
+    /// frames of `F`.
+    ///
+    /// Returns `None` if the routed speakers weren't made from null speakers
+    /// (such as a sound card, a WAV file or a loopback).
+    pub fn take_output<F: Frame<Chan = Ch32>>(&mut self) -> Option<Audio<F>> {
+        match &mut self.device {
+            SpeakersDevice::Ffi(_) => None,
+            SpeakersDevice::Virt(speakers) => speakers.take_output(),
+        }
+    }
+}
+
+impl Notifier for RoutedSpeakers {
+    type Event = RoutedSink;
+
+    fn poll_next(self: Pin<&mut Self>, e: &mut Exec<'_>) -> Poll<RoutedSink> {
+        let this = self.get_mut();
+        let pending = mem::take(&mut *this.pending.lock().unwrap());
+        if !pending.is_empty() {
+            this.device.write_raw(&pending);
+            this.buffer = pending;
+        }
+        if let Ready(()) = Pin::new(&mut this.device).poll(e) {
+            let frames = this.device.play_raw(this.channels as u8);
+            // Lend the last sink's buffer, so it isn't reallocated.
+            let mut buffer = mem::take(&mut this.buffer);
+            buffer.clear();
+            buffer.resize(frames * this.channels, Ch32::MID);
+            Ready(RoutedSink {
+                buffer,
+                channels: this.channels,
+                sample_rate: this
+                    .sample_rate()
+                    .unwrap_or_else(|| crate::consts::SAMPLE_RATE.into()),
+                pending: this.pending.clone(),
+            })
+        } else {
+            Pending
+        }
+    }
+}
+
+/// A period of [`RoutedSpeakers`], played once dropped.
+pub struct RoutedSink {
+    /// Interleaved audio of every channel.
+    buffer: Vec<Ch32>,
+    /// Number of channels.
+    channels: usize,
+    /// Sample rate of the speakers.
+    sample_rate: f64,
+    /// Where the audio is sent once the sink is dropped.
+    pending: Arc<Mutex<Vec<Ch32>>>,
+}
+
+impl Debug for RoutedSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "RoutedSink(channels: {})", self.channels)
+    }
+}
+
+impl RoutedSink {
+    /// Get the number of channels.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Get the number of frames in the period.
+    pub fn len(&self) -> usize {
+        self.buffer.len() / self.channels
+    }
+
+    /// Check if the period has no frames.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Get the sample rate of the speakers.
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Get the interleaved audio of every channel, to write it directly.
+    pub fn buffer(&mut self) -> &mut [Ch32] {
+        &mut self.buffer
+    }
+
+    /// Get a sink writing frames of `route` to its channels.
+    ///
+    /// # Panics
+    /// If the route's channels go past the sound card's channels.
+    pub fn route<'a, F>(
+        &'a mut self,
+        route: &'a mut Route<F>,
+    ) -> RouteSink<'a, F>
+    where
+        F: Frame<Chan = Ch32>,
+    {
+        assert!(
+            route.first + F::CHAN_COUNT <= self.channels,
+            "Route to channels {}..{} of {}",
+            route.first,
+            route.first + F::CHAN_COUNT,
+            self.channels,
+        );
+        let mut buffer = mem::take(&mut route.buffer);
+        buffer.clear();
+        buffer.resize(self.len(), F::default());
+        RouteSink {
+            sink: self,
+            route,
+            buffer,
+        }
+    }
+}
+
+impl Drop for RoutedSink {
+    fn drop(&mut self) {
+        *self.pending.lock().unwrap() = mem::take(&mut self.buffer);
+    }
+}
+
+/// A stream of frames of `F`, bound to consecutive channels of
+/// [`RoutedSpeakers`].  Keeps the sample rate converter's state between
+/// periods.
+pub struct Route<F: Frame<Chan = Ch32>> {
+    /// First channel written.
+    first: usize,
+    /// Sample rate conversion of audio streamed in.
+    converter: Converter,
+    /// Resampler context, for [`Sink`] impls that use it.
+    resampler: Resampler<F>,
+    /// Buffer to reuse for sinks.
+    buffer: Vec<F>,
+}
+
+impl<F: Frame<Chan = Ch32>> Debug for Route<F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let last = self.first + F::CHAN_COUNT;
+        write!(f, "Route(channels: {}..{})", self.first, last)
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Route<F> {
+    /// Create a route writing to channels starting at `first_channel` (from
+    /// 0), one for each channel of `F`.
+    pub fn new(first_channel: usize) -> Self {
+        Self {
+            first: first_channel,
+            converter: Converter::default(),
+            resampler: Resampler::new(F::default(), 0.0),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Get the first channel written.
+    pub fn first_channel(&self) -> usize {
+        self.first
+    }
+
+    /// Get how audio streamed in at other sample rates is converted.
+    pub fn resample_quality(&self) -> ResampleQuality {
+        self.converter.quality()
+    }
+
+    /// Set how audio streamed in at other sample rates is converted.
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.converter.set_quality(quality);
+    }
+}
+
+/// Sink for a [`Route`] in a period, writing to the route's channels once
+/// dropped.
+pub struct RouteSink<'a, F: Frame<Chan = Ch32>> {
+    /// The period written to.
+    sink: &'a mut RoutedSink,
+    /// The route written.
+    route: &'a mut Route<F>,
+    /// Frames for the period.
+    buffer: Vec<F>,
+}
+
+impl<F: Frame<Chan = Ch32>> Debug for RouteSink<'_, F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "RouteSink({:?})", self.route)
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Sink<F> for RouteSink<'_, F> {
+    fn sample_rate(&self) -> f64 {
+        self.sink.sample_rate
+    }
+
+    fn resampler(&mut self) -> &mut Resampler<F> {
+        &mut self.route.resampler
+    }
+
+    fn buffer(&mut self) -> &mut [F] {
+        &mut self.buffer
+    }
+
+    fn stream<S: Frame, M: Stream<S>>(&mut self, stream: M) {
+        let source_rate = stream.sample_rate();
+        let sample_rate = self.sink.sample_rate;
+        let buffer = &mut self.buffer;
+        self.route
+            .converter
+            .stream(source_rate, sample_rate, stream, buffer);
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Drop for RouteSink<'_, F> {
+    fn drop(&mut self) {
+        let channels = self.sink.channels;
+        let first = self.route.first;
+        let frames = self.sink.buffer.chunks_mut(channels);
+        for (dst, src) in frames.zip(&self.buffer) {
+            dst[first..][..F::CHAN_COUNT].copy_from_slice(src.channels());
+        }
+        self.route.buffer = mem::take(&mut self.buffer);
+    }
+}
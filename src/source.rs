@@ -0,0 +1,24 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+/// What a [`Microphone`](crate::Microphone) records.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SourceKind {
+    /// Sound from outside the computer (microphone, line in, etc.).
+    Input,
+    /// The capture end of a loopback device, which records what's played
+    /// into its playback end (ALSA's `snd-aloop`, or a [`Loopback`]
+    /// pair).
+    ///
+    /// [`Loopback`]: crate::Loopback
+    Loopback,
+    /// A monitor, which records what's played through an output device
+    /// (PulseAudio or PipeWire `.monitor` sources).
+    Monitor,
+}
@@ -9,17 +9,133 @@
 
 #![allow(clippy::needless_doctest_main)]
 
-use std::fmt::{Debug, Display, Formatter, Result};
+use std::{
+    fmt::{Debug, Display, Formatter, Result},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use fon::{chan::Ch32, Frame, Resampler, Sink};
+use fon::{chan::Ch32, Audio, Frame, Resampler, Sink, Stream};
 use pasts::prelude::*;
 
-use crate::ffi;
+use crate::{
+    blocking, ffi,
+    gain::{Gain, Ramp},
+    meter::Metering,
+    resample::Converter,
+    virt,
+    wav::WavWriter,
+    ChannelMap, ChannelPosition, Clock, EchoReference, Meter, ResampleQuality,
+    RoutedSpeakers, StreamConfig, VolumeControl, WavFormat,
+};
+
+/// Device backing a [`Speakers`], chosen at runtime.
+pub(crate) enum SpeakersDevice {
+    /// A sound card, through the platform's audio API.
+    Ffi(Box<ffi::Speakers>),
+    /// A virtual device.
+    Virt(Box<virt::Speakers>),
+}
+
+impl Default for SpeakersDevice {
+    fn default() -> Self {
+        Self::Ffi(Box::default())
+    }
+}
+
+impl Display for SpeakersDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::Ffi(speakers) => speakers.fmt(f),
+            Self::Virt(speakers) => speakers.fmt(f),
+        }
+    }
+}
+
+impl SpeakersDevice {
+    pub(crate) fn channels(&self) -> u8 {
+        match self {
+            Self::Ffi(speakers) => speakers.channels(),
+            Self::Virt(speakers) => speakers.channels(),
+        }
+    }
+
+    pub(crate) fn sample_rate(&self) -> Option<f64> {
+        match self {
+            Self::Ffi(speakers) => speakers.sample_rate(),
+            Self::Virt(speakers) => speakers.sample_rate(),
+        }
+    }
+
+    fn stream_config(&self) -> Option<StreamConfig> {
+        match self {
+            Self::Ffi(speakers) => speakers.stream_config(),
+            Self::Virt(speakers) => speakers.stream_config(),
+        }
+    }
+
+    fn channel_positions(&self) -> Vec<ChannelPosition> {
+        match self {
+            Self::Ffi(speakers) => speakers.channel_positions(),
+            Self::Virt(_) => Vec::new(),
+        }
+    }
+
+    fn volume_controls(&self) -> Vec<VolumeControl> {
+        match self {
+            Self::Ffi(speakers) => speakers
+                .volume_controls()
+                .into_iter()
+                .map(VolumeControl)
+                .collect(),
+            Self::Virt(_) => Vec::new(),
+        }
+    }
+
+    fn play<F: Frame<Chan = Ch32>>(&mut self) -> SinkDevice<F> {
+        match self {
+            Self::Ffi(speakers) => SinkDevice::Ffi(speakers.play()),
+            Self::Virt(speakers) => SinkDevice::Virt(speakers.play()),
+        }
+    }
+
+    /// Configure for `channels` interleaved channels, and get the number of
+    /// frames to write for the period.
+    pub(crate) fn play_raw(&mut self, channels: u8) -> usize {
+        match self {
+            Self::Ffi(speakers) => speakers.play_raw(channels),
+            Self::Virt(speakers) => speakers.play_raw(channels),
+        }
+    }
+
+    /// Queue interleaved audio for the period.
+    pub(crate) fn write_raw(&mut self, audio: &[Ch32]) {
+        match self {
+            Self::Ffi(speakers) => speakers.write_raw(audio),
+            Self::Virt(speakers) => speakers.write_raw(audio),
+        }
+    }
+}
+
+impl Future for SpeakersDevice {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, e: &mut Exec<'_>) -> Poll<()> {
+        match self.get_mut() {
+            Self::Ffi(speakers) => Pin::new(speakers).poll(e),
+            Self::Virt(speakers) => Pin::new(speakers).poll(e),
+        }
+    }
+}
 
 /// Play audio through speakers.  Notifier produces an audio sink, which
 /// consumes an audio stream of played samples.  If you don't write to the sink,
 /// it will keep playing whatever was last streamed into it.
 ///
+/// Speakers are `Send`, so they can be opened on one thread and used on
+/// another (such as an [`AudioThread`](crate::AudioThread)).
+///
 /// # 440 HZ Sine Wave Example
 /// **note:** This example depends on `twang = "0.5"` to synthesize the sine
 /// wave.
@@ -59,11 +175,31 @@ use crate::ffi;
 /// }
 /// ```
 #[derive(Default)]
-pub struct Speakers<const N: usize>(pub(super) ffi::Speakers);
+pub struct Speakers<const N: usize> {
+    /// The device audio is played through.
+    device: SpeakersDevice,
+    /// Interleaved frames written without a sink, waiting for a period.
+    staged: Vec<Ch32>,
+    /// Number of frames in a period (0 until known).
+    period: usize,
+    /// Whether the last period filled has written frames, which are only sent
+    /// to the device once the next period is ready.
+    unflushed: bool,
+    /// Software volume, ramped from period to period.
+    gain: Box<Gain>,
+    /// Level meter, if enabled.
+    meter: Option<Arc<Metering>>,
+    /// Sample rate conversion, shared with sinks.
+    converter: Arc<Mutex<Converter>>,
+    /// Channel mixing of audio streamed into sinks, if set.
+    channel_map: Option<Arc<ChannelMap>>,
+    /// Audio played, for echo cancellation, if enabled.
+    echo_reference: Option<EchoReference>,
+}
 
 impl<const N: usize> Display for Speakers<N> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        self.0.fmt(f)
+        self.device.fmt(f)
     }
 }
 
@@ -75,12 +211,176 @@ impl<const N: usize> Debug for Speakers<N> {
 
 impl Speakers<0> {
     /// Query available audio destinations.
+    ///
+    /// Sound cards are opened shared where they support it (through `dmix`
+    /// on Linux), so several `Speakers`, in this process or others, can play
+    /// through the same card at once.
     pub fn query() -> Vec<Self> {
-        ffi::device_list(Self)
+        let mut list = ffi::device_list(false, |speakers| {
+            Self::new(SpeakersDevice::Ffi(Box::new(speakers)))
+        });
+        for link in virt::Link::query() {
+            list.push(Self::from_virt(virt::Speakers::loopback(link)));
+        }
+        list
+    }
+
+    /// Query sound cards to play through directly, without any conversion by
+    /// the platform (`hw` devices on Linux, opened with every automatic
+    /// conversion disabled).  Cards are opened in their own sample format,
+    /// and [`Speakers::config()`] fails for numbers of channels they don't
+    /// have.  Unlike [`Speakers::query()`], nothing else can play through
+    /// these cards while they're open.
+    ///
+    /// Resampling of audio streamed into sinks is
+    /// [`ResampleQuality::Off`], so it must be at the card's sample rate (see
+    /// [`Speakers::stream_config()`]), or streaming it panics, unless turned
+    /// on with [`Speakers::set_resample_quality()`].
+    ///
+    /// ```no_run
+    /// use wavy::Speakers;
+    ///
+    /// let speakers = Speakers::query_exclusive().pop().unwrap();
+    /// let speakers = speakers.config::<2>().unwrap();
+    /// ```
+    pub fn query_exclusive() -> Vec<Self> {
+        ffi::device_list(true, |speakers| {
+            let mut speakers =
+                Self::new(SpeakersDevice::Ffi(Box::new(speakers)));
+            speakers.set_resample_quality(ResampleQuality::Off);
+            speakers
+        })
     }
 }
 
 impl<const N: usize> Speakers<N> {
+    fn new(device: SpeakersDevice) -> Self {
+        Self {
+            device,
+            staged: Vec::new(),
+            period: 0,
+            unflushed: false,
+            gain: Box::default(),
+            meter: None,
+            converter: Arc::default(),
+            channel_map: None,
+            echo_reference: None,
+        }
+    }
+
+    /// Wrap a virtual device.
+    pub(crate) fn from_virt(speakers: virt::Speakers) -> Self {
+        Self::new(SpeakersDevice::Virt(Box::new(speakers)))
+    }
+
+    /// Create speakers that aren't connected to a sound card.  Periods are
+    /// paced by `clock` at `sample_rate`, and everything streamed into the
+    /// [`SpeakersSink`] is kept in memory until retrieved with
+    /// [`Speakers::take_output()`].
+    ///
+    /// ```
+    /// use fon::{mono::Mono32, Sink};
+    /// use pasts::prelude::*;
+    /// use wavy::{Clock, Speakers};
+    ///
+    /// async fn run() {
+    ///     let mut speakers = Speakers::<1>::null(Clock::Virtual, 48_000);
+    ///     for _ in 0..4 {
+    ///         let mut sink = speakers.next().await;
+    ///         sink.stream(Mono32::new(0.5));
+    ///     }
+    ///     let _ = speakers.next().await;
+    ///     let output = speakers.take_output().unwrap();
+    ///     assert_eq!(output.len(), 64 * 4);
+    ///     assert!(output.iter().all(|frame| *frame == Mono32::new(0.5)));
+    /// }
+    ///
+    /// pasts::Executor::default().spawn(run());
+    /// ```
+    pub fn null(clock: Clock, sample_rate: u32) -> Self {
+        Self::from_virt(virt::Speakers::new(clock, sample_rate))
+    }
+
+    /// Create speakers that play into a WAV file at `path`, encoded with
+    /// `format`.  Periods are paced by `clock` at `sample_rate`.  The file
+    /// header is finalized when the speakers are dropped.
+    ///
+    /// ```
+    /// use fon::{stereo::Stereo32, Frame, Sink};
+    /// use pasts::prelude::*;
+    /// use wavy::{Clock, Microphone, Speakers, WavFormat};
+    ///
+    /// async fn run() {
+    ///     let path = std::env::temp_dir().join("wavy-doc-wav.wav");
+    ///     let mut speakers: Speakers<2> =
+    ///         Speakers::wav(Clock::Virtual, 48_000, &path, WavFormat::Pcm16)
+    ///             .unwrap();
+    ///     for _ in 0..3 {
+    ///         speakers.next().await.stream(Stereo32::new(0.5, -0.5));
+    ///     }
+    ///     drop(speakers);
+    ///     // Three periods of 64 frames, with 4 bytes per frame.
+    ///     let size = std::fs::metadata(&path).unwrap().len();
+    ///     assert_eq!(size, 44 + 3 * 64 * 4);
+    ///
+    ///     let mut microphone: Microphone<2> =
+    ///         Microphone::wav(Clock::Virtual, &path).unwrap();
+    ///     let mut recorded = Vec::new();
+    ///     while recorded.len() < 128 {
+    ///         recorded.extend(microphone.next().await);
+    ///     }
+    ///     let left = f32::from(recorded[0].channels()[0]);
+    ///     let right = f32::from(recorded[0].channels()[1]);
+    ///     assert!((left - 0.5).abs() < 0.001);
+    ///     assert!((right + 0.5).abs() < 0.001);
+    ///     std::fs::remove_file(path).unwrap();
+    /// }
+    ///
+    /// pasts::Executor::default().spawn(run());
+    /// ```
+    pub fn wav<P: AsRef<Path>>(
+        clock: Clock,
+        sample_rate: u32,
+        path: P,
+        format: WavFormat,
+    ) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let writer = WavWriter::create(path, format, sample_rate)?;
+        Ok(Self::from_virt(virt::Speakers::wav(
+            clock,
+            sample_rate,
+            path.display().to_string(),
+            writer,
+        )))
+    }
+
+    /// Take the error that stopped [`Speakers::wav()`] speakers writing to
+    /// their file, if any (audio played afterwards isn't written).  Errors
+    /// from updating the header are found by
+    /// [`Speakers::flush_blocking()`]; those on drop are ignored.
+    ///
+    /// ```
+    /// # #[cfg(target_os = "linux")] {
+    /// use fon::mono::Mono32;
+    /// use wavy::{Clock, Speakers, WavFormat};
+    ///
+    /// // A device that's always out of space.
+    /// let mut speakers: Speakers<1> =
+    ///     Speakers::wav(Clock::Virtual, 48_000, "/dev/full", WavFormat::Pcm16)
+    ///         .unwrap();
+    /// speakers.write_blocking(&[Mono32::new(0.5); 64]);
+    /// assert!(speakers.take_error().is_none());
+    /// speakers.flush_blocking();
+    /// assert!(speakers.take_error().is_some());
+    /// # }
+    /// ```
+    pub fn take_error(&mut self) -> Option<std::io::Error> {
+        match &mut self.device {
+            SpeakersDevice::Ffi(_) => None,
+            SpeakersDevice::Virt(speakers) => speakers.take_error(),
+        }
+    }
+
     /// Try a reconfiguration of speakers.
     pub fn config<const C: usize>(
         self,
@@ -89,12 +389,241 @@ impl<const N: usize> Speakers<N> {
         Speakers<C>: SpeakersProperties,
     {
         let bit = C - 1;
-        if (self.0.channels() & (1 << bit)) != 0 {
-            Ok(Speakers(self.0))
+        if (self.device.channels() & (1 << bit)) != 0 {
+            let mut speakers = Speakers::new(self.device);
+            speakers.gain = self.gain;
+            speakers.meter = self.meter;
+            speakers.converter = self.converter;
+            speakers.channel_map =
+                self.channel_map.filter(|map| map.outputs() == C);
+            speakers.echo_reference = self.echo_reference;
+            Ok(speakers)
         } else {
             Err(self)
         }
     }
+
+    /// Open the sound card with all of its channels, to route streams to
+    /// ranges of them.
+    ///
+    /// Settings of these speakers (such as the volume) don't apply to the
+    /// routed speakers.
+    pub fn routed(self) -> RoutedSpeakers {
+        RoutedSpeakers::new(self.device)
+    }
+
+    /// Get the sample rate of the speakers, or `None` if it isn't known until
+    /// the speakers are configured by the first period.
+    pub fn sample_rate(&self) -> Option<f64> {
+        self.device.sample_rate()
+    }
+
+    /// Get the configuration negotiated with the device, and what the
+    /// platform guarantees about it, or `None` until the speakers are
+    /// configured by the first period.
+    pub fn stream_config(&self) -> Option<StreamConfig> {
+        self.device.stream_config()
+    }
+
+    /// Get the software volume of the speakers.
+    pub fn volume(&self) -> f32 {
+        self.gain.volume()
+    }
+
+    /// Set the software volume of the speakers, as a linear gain applied to
+    /// audio played.  Changes are ramped over a few milliseconds so they
+    /// don't click.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.gain.set_volume(volume);
+    }
+
+    /// Check if the speakers are muted.
+    pub fn is_muted(&self) -> bool {
+        self.gain.is_muted()
+    }
+
+    /// Mute or unmute the speakers, without changing the volume.  Like volume
+    /// changes, muting is ramped so it doesn't click.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.gain.set_muted(muted);
+    }
+
+    /// Fade in to the volume over `duration`, starting from silence (or from
+    /// the current level, if fading out).
+    pub fn fade_in(&mut self, duration: Duration) {
+        self.gain.fade_in(duration);
+    }
+
+    /// Fade out to silence over `duration`.  The speakers stay silent until
+    /// faded in again.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use fon::{mono::Mono32, Frame};
+    /// use wavy::{Clock, Speakers};
+    ///
+    /// let mut speakers = Speakers::<1>::null(Clock::Virtual, 48_000);
+    /// speakers.fade_out(Duration::from_millis(1));
+    /// speakers.write_blocking(&[Mono32::new(0.5); 64]);
+    /// speakers.flush_blocking();
+    /// assert!(speakers.is_faded_out());
+    /// let output = speakers.take_output().unwrap();
+    /// let levels: Vec<f32> =
+    ///     output.iter().map(|f| f.channels()[0].into()).collect();
+    /// assert!(levels.windows(2).all(|w| w[1] <= w[0]));
+    /// assert_eq!(levels[47], 0.0);
+    /// ```
+    pub fn fade_out(&mut self, duration: Duration) {
+        self.gain.fade_out(duration);
+    }
+
+    /// Check if a fade out has finished, so the speakers can be stopped without
+    /// clicking.
+    pub fn is_faded_out(&self) -> bool {
+        self.gain.is_faded_out()
+    }
+
+    /// Get how audio streamed into sinks at other sample rates is converted.
+    pub fn resample_quality(&self) -> ResampleQuality {
+        self.converter.lock().unwrap().quality()
+    }
+
+    /// Set how audio streamed into sinks (with
+    /// [`Sink::stream()`](fon::Sink::stream)) at other sample rates is
+    /// converted to the speakers' sample rate.
+    ///
+    /// ```
+    /// use std::f32::consts::PI;
+    ///
+    /// use fon::{mono::Mono32, Audio, Frame, Sink};
+    /// use pasts::prelude::*;
+    /// use wavy::{Clock, ResampleQuality, Speakers};
+    ///
+    /// /// Play a 10 kHz tone at 44.1 kHz through 48 kHz speakers, and return
+    /// /// the signal to noise ratio in decibels.
+    /// async fn snr(quality: ResampleQuality) -> f32 {
+    ///     let mut speakers = Speakers::<1>::null(Clock::Virtual, 48_000);
+    ///     speakers.set_resample_quality(quality);
+    ///     let w = 2.0 * PI * 10_000.0 / 44_100.0;
+    ///     let tone = (0..8_000).map(|i| (w * i as f32).sin() / 2.0);
+    ///     let tone: Vec<Mono32> = tone.map(Mono32::new).collect();
+    ///     let mut audio = Audio::with_frames(44_100, tone);
+    ///     for _ in 0..100 {
+    ///         speakers.next().await.stream(audio.drain());
+    ///     }
+    ///     speakers.next().await;
+    ///     let output = speakers.take_output().unwrap();
+    ///     let output: Vec<f32> = output
+    ///         .iter()
+    ///         .skip(1000)
+    ///         .map(|f| f.channels()[0].into())
+    ///         .collect();
+    ///     // Fit a 10 kHz sine, and measure what's left.
+    ///     let w = 2.0 * PI * 10_000.0 / 48_000.0;
+    ///     let (mut s, mut c) = (0.0, 0.0);
+    ///     for (i, x) in output.iter().enumerate() {
+    ///         s += x * (w * i as f32).sin();
+    ///         c += x * (w * i as f32).cos();
+    ///     }
+    ///     let len = output.len() as f32;
+    ///     let (s, c) = (2.0 * s / len, 2.0 * c / len);
+    ///     let (mut signal, mut noise) = (0.0, 0.0);
+    ///     for (i, x) in output.iter().enumerate() {
+    ///         let fit = s * (w * i as f32).sin() + c * (w * i as f32).cos();
+    ///         signal += fit * fit;
+    ///         noise += (x - fit) * (x - fit);
+    ///     }
+    ///     10.0 * (signal / noise).log10()
+    /// }
+    ///
+    /// pasts::Executor::default().spawn(async {
+    ///     let linear = snr(ResampleQuality::Linear).await;
+    ///     let sinc = snr(ResampleQuality::Sinc(32)).await;
+    ///     println!("linear: {linear} dB, sinc: {sinc} dB");
+    ///     assert!(sinc > 60.0);
+    ///     assert!(sinc > linear + 20.0);
+    /// });
+    /// ```
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.converter.lock().unwrap().set_quality(quality);
+    }
+
+    /// Enable metering of the levels played (after the software volume),
+    /// returning the meter.  Metering is off until this is first called, and
+    /// later calls return the same meter.
+    pub fn meter(&mut self) -> Meter {
+        self.meter
+            .get_or_insert_with(Default::default)
+            .meter
+            .clone()
+    }
+
+    /// Enable keeping the audio played (after the software volume) as the
+    /// reference of an [`EchoCanceller`](crate::EchoCanceller), returning the
+    /// reference.  Later calls return the same reference.
+    pub fn echo_reference(&mut self) -> EchoReference {
+        self.echo_reference
+            .get_or_insert_with(EchoReference::default)
+            .clone()
+    }
+
+    /// Get the channel map audio streamed into sinks is mixed with.
+    pub fn channel_map(&self) -> Option<ChannelMap> {
+        self.channel_map.as_deref().copied()
+    }
+
+    /// Set how audio streamed into sinks (with
+    /// [`Sink::stream()`](fon::Sink::stream)) is mixed to the speakers'
+    /// channels, replacing fon's conversion for audio with the map's number
+    /// of input channels.  Audio with other numbers of channels is still
+    /// converted by fon.  The map is kept by [`Speakers::config()`] if it
+    /// still has the right number of outputs.
+    ///
+    /// # Panics
+    /// If the map doesn't have `N` output channels.
+    ///
+    /// ```
+    /// use fon::{stereo::Stereo32, surround::Surround32, Audio, Sink};
+    /// use pasts::prelude::*;
+    /// use wavy::{ChannelMap, Clock, Speakers};
+    ///
+    /// async fn run() {
+    ///     let mut speakers = Speakers::<2>::null(Clock::Virtual, 48_000);
+    ///     speakers.set_channel_map(Some(ChannelMap::surround_to_stereo()));
+    ///     // Center only.
+    ///     let frame = Surround32::new(0.0, 0.0, 0.5, 0.0, 0.0, 0.0);
+    ///     let mut audio = Audio::with_frames(48_000, vec![frame; 64]);
+    ///     speakers.next().await.stream(audio.drain());
+    ///     speakers.next().await;
+    ///     let output = speakers.take_output().unwrap();
+    ///     let half = 0.5f32.sqrt() / 2.0;
+    ///     let expected = Stereo32::new(half, half);
+    ///     assert!(output.iter().all(|f| *f == expected));
+    /// }
+    ///
+    /// pasts::Executor::default().spawn(run());
+    /// ```
+    pub fn set_channel_map(&mut self, map: Option<ChannelMap>) {
+        if let Some(map) = map {
+            assert_eq!(map.outputs(), N, "Channel map has wrong outputs");
+        }
+        self.channel_map = map.map(Arc::new);
+    }
+
+    /// Get the position of each channel of the sound card, in the order of
+    /// frames' channels (empty until configured by the first period, for
+    /// virtual devices, and on platforms that don't report them).
+    pub fn channel_positions(&self) -> Vec<ChannelPosition> {
+        self.device.channel_positions()
+    }
+
+    /// Get the hardware playback volume controls of the sound card the speakers
+    /// are on (empty for virtual devices, and on platforms without
+    /// them).
+    pub fn volume_controls(&self) -> Vec<VolumeControl> {
+        self.device.volume_controls()
+    }
 }
 
 pub trait SpeakersProperties {
@@ -113,6 +642,171 @@ impl SpeakersProperties for Speakers<6> {
     type Sample = fon::surround::Surround32;
 }
 
+impl<const N: usize> Speakers<N>
+where
+    Speakers<N>: SpeakersProperties,
+{
+    /// Take the audio played so far through [`Speakers::null()`] speakers.
+    ///
+    /// Returns `None` if these speakers aren't null speakers.
+    pub fn take_output(
+        &mut self,
+    ) -> Option<Audio<<Self as SpeakersProperties>::Sample>> {
+        match &mut self.device {
+            SpeakersDevice::Ffi(_) => None,
+            SpeakersDevice::Virt(speakers) => speakers.take_output(),
+        }
+    }
+
+    /// Write as many `frames` as fit without blocking, and return how many
+    /// were written.
+    ///
+    /// Frames are played at [`Speakers::sample_rate()`] without resampling.
+    /// Up to a period of frames is buffered until the rest of the period is
+    /// written (see [`Speakers::flush_blocking()`]).
+    pub fn try_write(
+        &mut self,
+        frames: &[<Self as SpeakersProperties>::Sample],
+    ) -> usize {
+        let mut written = 0;
+        loop {
+            written += self.stage(&frames[written..]);
+            if self.period != 0 && self.staged.len() < self.period * N {
+                return written;
+            }
+            // A period is full, or the period isn't known yet.
+            match blocking::poll_now(|cx| {
+                self.poll_write(cx, &frames[written..])
+            }) {
+                Ready(count) => written += count,
+                Pending => return written,
+            }
+        }
+    }
+
+    /// Write all of `frames`, blocking the thread until they fit.  This
+    /// doesn't require an async executor.
+    ///
+    /// Frames are played at [`Speakers::sample_rate()`] without resampling.
+    /// Up to a period of frames is buffered until the rest of the period is
+    /// written (see [`Speakers::flush_blocking()`]).
+    ///
+    /// ```
+    /// use fon::mono::Mono32;
+    /// use wavy::{Clock, Speakers};
+    ///
+    /// let mut speakers = Speakers::<1>::null(Clock::Virtual, 48_000);
+    /// speakers.write_blocking(&[Mono32::new(0.5); 100]);
+    /// speakers.flush_blocking();
+    /// let output = speakers.take_output().unwrap();
+    /// assert_eq!(output.len(), 128);
+    /// assert!(output.iter().take(100).all(|f| *f == Mono32::new(0.5)));
+    /// assert!(output.iter().skip(100).all(|f| *f == Mono32::new(0.0)));
+    /// ```
+    pub fn write_blocking(
+        &mut self,
+        frames: &[<Self as SpeakersProperties>::Sample],
+    ) {
+        let mut written = 0;
+        loop {
+            written += self.try_write(&frames[written..]);
+            if written == frames.len() {
+                return;
+            }
+            written +=
+                blocking::wait(|cx| self.poll_write(cx, &frames[written..]));
+        }
+    }
+
+    /// Play frames buffered by [`Speakers::write_blocking()`] or
+    /// [`Speakers::try_write()`], padding the last period with silence, and
+    /// block the thread until they've been handed to the device.  The header
+    /// of a [`Speakers::wav()`] file is updated, so it's valid up to here.
+    pub fn flush_blocking(&mut self) {
+        blocking::wait(|cx| self.poll_flush(cx));
+    }
+
+    /// Buffer all of `frames`, even if there are more than fit in the period.
+    #[cfg(feature = "futures")]
+    pub(crate) fn push(
+        &mut self,
+        frames: &[<Self as SpeakersProperties>::Sample],
+    ) {
+        for frame in frames {
+            self.staged.extend_from_slice(frame.channels());
+        }
+    }
+
+    /// Hand full periods of buffered frames to the device, until less than a
+    /// period is buffered.
+    #[cfg(feature = "futures")]
+    pub(crate) fn poll_ready(&mut self, cx: &mut Exec<'_>) -> Poll<()> {
+        while self.staged.len() >= self.period.max(1) * N {
+            if self.poll_write(cx, &[]).is_pending() {
+                return Pending;
+            }
+        }
+        Ready(())
+    }
+
+    /// Hand all buffered frames to the device, padding the last period with
+    /// silence.
+    pub(crate) fn poll_flush(&mut self, cx: &mut Exec<'_>) -> Poll<()> {
+        // The last period is sent to the device when the next one is ready.
+        while !self.staged.is_empty() || self.unflushed {
+            if self.poll_write(cx, &[]).is_pending() {
+                return Pending;
+            }
+        }
+        if let SpeakersDevice::Virt(speakers) = &mut self.device {
+            speakers.sync();
+        }
+        Ready(())
+    }
+
+    /// Buffer as many `frames` as fit in the period, returning how many did.
+    fn stage(
+        &mut self,
+        frames: &[<Self as SpeakersProperties>::Sample],
+    ) -> usize {
+        let space = self.period.saturating_sub(self.staged.len() / N);
+        let count = space.min(frames.len());
+        for frame in &frames[..count] {
+            self.staged.extend_from_slice(frame.channels());
+        }
+        count
+    }
+
+    /// Fill the next period with buffered frames, followed by `frames`, once
+    /// the speakers are ready, returning how many of `frames` were used.
+    fn poll_write(
+        &mut self,
+        cx: &mut Exec<'_>,
+        frames: &[<Self as SpeakersProperties>::Sample],
+    ) -> Poll<usize> {
+        let mut sink = match Pin::new(&mut *self).poll_next(cx) {
+            Ready(sink) => sink,
+            Pending => return Pending,
+        };
+        let buffer = sink.buffer();
+        if self.period == 0 {
+            self.period = buffer.len();
+        }
+        let staged = (self.staged.len() / N).min(buffer.len());
+        for (dst, src) in buffer.iter_mut().zip(self.staged.chunks(N)) {
+            *dst = Frame::from_channels(src);
+        }
+        self.staged.drain(..staged * N);
+        let count = (buffer.len() - staged).min(frames.len());
+        buffer[staged..][..count].copy_from_slice(&frames[..count]);
+        for dst in &mut buffer[staged + count..] {
+            *dst = Default::default();
+        }
+        self.unflushed = staged + count != 0;
+        Ready(count)
+    }
+}
+
 impl<const N: usize> Notifier for Speakers<N>
 where
     Speakers<N>: SpeakersProperties,
@@ -121,16 +815,55 @@ where
 
     fn poll_next(self: Pin<&mut Self>, e: &mut Exec<'_>) -> Poll<Self::Event> {
         let this = self.get_mut();
-        if let Ready(()) = Pin::new(&mut this.0).poll(e) {
-            Ready(SpeakersSink(this.0.play()))
+        if let Ready(()) = Pin::new(&mut this.device).poll(e) {
+            let mut sink = SpeakersSink {
+                device: this.device.play(),
+                gain: Ramp::default(),
+                meter: this.meter.clone(),
+                converter: this.converter.clone(),
+                channel_map: this.channel_map.clone(),
+                echo_reference: this.echo_reference.clone().map(|reference| {
+                    let config = this.device.stream_config();
+                    (reference, config.map_or(0, |config| config.latency))
+                }),
+            };
+            let frames = sink.buffer().len();
+            sink.gain = this.gain.period(sink.sample_rate(), frames);
+            Ready(sink)
         } else {
             Pending
         }
     }
 }
 
+/// Device backing a [`SpeakersSink`].
+enum SinkDevice<F: Frame<Chan = Ch32>> {
+    Ffi(ffi::SpeakersSink<F>),
+    Virt(virt::SpeakersSink<F>),
+}
+
 /// A sink that consumes audio samples and plays them through the speakers.
-pub struct SpeakersSink<F: Frame<Chan = Ch32>>(ffi::SpeakersSink<F>);
+///
+/// The sink owns its buffer; the audio written to it is queued for the next
+/// period when it's dropped, after the speakers' volume is applied (and the
+/// levels are metered, if enabled).  Audio streamed in at another sample rate
+/// is converted as set by [`Speakers::set_resample_quality()`], and mixed
+/// to the speakers' channels as set by [`Speakers::set_channel_map()`].
+pub struct SpeakersSink<F: Frame<Chan = Ch32>> {
+    /// Device the audio is played through.
+    device: SinkDevice<F>,
+    /// Software volume of the speakers over the period.
+    gain: Ramp,
+    /// Level meter of the speakers, if enabled.
+    meter: Option<Arc<Metering>>,
+    /// Sample rate conversion of the speakers.
+    converter: Arc<Mutex<Converter>>,
+    /// Channel mixing of the speakers, if set.
+    channel_map: Option<Arc<ChannelMap>>,
+    /// Audio played by the speakers, if kept for echo cancellation, and their
+    /// latency in frames.
+    echo_reference: Option<(EchoReference, usize)>,
+}
 
 impl<F: Frame<Chan = Ch32>> Debug for SpeakersSink<F> {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> Result {
@@ -140,14 +873,67 @@ impl<F: Frame<Chan = Ch32>> Debug for SpeakersSink<F> {
 
 impl<F: Frame<Chan = Ch32>> Sink<F> for SpeakersSink<F> {
     fn sample_rate(&self) -> f64 {
-        self.0.sample_rate()
+        match &self.device {
+            SinkDevice::Ffi(sink) => sink.sample_rate(),
+            SinkDevice::Virt(sink) => sink.sample_rate(),
+        }
     }
 
     fn resampler(&mut self) -> &mut Resampler<F> {
-        self.0.resampler()
+        match &mut self.device {
+            SinkDevice::Ffi(sink) => sink.resampler(),
+            SinkDevice::Virt(sink) => sink.resampler(),
+        }
     }
 
     fn buffer(&mut self) -> &mut [F] {
-        self.0.buffer()
+        match &mut self.device {
+            SinkDevice::Ffi(sink) => sink.buffer(),
+            SinkDevice::Virt(sink) => sink.buffer(),
+        }
+    }
+
+    fn stream<S: Frame, M: Stream<S>>(&mut self, stream: M) {
+        let source_rate = stream.sample_rate();
+        let sample_rate = self.sample_rate();
+        let converter = self.converter.clone();
+        let mut converter = converter.lock().unwrap();
+        match self.channel_map.as_deref().copied() {
+            Some(map) if map.inputs() == S::CHAN_COUNT => {
+                let stream =
+                    stream.into_iter().map(|frame| map.mix::<S, F>(frame));
+                converter.stream(
+                    source_rate,
+                    sample_rate,
+                    stream,
+                    self.buffer(),
+                );
+            }
+            _ => {
+                converter.stream(
+                    source_rate,
+                    sample_rate,
+                    stream,
+                    self.buffer(),
+                );
+            }
+        }
+    }
+}
+
+impl<F: Frame<Chan = Ch32>> Drop for SpeakersSink<F> {
+    fn drop(&mut self) {
+        let sample_rate = self.sample_rate();
+        let mut gain = self.gain;
+        gain.apply(self.buffer());
+        if let Some(metering) = self.meter.take() {
+            let mut measure = metering.measure(F::CHAN_COUNT);
+            for frame in self.buffer().iter() {
+                measure.frame(frame.channels());
+            }
+        }
+        if let Some((reference, latency)) = self.echo_reference.take() {
+            reference.push(self.buffer(), sample_rate, latency);
+        }
     }
 }
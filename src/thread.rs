@@ -0,0 +1,266 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use fon::{Sink, Stream};
+use pasts::Notifier;
+
+use crate::{
+    blocking, ffi, microphone::MicrophoneProperties,
+    speakers::SpeakersProperties, Microphone, MicrophoneStream, Speakers,
+    SpeakersSink,
+};
+
+/// Scheduling priority of an [`AudioThread`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Priority {
+    /// Same priority as other threads.
+    Normal,
+    /// Real-time priority (`SCHED_FIFO` on Linux) if the process is allowed
+    /// to use it, otherwise normal priority.
+    RealTime,
+}
+
+/// Timing of the periods processed by an [`AudioThread`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Timing {
+    /// Number of periods processed.
+    pub periods: u64,
+    /// Number of periods where the callback took longer than the period
+    /// lasts, which causes underruns (speakers) or overruns (microphone).
+    pub overruns: u64,
+    /// How long the callback took for the last period.
+    pub last: Duration,
+    /// How long the callback took for the slowest period.
+    pub max: Duration,
+    /// How long the last period lasts.
+    pub budget: Duration,
+}
+
+/// Timing stored as counters and nanoseconds, so the audio thread updates it
+/// without locking.
+#[derive(Debug, Default)]
+struct AtomicTiming {
+    periods: AtomicU64,
+    overruns: AtomicU64,
+    last: AtomicU64,
+    max: AtomicU64,
+    budget: AtomicU64,
+}
+
+impl AtomicTiming {
+    /// Add a period, where the callback took `elapsed` out of `budget`.
+    fn record(&self, elapsed: Duration, budget: Duration) {
+        let nanos = |duration: Duration| {
+            duration.as_nanos().try_into().unwrap_or(u64::MAX)
+        };
+        if !budget.is_zero() && elapsed > budget {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        self.last.store(nanos(elapsed), Ordering::Relaxed);
+        self.max.fetch_max(nanos(elapsed), Ordering::Relaxed);
+        self.budget.store(nanos(budget), Ordering::Relaxed);
+        self.periods.fetch_add(1, Ordering::Release);
+    }
+
+    /// Read the timing (fields may be from consecutive periods).
+    fn load(&self) -> Timing {
+        Timing {
+            periods: self.periods.load(Ordering::Acquire),
+            overruns: self.overruns.load(Ordering::Relaxed),
+            last: Duration::from_nanos(self.last.load(Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max.load(Ordering::Relaxed)),
+            budget: Duration::from_nanos(self.budget.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// A dedicated thread that calls back for every period of a device, so that
+/// audio doesn't share a thread with other futures.  The thread is stopped
+/// when dropped.
+///
+/// The device is returned by the `open` function on the new thread.  It can
+/// be opened there, or opened beforehand and moved in.
+///
+/// ```
+/// use std::{sync::mpsc, time::Duration};
+///
+/// use fon::mono::Mono32;
+/// use wavy::{AudioThread, Clock, Microphone, MicrophoneStream, Priority};
+///
+/// let (sender, receiver) = mpsc::channel();
+/// let thread = AudioThread::microphone(
+///     Priority::RealTime,
+///     || {
+///         let signal = std::iter::repeat(Mono32::new(0.25));
+///         Microphone::<1>::null(Clock::RealTime, 48_000, signal)
+///     },
+///     move |stream: MicrophoneStream<Mono32>| {
+///         let _ = sender.send(stream.collect::<Vec<_>>());
+///     },
+/// );
+/// let frames = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+/// assert!(frames.iter().all(|f| *f == Mono32::new(0.25)));
+/// drop(thread);
+/// ```
+#[derive(Debug)]
+pub struct AudioThread {
+    /// The audio thread.
+    thread: Option<JoinHandle<()>>,
+    /// Set to stop the audio thread.
+    quit: Arc<AtomicBool>,
+    /// Timing of the periods, updated by the audio thread.
+    timing: Arc<AtomicTiming>,
+    /// Whether the thread got real-time priority.
+    realtime: bool,
+}
+
+impl AudioThread {
+    /// Spawn a thread that plays audio through the speakers returned by
+    /// `open`, calling `callback` to fill each period.
+    ///
+    /// ```
+    /// use std::{thread, time::Duration};
+    ///
+    /// use fon::{stereo::Stereo32, Sink};
+    /// use wavy::{AudioThread, Clock, Priority, Speakers, SpeakersSink};
+    ///
+    /// let speakers = Speakers::<2>::null(Clock::RealTime, 48_000);
+    /// let thread = AudioThread::speakers(
+    ///     Priority::Normal,
+    ///     move || speakers,
+    ///     |sink: &mut SpeakersSink<Stereo32>| {
+    ///         sink.stream(Stereo32::new(0.5, 0.5))
+    ///     },
+    /// );
+    /// thread::sleep(Duration::from_millis(100));
+    /// let timing = thread.timing();
+    /// assert!(timing.periods > 1);
+    /// assert!(timing.overruns < timing.periods);
+    /// assert!(timing.last <= timing.max);
+    /// // A period of the null speakers lasts a few milliseconds.
+    /// assert!(timing.budget > Duration::ZERO);
+    /// assert!(timing.budget < Duration::from_millis(100));
+    /// ```
+    pub fn speakers<const N: usize, O, C>(
+        priority: Priority,
+        open: O,
+        mut callback: C,
+    ) -> Self
+    where
+        Speakers<N>: SpeakersProperties,
+        O: FnOnce() -> Speakers<N> + Send + 'static,
+        C: FnMut(
+                &mut SpeakersSink<<Speakers<N> as SpeakersProperties>::Sample>,
+            ) + Send
+            + 'static,
+    {
+        Self::spawn(priority, open, move |mut sink| {
+            let budget = sink.buffer().len() as f64 / sink.sample_rate();
+            callback(&mut sink);
+            budget
+        })
+    }
+
+    /// Spawn a thread that records audio from the microphone returned by
+    /// `open`, calling `callback` with each period.
+    pub fn microphone<const N: usize, O, C>(
+        priority: Priority,
+        open: O,
+        mut callback: C,
+    ) -> Self
+    where
+        Microphone<N>: MicrophoneProperties,
+        O: FnOnce() -> Microphone<N> + Send + 'static,
+        C: FnMut(
+                MicrophoneStream<
+                    <Microphone<N> as MicrophoneProperties>::Sample,
+                >,
+            ) + Send
+            + 'static,
+    {
+        Self::spawn(priority, open, move |stream| {
+            let budget = match (stream.len(), stream.sample_rate()) {
+                (Some(len), Some(sample_rate)) => len as f64 / sample_rate,
+                _ => 0.0,
+            };
+            callback(stream);
+            budget
+        })
+    }
+
+    /// Spawn a thread that handles each event of the notifier returned by
+    /// `open`, where `handle` returns how many seconds the period lasts.
+    fn spawn<T, O, H>(priority: Priority, open: O, mut handle: H) -> Self
+    where
+        T: Notifier + Unpin,
+        O: FnOnce() -> T + Send + 'static,
+        H: FnMut(T::Event) -> f64 + Send + 'static,
+    {
+        let quit = Arc::new(AtomicBool::new(false));
+        let timing = Arc::new(AtomicTiming::default());
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let thread = {
+            let quit = quit.clone();
+            let timing = timing.clone();
+            thread::Builder::new()
+                .name("wavy".to_string())
+                .spawn(move || {
+                    let realtime = priority == Priority::RealTime
+                        && ffi::set_realtime_priority();
+                    let _ = sender.send(realtime);
+                    let mut notifier = open();
+                    while let Some(event) = blocking::wait_unless(&quit, |cx| {
+                        Pin::new(&mut notifier).poll_next(cx)
+                    }) {
+                        let start = Instant::now();
+                        let budget = handle(event);
+                        let elapsed = start.elapsed();
+                        timing.record(elapsed, Duration::from_secs_f64(budget));
+                    }
+                })
+                .expect("Failed to spawn audio thread")
+        };
+        Self {
+            realtime: receiver.recv().unwrap_or(false),
+            thread: Some(thread),
+            quit,
+            timing,
+        }
+    }
+
+    /// Get the timing of the periods processed so far.
+    pub fn timing(&self) -> Timing {
+        self.timing.load()
+    }
+
+    /// Check whether the thread runs with real-time priority.
+    pub fn is_realtime(&self) -> bool {
+        self.realtime
+    }
+}
+
+impl Drop for AudioThread {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.quit.store(true, Ordering::Release);
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
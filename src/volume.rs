@@ -0,0 +1,145 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+use std::{
+    fmt::{Debug, Display, Formatter},
+    io::Result,
+};
+
+use pasts::prelude::*;
+
+use crate::ffi;
+
+/// A hardware volume control (on Linux, an ALSA simple mixer element such as
+/// "Master" or "Capture") of the sound card a device is on.  Notifier
+/// produces when the volume or mute switch is changed, by this or another
+/// program.
+///
+/// Get them with [`Speakers::volume_controls()`](crate::Speakers) or
+/// [`Microphone::volume_controls()`](crate::Microphone).
+///
+/// ```no_run
+/// use wavy::Speakers;
+///
+/// let speakers = Speakers::<2>::default();
+/// for mut control in speakers.volume_controls() {
+///     if control.name() == "Master" {
+///         control.set_volume_percent(50.0).unwrap();
+///         control.set_muted(false).unwrap();
+///     }
+/// }
+/// ```
+///
+/// Volumes set are read back, rounded to the hardware's steps:
+///
+/// ```
+/// # // Runs with `WAVY_TEST_DUMMY=1` and `snd-dummy` loaded.
+/// # if std::env::var_os("WAVY_TEST_DUMMY").is_none() {
+/// #     return;
+/// # }
+/// use wavy::{Microphone, Speakers};
+///
+/// // Load the kernel's dummy sound card first: `sudo modprobe snd-dummy`
+/// let dummy = |name: String| name.starts_with("Dummy");
+/// let speakers = Speakers::query()
+///     .into_iter()
+///     .find(|speakers| dummy(speakers.to_string()))
+///     .unwrap();
+/// let microphone = Microphone::query()
+///     .into_iter()
+///     .find(|microphone| dummy(microphone.to_string()))
+///     .unwrap();
+/// let mut controls = speakers.volume_controls();
+/// controls.extend(microphone.volume_controls());
+///
+/// let (mut volumes, mut switches) = (0, 0);
+/// for control in &mut controls {
+///     if let Some(old) = control.volume_percent() {
+///         control.set_volume_percent(25.0).unwrap();
+///         assert!((control.volume_percent().unwrap() - 25.0).abs() <= 1.0);
+///         control.set_volume_percent(old).unwrap();
+///         volumes += 1;
+///     }
+///     if let Some(old) = control.is_muted() {
+///         control.set_muted(!old).unwrap();
+///         assert_eq!(control.is_muted(), Some(!old));
+///         control.set_muted(old).unwrap();
+///         assert_eq!(control.is_muted(), Some(old));
+///         switches += 1;
+///     }
+/// }
+/// assert!(volumes > 0 && switches > 0);
+/// ```
+pub struct VolumeControl(pub(crate) ffi::VolumeControl);
+
+impl Debug for VolumeControl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "VolumeControl({})", self.name())
+    }
+}
+
+impl Display for VolumeControl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl VolumeControl {
+    /// Get the name of the control.
+    pub fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    /// Get the minimum and maximum volume in decibels, or `None` if the
+    /// control has no volume (or it isn't in decibels).
+    pub fn db_range(&self) -> Option<(f32, f32)> {
+        self.0.db_range()
+    }
+
+    /// Get the volume in decibels, or `None` if the control has no volume (or
+    /// it isn't in decibels).
+    pub fn volume_db(&self) -> Option<f32> {
+        self.0.volume_db()
+    }
+
+    /// Set the volume in decibels, rounded to the nearest step the hardware
+    /// supports.
+    pub fn set_volume_db(&mut self, db: f32) -> Result<()> {
+        self.0.set_volume_db(db)
+    }
+
+    /// Get the volume as a percentage of the hardware's range, or `None` if
+    /// the control has no volume.
+    pub fn volume_percent(&self) -> Option<f32> {
+        self.0.volume_percent()
+    }
+
+    /// Set the volume as a percentage (0 to 100) of the hardware's range.
+    pub fn set_volume_percent(&mut self, percent: f32) -> Result<()> {
+        self.0.set_volume_percent(percent)
+    }
+
+    /// Check if the control is muted, or `None` if it has no mute switch.
+    pub fn is_muted(&self) -> Option<bool> {
+        self.0.is_muted()
+    }
+
+    /// Mute or unmute the control.
+    pub fn set_muted(&mut self, muted: bool) -> Result<()> {
+        self.0.set_muted(muted)
+    }
+}
+
+impl Notifier for VolumeControl {
+    type Event = ();
+
+    fn poll_next(self: Pin<&mut Self>, e: &mut Exec<'_>) -> Poll<()> {
+        self.get_mut().0.poll_change(e)
+    }
+}
@@ -0,0 +1,543 @@
+// Copyright © 2019-2022 The Wavy Contributors.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// - MIT License (https://mit-license.org/)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! RIFF WAV encoding and decoding.
+
+use std::{
+    fs::File,
+    io::{
+        BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom,
+        Write,
+    },
+    path::Path,
+};
+
+use fon::{
+    chan::{Ch32, Channel},
+    surround::Surround32,
+};
+
+use crate::virt::{read_frame, write_frame};
+
+/// `WAVE_FORMAT_PCM`
+const FORMAT_PCM: u16 = 1;
+/// `WAVE_FORMAT_IEEE_FLOAT`
+const FORMAT_FLOAT: u16 = 3;
+/// `WAVE_FORMAT_EXTENSIBLE`
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Byte offset of the RIFF chunk size.
+const RIFF_SIZE_OFFSET: u64 = 4;
+/// Byte offset of the data chunk size.
+const DATA_SIZE_OFFSET: u64 = 40;
+/// Largest data chunk, so the RIFF chunk size (with the rest of the header
+/// and padding) fits in 32 bits.
+const DATA_SIZE_MAX: u32 = u32::MAX - 37;
+
+/// Sample encoding of a WAV file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WavFormat {
+    /// 16-bit signed integer PCM.
+    Pcm16,
+    /// 24-bit signed integer PCM.
+    Pcm24,
+    /// 32-bit IEEE float.
+    Float32,
+}
+
+impl WavFormat {
+    /// Bytes per sample.
+    fn bytes(self) -> u16 {
+        match self {
+            WavFormat::Pcm16 => 2,
+            WavFormat::Pcm24 => 3,
+            WavFormat::Float32 => 4,
+        }
+    }
+
+    /// Format tag for the fmt chunk.
+    fn tag(self) -> u16 {
+        match self {
+            WavFormat::Pcm16 | WavFormat::Pcm24 => FORMAT_PCM,
+            WavFormat::Float32 => FORMAT_FLOAT,
+        }
+    }
+
+    /// Append an encoded sample.
+    fn encode(self, sample: Ch32, bytes: &mut Vec<u8>) {
+        let sample = f32::from(sample).clamp(-1.0, 1.0);
+        match self {
+            WavFormat::Pcm16 => {
+                let sample = (sample * i16::MAX as f32).round() as i16;
+                bytes.extend(sample.to_le_bytes());
+            }
+            WavFormat::Pcm24 => {
+                let sample = (sample * 8_388_607.0).round() as i32;
+                bytes.extend(&sample.to_le_bytes()[..3]);
+            }
+            WavFormat::Float32 => bytes.extend(sample.to_le_bytes()),
+        }
+    }
+}
+
+/// Streams interleaved audio into a WAV file.
+pub(crate) struct WavWriter {
+    /// The file being written.
+    file: BufWriter<File>,
+    /// Sample encoding.
+    format: WavFormat,
+    /// Sample rate of the file.
+    sample_rate: u32,
+    /// Number of channels in the file (0 until the header is written).
+    channels: u8,
+    /// Number of bytes written to the data chunk.
+    size: u32,
+    /// Encoding buffer.
+    bytes: Vec<u8>,
+}
+
+impl WavWriter {
+    /// Create a new WAV file, the header is written with the first audio.
+    pub(crate) fn create(
+        path: &Path,
+        format: WavFormat,
+        sample_rate: u32,
+    ) -> Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+            format,
+            sample_rate,
+            channels: 0,
+            size: 0,
+            bytes: Vec::new(),
+        })
+    }
+
+    /// Get the number of bytes written to the file (including the header).
+    pub(crate) fn size(&self) -> u64 {
+        44 + u64::from(self.size)
+    }
+
+    /// Write interleaved audio with `channels` channels (1, 2 or 6).  The
+    /// number of channels in the file is fixed by the first write, and later
+    /// audio is converted to match.
+    pub(crate) fn write(&mut self, audio: &[Ch32], channels: u8) -> Result<()> {
+        if self.channels == 0 {
+            self.channels = channels;
+            self.write_header()?;
+        }
+        let mut frame = [Ch32::MID; 6];
+        let frame = &mut frame[..self.channels.into()];
+        self.bytes.clear();
+        for src in audio.chunks(channels.into()) {
+            write_frame(read_frame(src), frame);
+            for sample in frame.iter() {
+                self.format.encode(*sample, &mut self.bytes);
+            }
+        }
+        let size = u32::try_from(self.bytes.len())
+            .ok()
+            .and_then(|len| self.size.checked_add(len))
+            .filter(|size| *size <= DATA_SIZE_MAX)
+            .ok_or_else(|| Error::other("WAV data chunk over 4 GiB"))?;
+        self.file.write_all(&self.bytes)?;
+        self.size = size;
+        Ok(())
+    }
+
+    /// Write the header, with sizes for the data written so far.
+    fn write_header(&mut self) -> Result<()> {
+        let channels = u16::from(self.channels);
+        let block_align = channels * self.format.bytes();
+        let mut header = Vec::with_capacity(44);
+        header.extend(b"RIFF");
+        header.extend((36 + self.size).to_le_bytes());
+        header.extend(b"WAVEfmt ");
+        header.extend(16u32.to_le_bytes());
+        header.extend(self.format.tag().to_le_bytes());
+        header.extend(channels.to_le_bytes());
+        header.extend(self.sample_rate.to_le_bytes());
+        header
+            .extend((self.sample_rate * u32::from(block_align)).to_le_bytes());
+        header.extend(block_align.to_le_bytes());
+        header.extend((self.format.bytes() * 8).to_le_bytes());
+        header.extend(b"data");
+        header.extend(self.size.to_le_bytes());
+        self.file.write_all(&header)
+    }
+
+    /// Update the chunk sizes in the header, and flush to disk.  More audio
+    /// may be written afterwards.
+    pub(crate) fn finalize(&mut self) -> Result<()> {
+        self.file.flush()?;
+        if self.channels == 0 {
+            return Ok(());
+        }
+        let file = self.file.get_mut();
+        let end = file.stream_position()?;
+        // Data chunk is padded to an even number of bytes.
+        let pad = self.size % 2;
+        if pad == 1 {
+            file.write_all(&[0])?;
+        }
+        file.seek(SeekFrom::Start(RIFF_SIZE_OFFSET))?;
+        file.write_all(&(36 + self.size + pad).to_le_bytes())?;
+        file.seek(SeekFrom::Start(DATA_SIZE_OFFSET))?;
+        file.write_all(&self.size.to_le_bytes())?;
+        // Padding is overwritten by any audio written later.
+        file.seek(SeekFrom::Start(end))?;
+        file.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        // Errors are reported by finalizing before the writer is dropped.
+        let _ = self.finalize();
+    }
+}
+
+/// Decodes audio frames from a WAV file.
+pub(crate) struct WavReader<R: Read + Seek = BufReader<File>> {
+    /// The file being read.
+    file: R,
+    /// Format tag (PCM or float).
+    tag: u16,
+    /// Bits per sample.
+    bits: u16,
+    /// Number of channels (1, 2 or 6).
+    channels: u8,
+    /// Sample rate of the file.
+    sample_rate: u32,
+    /// Size of the data chunk.
+    size: u32,
+    /// Byte offset of the data chunk's contents.
+    start: u64,
+    /// Number of bytes left in the data chunk.
+    remaining: u32,
+    /// The error that stopped reading before the end of the data, if not
+    /// yet taken.
+    error: Option<Error>,
+}
+
+impl WavReader {
+    /// Open a WAV file, and read its header.
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> WavReader<R> {
+    /// Read the header of a WAV file, leaving `file` at the data.
+    pub(crate) fn new(mut file: R) -> Result<Self> {
+        let mut riff = [0; 12];
+        file.read_exact(&mut riff)?;
+        if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+            return Err(invalid("not a RIFF WAVE file"));
+        }
+        let mut fmt = None;
+        loop {
+            let mut chunk = [0; 8];
+            file.read_exact(&mut chunk)?;
+            let size = u32::from_le_bytes(chunk[4..].try_into().unwrap());
+            match &chunk[..4] {
+                b"fmt " => {
+                    let mut data = vec![0; size as usize];
+                    file.read_exact(&mut data)?;
+                    if data.len() < 16 {
+                        return Err(invalid("fmt chunk too short"));
+                    }
+                    fmt = Some(data);
+                    if size % 2 == 1 {
+                        file.seek(SeekFrom::Current(1))?;
+                    }
+                }
+                b"data" => {
+                    let fmt = fmt.ok_or_else(|| invalid("no fmt chunk"))?;
+                    return Self::with_format(file, &fmt, size);
+                }
+                _ => {
+                    let skip = i64::from(size) + i64::from(size % 2);
+                    file.seek(SeekFrom::Current(skip))?;
+                }
+            }
+        }
+    }
+
+    /// Create a reader from a fmt chunk, positioned at the data.
+    fn with_format(mut file: R, fmt: &[u8], size: u32) -> Result<Self> {
+        let field = |i: usize| u16::from_le_bytes([fmt[i], fmt[i + 1]]);
+        let mut tag = field(0);
+        let channels = field(2);
+        let sample_rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+        let bits = field(14);
+        if tag == FORMAT_EXTENSIBLE {
+            if fmt.len() < 26 {
+                return Err(invalid("extensible fmt chunk too short"));
+            }
+            tag = field(24);
+        }
+        let supported = matches!(
+            (tag, bits),
+            (FORMAT_PCM, 8 | 16 | 24 | 32) | (FORMAT_FLOAT, 32 | 64)
+        );
+        if !supported {
+            return Err(invalid("unsupported sample format"));
+        }
+        if !matches!(channels, 1 | 2 | 6) {
+            return Err(invalid("unsupported number of channels"));
+        }
+        let start = file.stream_position()?;
+        Ok(Self {
+            file,
+            tag,
+            bits,
+            channels: channels as u8,
+            sample_rate,
+            size,
+            start,
+            remaining: size,
+            error: None,
+        })
+    }
+
+    /// Number of bytes in a frame.
+    fn frame_bytes(&self) -> u32 {
+        u32::from(self.bits / 8) * u32::from(self.channels)
+    }
+
+    /// Get the number of frames in the file.
+    pub(crate) fn len(&self) -> u64 {
+        (self.size / self.frame_bytes()).into()
+    }
+
+    /// Move to frame number `frame` (or the end, if past it).
+    pub(crate) fn seek(&mut self, frame: u64) -> Result<()> {
+        let frame = frame.min(self.len()) as u32;
+        let offset = frame * self.frame_bytes();
+        self.file
+            .seek(SeekFrom::Start(self.start + u64::from(offset)))?;
+        self.remaining = self.size - offset;
+        Ok(())
+    }
+
+    /// Sample rate of the file.
+    pub(crate) fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Take the error that ended the frames before the end of the data, if
+    /// any.
+    pub(crate) fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    /// Read one sample.
+    fn sample(&mut self) -> Option<Ch32> {
+        let size = u32::from(self.bits / 8);
+        self.remaining = self.remaining.checked_sub(size)?;
+        let mut bytes = [0; 8];
+        if let Err(error) = self.file.read_exact(&mut bytes[..size as usize]) {
+            // Stop here, rather than reading from the middle of a sample.
+            self.remaining = 0;
+            self.error = Some(error);
+            return None;
+        }
+        let sample = match (self.tag, self.bits) {
+            (FORMAT_PCM, 8) => (f32::from(bytes[0]) - 128.0) / 128.0,
+            (FORMAT_PCM, 16) => {
+                f32::from(i16::from_le_bytes([bytes[0], bytes[1]])) / 32_768.0
+            }
+            (FORMAT_PCM, 24) => {
+                let sample =
+                    i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]);
+                (sample >> 8) as f32 / 8_388_608.0
+            }
+            (FORMAT_PCM, 32) => {
+                let sample = i32::from_le_bytes(bytes[..4].try_into().unwrap());
+                sample as f32 / 2_147_483_648.0
+            }
+            (FORMAT_FLOAT, 32) => {
+                f32::from_le_bytes(bytes[..4].try_into().unwrap())
+            }
+            (FORMAT_FLOAT, 64) => f64::from_le_bytes(bytes) as f32,
+            _ => unreachable!(),
+        };
+        Some(Ch32::new(sample))
+    }
+}
+
+impl<R: Read + Seek> Iterator for WavReader<R> {
+    type Item = Surround32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = [Ch32::MID; 6];
+        let frame = &mut frame[..self.channels.into()];
+        for sample in frame.iter_mut() {
+            *sample = self.sample()?;
+        }
+        Some(read_frame(frame))
+    }
+}
+
+/// Create an invalid data error.
+fn invalid(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("Invalid WAV file: {}", message),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Write `audio` to a WAV file named `name`, and return its bytes.
+    fn encode(name: &str, format: WavFormat, audio: &[f32], ch: u8) -> Vec<u8> {
+        let path = std::env::temp_dir().join(name);
+        let mut writer = WavWriter::create(&path, format, 44_100).unwrap();
+        let audio: Vec<_> = audio.iter().copied().map(Ch32::new).collect();
+        writer.write(&audio, ch).unwrap();
+        writer.finalize().unwrap();
+        drop(writer);
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        bytes
+    }
+
+    /// Read all frames of `reader` back as `ch` interleaved channels.
+    fn decode<R: Read + Seek>(
+        reader: &mut WavReader<R>,
+        ch: usize,
+    ) -> Vec<f32> {
+        let mut audio = Vec::new();
+        let mut frame = [Ch32::MID; 6];
+        for surround in reader {
+            write_frame(surround, &mut frame[..ch]);
+            audio.extend(frame[..ch].iter().map(|chan| f32::from(*chan)));
+        }
+        audio
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..][..4].try_into().unwrap())
+    }
+
+    #[test]
+    fn header() {
+        let audio = [0.0, 0.5, -0.5, 1.0];
+        let bytes = encode("wavy-test-header.wav", WavFormat::Pcm16, &audio, 2);
+        assert_eq!(bytes.len(), 44 + 8);
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, RIFF_SIZE_OFFSET as usize), 36 + 8);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&bytes, 16), 16);
+        assert_eq!(&bytes[20..24], [1, 0, 2, 0]);
+        assert_eq!(u32_at(&bytes, 24), 44_100);
+        assert_eq!(u32_at(&bytes, 28), 44_100 * 4);
+        assert_eq!(&bytes[32..36], [4, 0, 16, 0]);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(u32_at(&bytes, DATA_SIZE_OFFSET as usize), 8);
+    }
+
+    #[test]
+    fn odd_size_padded() {
+        let audio = [0.25; 3];
+        let bytes = encode("wavy-test-pad.wav", WavFormat::Pcm24, &audio, 1);
+        assert_eq!(bytes.len(), 44 + 10);
+        assert_eq!(u32_at(&bytes, RIFF_SIZE_OFFSET as usize), 36 + 10);
+        assert_eq!(u32_at(&bytes, DATA_SIZE_OFFSET as usize), 9);
+        assert_eq!(bytes[53], 0);
+    }
+
+    #[test]
+    fn round_trip() {
+        let audio = [0.0, 0.5, -0.5, 0.25, -1.0, 0.75];
+        let formats = [
+            (WavFormat::Pcm16, 1.0 / 32_767.0),
+            (WavFormat::Pcm24, 1.0 / 8_388_607.0),
+            (WavFormat::Float32, 0.0),
+        ];
+        for (format, error) in formats {
+            let bytes = encode("wavy-test-trip.wav", format, &audio, 2);
+            let mut reader = WavReader::new(Cursor::new(bytes)).unwrap();
+            assert_eq!(reader.sample_rate(), 44_100);
+            assert_eq!(reader.len(), 3);
+            let decoded = decode(&mut reader, 2);
+            assert!(reader.take_error().is_none());
+            for (a, b) in audio.iter().zip(&decoded) {
+                assert!((a - b).abs() <= error, "{:?}: {} {}", format, a, b);
+            }
+            assert_eq!(decoded.len(), audio.len());
+        }
+    }
+
+    #[test]
+    fn seek() {
+        let audio = [0.0, 0.125, 0.25, 0.375];
+        let bytes = encode("wavy-test-seek.wav", WavFormat::Float32, &audio, 1);
+        let mut reader = WavReader::new(Cursor::new(bytes)).unwrap();
+        reader.seek(2).unwrap();
+        assert_eq!(decode(&mut reader, 1), [0.25, 0.375]);
+        reader.seek(10).unwrap();
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn data_size_limit() {
+        let path = std::env::temp_dir().join("wavy-test-limit.wav");
+        let mut writer =
+            WavWriter::create(&path, WavFormat::Pcm16, 44_100).unwrap();
+        writer.write(&[Ch32::MID; 2], 1).unwrap();
+        writer.size = DATA_SIZE_MAX - 2;
+        writer.write(&[Ch32::MID], 1).unwrap();
+        assert_eq!(writer.size, DATA_SIZE_MAX);
+        assert!(writer.write(&[Ch32::MID], 1).is_err());
+        assert_eq!(writer.size, DATA_SIZE_MAX);
+        // Don't finalize the fake size into the file.
+        writer.channels = 0;
+        drop(writer);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn extensible_and_unknown_chunks() {
+        let mut bytes = Vec::new();
+        bytes.extend(b"RIFF\0\0\0\0WAVE");
+        bytes.extend(b"LIST");
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend([1, 2, 3, 0]);
+        bytes.extend(b"fmt ");
+        bytes.extend(40u32.to_le_bytes());
+        bytes.extend(FORMAT_EXTENSIBLE.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(8_000u32.to_le_bytes());
+        bytes.extend(16_000u32.to_le_bytes());
+        bytes.extend(2u16.to_le_bytes());
+        bytes.extend(16u16.to_le_bytes());
+        bytes.extend([0; 8]);
+        bytes.extend(FORMAT_PCM.to_le_bytes());
+        bytes.extend([0; 14]);
+        bytes.extend(b"data");
+        bytes.extend(2u32.to_le_bytes());
+        bytes.extend((-16_384i16).to_le_bytes());
+        let mut reader = WavReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.sample_rate(), 8_000);
+        assert_eq!(decode(&mut reader, 1), [-0.5]);
+    }
+
+    #[test]
+    fn invalid() {
+        let bytes = b"RIFF\0\0\0\0AVI data\0\0\0\0".to_vec();
+        let error = WavReader::new(Cursor::new(bytes)).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}